@@ -0,0 +1,143 @@
+//! Server side checking of the credentials sent with `Req::Init`.
+//!
+//! A server holds some `Verifier` and calls it for every `Req::Init`; a rejection is answered
+//! with `Rep::AuthFailed` carrying the returned reason. `StaticTokens` and `StaticKeys` check
+//! against secrets known in advance. `StaticKeys` takes the MAC function as a parameter, so this
+//! crate does not depend on a particular HMAC implementation.
+//!
+//! HMAC credentials are bound to a server challenge: when `Verifier::wants_challenge` holds, an
+//! `Req::Init` without credentials is answered with `Rep::AuthChallenge(nonce)`. The server
+//! generates the nonce (at least 16 random bytes), remembers it for the session and passes it to
+//! the next `verify` call only, so a captured `Req::Init` cannot be replayed.
+
+use std::collections::{HashMap, HashSet};
+use super::Credentials;
+
+pub trait Verifier {
+    /// Whether an `Req::Init` without credentials gets a `Rep::AuthChallenge` instead of a rejection.
+    fn wants_challenge(&self) -> bool {
+        false
+    }
+
+    /// Checks the credentials of a `Req::Init`, `Err` carries the reason sent in `Rep::AuthFailed`.
+    /// `challenge` is the nonce of the `Rep::AuthChallenge` sent in this session, if any.
+    fn verify(&self, credentials: Option<&Credentials>, challenge: Option<&[u8]>) -> Result<(), String>;
+}
+
+/// Accepts every client, with or without credentials.
+#[derive(Debug)]
+pub struct AllowAll;
+
+impl Verifier for AllowAll {
+    fn verify(&self, _credentials: Option<&Credentials>, _challenge: Option<&[u8]>) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Accepts `Credentials::Token` with one of the configured tokens.
+#[derive(Debug)]
+pub struct StaticTokens {
+    tokens: HashSet<String>,
+}
+
+impl StaticTokens {
+    pub fn new<I>(tokens: I) -> StaticTokens where I: IntoIterator<Item = String> {
+        StaticTokens { tokens: tokens.into_iter().collect(), }
+    }
+}
+
+impl Verifier for StaticTokens {
+    fn verify(&self, credentials: Option<&Credentials>, _challenge: Option<&[u8]>) -> Result<(), String> {
+        match credentials {
+            Some(&Credentials::Token(ref token)) if self.tokens.iter().any(|t| constant_time_eq(t.as_bytes(), token.as_bytes())) =>
+                Ok(()),
+            Some(&Credentials::Token(..)) =>
+                Err("invalid token".to_owned()),
+            _ =>
+                Err("token required".to_owned()),
+        }
+    }
+}
+
+/// Accepts `Credentials::Hmac` for the current challenge whose mac equals `mac(key, nonce)` for the
+/// key named by `key_id`.
+pub struct StaticKeys<F> where F: Fn(&[u8], &[u8]) -> Vec<u8> {
+    keys: HashMap<String, Vec<u8>>,
+    mac: F,
+}
+
+impl<F> StaticKeys<F> where F: Fn(&[u8], &[u8]) -> Vec<u8> {
+    pub fn new(keys: HashMap<String, Vec<u8>>, mac: F) -> StaticKeys<F> {
+        StaticKeys { keys: keys, mac: mac, }
+    }
+}
+
+impl<F> Verifier for StaticKeys<F> where F: Fn(&[u8], &[u8]) -> Vec<u8> {
+    fn wants_challenge(&self) -> bool {
+        true
+    }
+
+    fn verify(&self, credentials: Option<&Credentials>, challenge: Option<&[u8]>) -> Result<(), String> {
+        match (credentials, challenge) {
+            (Some(&Credentials::Hmac { ref nonce, .. }), Some(challenge)) if !constant_time_eq(nonce, challenge) =>
+                Err("nonce does not match the challenge".to_owned()),
+            (Some(&Credentials::Hmac { .. }), None) =>
+                Err("no challenge was issued".to_owned()),
+            (Some(&Credentials::Hmac { ref key_id, ref nonce, ref mac, }), Some(..)) => match self.keys.get(key_id) {
+                Some(key) if constant_time_eq(&(self.mac)(key, nonce), mac) =>
+                    Ok(()),
+                Some(..) =>
+                    Err("invalid mac".to_owned()),
+                None =>
+                    Err(format!("unknown key id {}", key_id)),
+            },
+            _ =>
+                Err("hmac credentials required".to_owned()),
+        }
+    }
+}
+
+/// Compares two byte strings in time depending only on their lengths.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use super::{Verifier, AllowAll, StaticTokens, StaticKeys, constant_time_eq};
+    use super::super::Credentials;
+
+    #[test]
+    fn tokens() {
+        let verifier = StaticTokens::new(vec!["secret".to_owned()]);
+        assert!(verifier.verify(Some(&Credentials::Token("secret".to_owned())), None).is_ok());
+        assert!(verifier.verify(Some(&Credentials::Token("guess".to_owned())), None).is_err());
+        assert!(verifier.verify(None, None).is_err());
+        assert!(!verifier.wants_challenge());
+        assert!(AllowAll.verify(None, None).is_ok());
+        assert_eq!(format!("{:?}", Credentials::Token("secret".to_owned())), "Token(<redacted>)");
+    }
+
+    #[test]
+    fn keys() {
+        // toy mac for the test only: xor of key and nonce bytes
+        let mac = |key: &[u8], nonce: &[u8]| nonce.iter().zip(key.iter().cycle()).map(|(n, k)| n ^ k).collect::<Vec<u8>>();
+        let mut keys = HashMap::new();
+        keys.insert("client-1".to_owned(), vec![0x55, 0xaa]);
+        let verifier = StaticKeys::new(keys, mac);
+        let hmac = |key_id: &str, mac: Vec<u8>| Credentials::Hmac { key_id: key_id.to_owned(), nonce: vec![1, 2, 3], mac: mac, };
+        let challenge: &[u8] = &[1, 2, 3];
+        assert!(verifier.wants_challenge());
+        assert!(verifier.verify(Some(&hmac("client-1", vec![0x54, 0xa8, 0x56])), Some(challenge)).is_ok());
+        assert!(verifier.verify(Some(&hmac("client-1", vec![0x54, 0xa8, 0x57])), Some(challenge)).is_err());
+        assert!(verifier.verify(Some(&hmac("client-2", vec![0x54, 0xa8, 0x56])), Some(challenge)).is_err());
+        assert!(verifier.verify(Some(&Credentials::Token("secret".to_owned())), Some(challenge)).is_err());
+        // a replayed frame carries the nonce of an older challenge
+        assert!(verifier.verify(Some(&hmac("client-1", vec![0x54, 0xa8, 0x56])), Some(&[4, 5, 6])).is_err());
+        assert!(verifier.verify(Some(&hmac("client-1", vec![0x54, 0xa8, 0x56])), None).is_err());
+        let debug = format!("{:?}", hmac("client-1", vec![0x54, 0xa8, 0x56]));
+        assert!(debug.contains("client-1") && debug.contains("<redacted>") && !debug.contains("84"));
+        assert!(!constant_time_eq(b"ab", b"abc"));
+    }
+}
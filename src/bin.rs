@@ -1,4 +1,4 @@
-use std::{io, fmt, str};
+use std::{io, fmt, str, cmp};
 use std::sync::Arc;
 use std::fmt::Debug;
 use std::ops::Deref;
@@ -6,8 +6,8 @@ use std::mem::size_of;
 use byteorder::{ByteOrder, NativeEndian};
 use super::{
     Workload,
-    Trans, Req, LookupTask, PostAction, InsertCond, ClusterAssign, AssignCond, ClusterChoice, LookupType,
-    Rep, LookupResult, Match
+    Trans, Req, ReqKind, ClusterInfo, Member, DocRef, Batch, BatchTask, BatchInsert, CancelState, InitParams, Credentials, EventFilter, EventKind, Event, UnexpectedReason, ReqEcho, BusyInfo, BusyReason, LookupError, ErrorCode, LookupTask, Document, Signature, PostAction, InsertCond, ClusterAssign, AssignCond, Cond, ClusterChoice, LookupType,
+    Rep, LookupResult, InsertOutcome, Match, MAX_COND_DEPTH
 };
 
 #[derive(Debug)]
@@ -16,6 +16,8 @@ pub enum Error {
     Utf8(str::Utf8Error),
     UnexpectedEOF,
     InvalidTag(u8),
+    InvalidThreshold(f64),
+    InvalidNesting(u8),
 }
 
 pub trait ToBin {
@@ -75,7 +77,7 @@ impl U8Support for NativeEndian {
     fn write_u8(buf: &mut [u8], n: u8) { buf[0] = n; }
 }
 
-macro_rules! try_get_str {
+macro_rules! try_get_bytes {
     ($buf:expr) => ({
         let buf = $buf;
         let (len, buf) = try_get!(buf, u32, read_u32);
@@ -83,14 +85,21 @@ macro_rules! try_get_str {
         if buf.len() < len {
             return Err(Error::UnexpectedEOF)
         } else {
-            (try!(str::from_utf8(&buf[0 .. len]).map_err(|e| Error::Utf8(e))).to_owned(), &buf[len ..])
+            (&buf[0 .. len], &buf[len ..])
         }
     })
 }
 
-macro_rules! put_str_adv {
-    ($area:expr, $str:ident) => ({
-        let src = $str.as_bytes();
+macro_rules! try_get_str {
+    ($buf:expr) => ({
+        let (bytes, buf) = try_get_bytes!($buf);
+        (try!(str::from_utf8(bytes).map_err(|e| Error::Utf8(e))).to_owned(), buf)
+    })
+}
+
+macro_rules! put_bytes_adv {
+    ($area:expr, $bytes:expr) => ({
+        let src: &[u8] = $bytes;
         let dst = $area;
         let src_len_value = src.len() as u32;
         let area = put_adv!(dst, u32, write_u32, src_len_value);
@@ -99,6 +108,10 @@ macro_rules! put_str_adv {
     })
 }
 
+macro_rules! put_str_adv {
+    ($area:expr, $str:ident) => (put_bytes_adv!($area, $str.as_bytes()))
+}
+
 macro_rules! impl_bin {
     ($(($ty:ty, $reader:ident, $writer:ident)),*) => ($(
         impl ToBin for $ty {
@@ -148,11 +161,71 @@ impl FromBin for String {
     }
 }
 
+impl<T> ToBin for Vec<T> where T: ToBin {
+    fn encode_len(&self) -> usize {
+        size_of::<u32>() + self.iter().fold(0, |total, value| total + value.encode_len())
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        let area = put_adv!(area, u32, write_u32, self.len() as u32);
+        self.iter().fold(area, |area, value| value.encode(area))
+    }
+}
+
+impl<T> FromBin for Vec<T> where T: FromBin {
+    fn decode<'a>(area: &'a [u8]) -> Result<(Vec<T>, &'a [u8]), Error> {
+        let (len, mut area) = try_get!(area, u32, read_u32);
+        let mut values = Vec::with_capacity(cmp::min(len as usize, area.len()));
+        for _ in 0 .. len {
+            let (value, next_area) = try!(T::decode(area));
+            values.push(value);
+            area = next_area;
+        }
+        Ok((values, area))
+    }
+}
+
+impl<T> ToBin for Option<T> where T: ToBin {
+    fn encode_len(&self) -> usize {
+        size_of::<u8>() + match self {
+            &None => 0,
+            &Some(ref value) => value.encode_len(),
+        }
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        match self {
+            &None =>
+                put_adv!(area, u8, write_u8, 1),
+            &Some(ref value) => {
+                let area = put_adv!(area, u8, write_u8, 2);
+                value.encode(area)
+            },
+        }
+    }
+}
+
+impl<T> FromBin for Option<T> where T: FromBin {
+    fn decode<'a>(area: &'a [u8]) -> Result<(Option<T>, &'a [u8]), Error> {
+        match try_get!(area, u8, read_u8) {
+            (1, area) =>
+                Ok((None, area)),
+            (2, area) => {
+                let (value, area) = try!(T::decode(area));
+                Ok((Some(value), area))
+            },
+            (tag, _) =>
+                Err(Error::InvalidTag(tag)),
+        }
+    }
+}
+
 impl<UD> ToBin for Trans<UD> where UD: ToBin + Debug {
     fn encode_len(&self) -> usize {
         size_of::<u8>() + match self {
             &Trans::Async(ref req) => req.encode_len(),
             &Trans::Sync(ref req) => req.encode_len(),
+            &Trans::Tagged(_, ref trans) | &Trans::Deadline(_, ref trans) => size_of::<u64>() + trans.encode_len(),
         }
     }
 
@@ -166,6 +239,16 @@ impl<UD> ToBin for Trans<UD> where UD: ToBin + Debug {
                 let area = put_adv!(area, u8, write_u8, 2);
                 req.encode(area)
             },
+            &Trans::Tagged(id, ref trans) => {
+                let area = put_adv!(area, u8, write_u8, 3);
+                let area = put_adv!(area, u64, write_u64, id);
+                trans.encode(area)
+            },
+            &Trans::Deadline(ms, ref trans) => {
+                let area = put_adv!(area, u8, write_u8, 4);
+                let area = put_adv!(area, u64, write_u64, ms);
+                trans.encode(area)
+            },
         }
     }
 }
@@ -173,21 +256,116 @@ impl<UD> ToBin for Trans<UD> where UD: ToBin + Debug {
 impl<UD> ToBin for Req<UD> where UD: ToBin + Debug {
     fn encode_len(&self) -> usize {
         size_of::<u8>() + match self {
-            &Req::Init | &Req::Terminate => 0,
-            &Req::Lookup(ref workload) => workload.encode_len(),
+            &Req::Init(ref params) if params.is_default() => 0,
+            &Req::Init(ref params) if params.credentials.is_none() => params.collection.encode_len(),
+            &Req::Init(ref params) => params.encode_len(),
+            &Req::Terminate | &Req::ListCollections => 0,
+            &Req::CreateCollection(ref name) | &Req::DropCollection(ref name) => name.encode_len(),
+            &Req::Lookup(ref workload) => match text_workload(workload) {
+                Some(ref text_workload) => text_workload.encode_len(),
+                None => workload.encode_len(),
+            },
+            &Req::Snapshot { path: ref p, } | &Req::Restore { path: ref p, } => p.encode_len(),
+            &Req::Ping(..) | &Req::Cancel(..) => size_of::<u64>(),
+            &Req::LookupStream { ref tasks, .. } => tasks.encode_len() + size_of::<u32>(),
+            &Req::Subscribe(ref filter) => filter.encode_len(),
+            &Req::FetchMore(ref cursor) => cursor.encode_len(),
+            &Req::LookupBatch(ref batch) => batch.encode_len(),
+            &Req::GetCluster { ref limit, .. } => size_of::<u64>() + size_of::<u64>() + limit.encode_len(),
+            &Req::MergeClusters { .. } => size_of::<u64>() + size_of::<u64>(),
+            &Req::MoveDocument { ref doc, ref to_cluster, } => doc.encode_len() + to_cluster.encode_len(),
+            &Req::UpdateUserData { ref doc, ref user_data, } => doc.encode_len() + user_data.encode_len(),
         }
     }
 
     fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
         match self {
-            &Req::Init =>
+            &Req::Init(ref params) if params.is_default() =>
                 put_adv!(area, u8, write_u8, 1),
-            &Req::Lookup(ref workload) => {
-                let area = put_adv!(area, u8, write_u8, 2);
-                workload.encode(area)
+            &Req::Init(ref params) if params.credentials.is_none() => {
+                let area = put_adv!(area, u8, write_u8, 7);
+                params.collection.encode(area)
+            },
+            &Req::Init(ref params) => {
+                let area = put_adv!(area, u8, write_u8, 13);
+                params.encode(area)
+            },
+            &Req::Lookup(ref workload) => match text_workload(workload) {
+                Some(ref text_workload) => {
+                    let area = put_adv!(area, u8, write_u8, 2);
+                    text_workload.encode(area)
+                },
+                None => {
+                    let area = put_adv!(area, u8, write_u8, 21);
+                    workload.encode(area)
+                },
             },
             &Req::Terminate =>
                 put_adv!(area, u8, write_u8, 3),
+            &Req::Snapshot { path: ref p, } => {
+                let area = put_adv!(area, u8, write_u8, 4);
+                p.encode(area)
+            },
+            &Req::Restore { path: ref p, } => {
+                let area = put_adv!(area, u8, write_u8, 5);
+                p.encode(area)
+            },
+            &Req::Ping(nonce) => {
+                let area = put_adv!(area, u8, write_u8, 6);
+                put_adv!(area, u64, write_u64, nonce)
+            },
+            &Req::CreateCollection(ref name) => {
+                let area = put_adv!(area, u8, write_u8, 8);
+                name.encode(area)
+            },
+            &Req::ListCollections =>
+                put_adv!(area, u8, write_u8, 9),
+            &Req::DropCollection(ref name) => {
+                let area = put_adv!(area, u8, write_u8, 10);
+                name.encode(area)
+            },
+            &Req::LookupStream { ref tasks, chunk_size, } => {
+                let area = put_adv!(area, u8, write_u8, 11);
+                let area = tasks.encode(area);
+                put_adv!(area, u32, write_u32, chunk_size)
+            },
+            &Req::Cancel(id) => {
+                let area = put_adv!(area, u8, write_u8, 12);
+                put_adv!(area, u64, write_u64, id)
+            },
+            &Req::Subscribe(ref filter) => {
+                let area = put_adv!(area, u8, write_u8, 14);
+                filter.encode(area)
+            },
+            &Req::FetchMore(ref cursor) => {
+                let area = put_adv!(area, u8, write_u8, 15);
+                cursor.encode(area)
+            },
+            &Req::LookupBatch(ref batch) => {
+                let area = put_adv!(area, u8, write_u8, 16);
+                batch.encode(area)
+            },
+            &Req::GetCluster { cluster_id, offset, ref limit, } => {
+                let area = put_adv!(area, u8, write_u8, 17);
+                let area = put_adv!(area, u64, write_u64, cluster_id);
+                let area = put_adv!(area, u64, write_u64, offset);
+                limit.encode(area)
+            },
+            &Req::MergeClusters { from, into, } => {
+                let area = put_adv!(area, u8, write_u8, 18);
+                let area = put_adv!(area, u64, write_u64, from);
+                put_adv!(area, u64, write_u64, into)
+            },
+            &Req::MoveDocument { ref doc, ref to_cluster, } => {
+                let area = put_adv!(area, u8, write_u8, 19);
+                let area = doc.encode(area);
+                to_cluster.encode(area)
+            },
+            &Req::UpdateUserData { ref doc, ref user_data, } => {
+                let area = put_adv!(area, u8, write_u8, 20);
+                let area = doc.encode(area);
+                user_data.encode(area)
+            },
         }
     }
 }
@@ -203,6 +381,23 @@ impl<UD> FromBin for Trans<UD> where UD: FromBin + Debug {
                 let (req, area) = try!(Req::decode(area));
                 Ok((Trans::Sync(req), area))
             },
+            (3, area) => {
+                let (id, area) = try_get!(area, u64, read_u64);
+                if area.first() == Some(&3) {
+                    return Err(Error::InvalidNesting(3));
+                }
+                let (trans, area) = try!(Trans::decode(area));
+                Ok((Trans::Tagged(id, Box::new(trans)), area))
+            },
+            (4, area) => {
+                let (ms, area) = try_get!(area, u64, read_u64);
+                match area.first() {
+                    Some(&tag) if tag == 3 || tag == 4 => return Err(Error::InvalidNesting(tag)),
+                    _ => (),
+                }
+                let (trans, area) = try!(Trans::decode(area));
+                Ok((Trans::Deadline(ms, Box::new(trans)), area))
+            },
             (tag, _) =>
                 Err(Error::InvalidTag(tag)),
         }
@@ -213,13 +408,155 @@ impl<UD> FromBin for Req<UD> where UD: FromBin + Debug {
     fn decode<'a>(area: &'a [u8]) -> Result<(Req<UD>, &'a [u8]), Error> {
         match try_get!(area, u8, read_u8) {
             (1, area) =>
-                Ok((Req::Init, area)),
+                Ok((Req::Init(InitParams::default()), area)),
             (2, area) => {
-                let (workload, area) = try!(Workload::decode(area));
+                let (workload, area) = try!(decode_text_workload(area));
                 Ok((Req::Lookup(workload), area))
             },
             (3, area) =>
                 Ok((Req::Terminate, area)),
+            (4, area) => {
+                let (path, area) = try!(String::decode(area));
+                Ok((Req::Snapshot { path: path, }, area))
+            },
+            (5, area) => {
+                let (path, area) = try!(String::decode(area));
+                Ok((Req::Restore { path: path, }, area))
+            },
+            (6, area) => {
+                let (nonce, area) = try_get!(area, u64, read_u64);
+                Ok((Req::Ping(nonce), area))
+            },
+            (7, area) => {
+                let (collection, area) = try!(Option::decode(area));
+                Ok((Req::Init(InitParams { collection: collection, credentials: None, }), area))
+            },
+            (8, area) => {
+                let (name, area) = try!(String::decode(area));
+                Ok((Req::CreateCollection(name), area))
+            },
+            (9, area) =>
+                Ok((Req::ListCollections, area)),
+            (10, area) => {
+                let (name, area) = try!(String::decode(area));
+                Ok((Req::DropCollection(name), area))
+            },
+            (11, area) => {
+                let (tasks, area) = try!(Vec::decode(area));
+                let (chunk_size, area) = try_get!(area, u32, read_u32);
+                Ok((Req::LookupStream { tasks: tasks, chunk_size: chunk_size, }, area))
+            },
+            (12, area) => {
+                let (id, area) = try_get!(area, u64, read_u64);
+                Ok((Req::Cancel(id), area))
+            },
+            (13, area) => {
+                let (params, area) = try!(InitParams::decode(area));
+                Ok((Req::Init(params), area))
+            },
+            (14, area) => {
+                let (filter, area) = try!(EventFilter::decode(area));
+                Ok((Req::Subscribe(filter), area))
+            },
+            (15, area) => {
+                let (cursor, area) = try!(String::decode(area));
+                Ok((Req::FetchMore(cursor), area))
+            },
+            (16, area) => {
+                let (batch, area) = try!(Batch::decode(area));
+                Ok((Req::LookupBatch(batch), area))
+            },
+            (17, area) => {
+                let (cluster_id, area) = try_get!(area, u64, read_u64);
+                let (offset, area) = try_get!(area, u64, read_u64);
+                let (limit, area) = try!(Option::decode(area));
+                Ok((Req::GetCluster { cluster_id: cluster_id, offset: offset, limit: limit, }, area))
+            },
+            (18, area) => {
+                let (from, area) = try_get!(area, u64, read_u64);
+                let (into, area) = try_get!(area, u64, read_u64);
+                Ok((Req::MergeClusters { from: from, into: into, }, area))
+            },
+            (19, area) => {
+                let (doc, area) = try!(DocRef::decode(area));
+                let (to_cluster, area) = try!(Option::decode(area));
+                Ok((Req::MoveDocument { doc: doc, to_cluster: to_cluster, }, area))
+            },
+            (20, area) => {
+                let (doc, area) = try!(DocRef::decode(area));
+                let (user_data, area) = try!(UD::decode(area));
+                Ok((Req::UpdateUserData { doc: doc, user_data: user_data, }, area))
+            },
+            (21, area) => {
+                let (workload, area) = try!(Workload::decode(area));
+                Ok((Req::Lookup(workload), area))
+            },
+            (tag, _) =>
+                Err(Error::InvalidTag(tag)),
+        }
+    }
+}
+
+impl ToBin for InitParams {
+    fn encode_len(&self) -> usize {
+        self.collection.encode_len() + self.credentials.encode_len()
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        let area = self.collection.encode(area);
+        self.credentials.encode(area)
+    }
+}
+
+impl FromBin for InitParams {
+    fn decode<'a>(area: &'a [u8]) -> Result<(InitParams, &'a [u8]), Error> {
+        let (collection, area) = try!(Option::decode(area));
+        let (credentials, area) = try!(Option::decode(area));
+        Ok((InitParams {
+            collection: collection,
+            credentials: credentials,
+        }, area))
+    }
+}
+
+impl ToBin for Credentials {
+    fn encode_len(&self) -> usize {
+        size_of::<u8>() + match self {
+            &Credentials::Token(ref token) => token.encode_len(),
+            &Credentials::Hmac { ref key_id, ref nonce, ref mac, } =>
+                key_id.encode_len() + size_of::<u32>() + nonce.len() + size_of::<u32>() + mac.len(),
+        }
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        match self {
+            &Credentials::Token(ref token) => {
+                let area = put_adv!(area, u8, write_u8, 1);
+                token.encode(area)
+            },
+            &Credentials::Hmac { ref key_id, ref nonce, ref mac, } => {
+                let area = put_adv!(area, u8, write_u8, 2);
+                let area = key_id.encode(area);
+                let area = put_bytes_adv!(area, nonce);
+                put_bytes_adv!(area, mac)
+            },
+        }
+    }
+}
+
+impl FromBin for Credentials {
+    fn decode<'a>(area: &'a [u8]) -> Result<(Credentials, &'a [u8]), Error> {
+        match try_get!(area, u8, read_u8) {
+            (1, area) => {
+                let (token, area) = try!(String::decode(area));
+                Ok((Credentials::Token(token), area))
+            },
+            (2, area) => {
+                let (key_id, area) = try!(String::decode(area));
+                let (nonce, area) = try_get_bytes!(area);
+                let (mac, area) = try_get_bytes!(area);
+                Ok((Credentials::Hmac { key_id: key_id, nonce: nonce.to_vec(), mac: mac.to_vec(), }, area))
+            },
             (tag, _) =>
                 Err(Error::InvalidTag(tag)),
         }
@@ -230,7 +567,7 @@ impl<T> ToBin for Workload<T> where T: ToBin + Debug {
     fn encode_len(&self) -> usize {
         size_of::<u8>() + match self {
             &Workload::Single(ref value) => value.encode_len(),
-            &Workload::Many(ref values) => size_of::<u32>() + values.iter().fold(0, |total, value| total + value.encode_len()),
+            &Workload::Many(ref values) => values.encode_len(),
         }
     }
 
@@ -242,8 +579,7 @@ impl<T> ToBin for Workload<T> where T: ToBin + Debug {
             },
             &Workload::Many(ref values) => {
                 let area = put_adv!(area, u8, write_u8, 2);
-                let area = put_adv!(area, u32, write_u32, values.len() as u32);
-                values.iter().fold(area, |area, value| value.encode(area))
+                values.encode(area)
             },
         }
     }
@@ -257,13 +593,7 @@ impl<T> FromBin for Workload<T> where T: FromBin + Debug {
                 Ok((Workload::Single(value), area))
             },
             (2, area) => {
-                let (len, mut area) = try_get!(area, u32, read_u32);
-                let mut values = Vec::with_capacity(len as usize);
-                for _ in 0 .. len {
-                    let (value, next_area) = try!(T::decode(area));
-                    values.push(value);
-                    area = next_area;
-                }
+                let (values, area) = try!(Vec::decode(area));
                 Ok((Workload::Many(values), area))
             },
             (tag, _) =>
@@ -272,13 +602,77 @@ impl<T> FromBin for Workload<T> where T: FromBin + Debug {
     }
 }
 
+/// Task of a `Req::Lookup` where every document is a text. It keeps the baseline layout under
+/// tag 2, with the text in place of a tagged `Document`, so older peers still decode it; other
+/// workloads go under tag 21.
+#[derive(Debug)]
+struct TextTask<'a, UD>(&'a str, &'a LookupTask<UD>) where UD: 'a + Debug;
+
+fn text_workload<'a, UD>(workload: &'a Workload<LookupTask<UD>>) -> Option<Workload<TextTask<'a, UD>>> where UD: Debug {
+    fn text_task<'a, UD>(task: &'a LookupTask<UD>) -> Option<TextTask<'a, UD>> where UD: Debug {
+        match task.doc {
+            Document::Text(ref text) => Some(TextTask(text, task)),
+            _ => None,
+        }
+    }
+
+    match workload {
+        &Workload::Single(ref task) => text_task(task).map(Workload::Single),
+        &Workload::Many(ref tasks) => tasks.iter().map(text_task).collect::<Option<Vec<_>>>().map(Workload::Many),
+    }
+}
+
+impl<'a, UD> ToBin for TextTask<'a, UD> where UD: ToBin + Debug {
+    fn encode_len(&self) -> usize {
+        size_of::<u32>() + self.0.len() + self.1.result.encode_len() + self.1.post_action.encode_len()
+    }
+
+    fn encode<'b>(&self, area: &'b mut [u8]) -> &'b mut [u8] {
+        let area = put_bytes_adv!(area, self.0.as_bytes());
+        let area = self.1.result.encode(area);
+        self.1.post_action.encode(area)
+    }
+}
+
+fn decode_text_task<'a, UD>(area: &'a [u8]) -> Result<(LookupTask<UD>, &'a [u8]), Error> where UD: FromBin + Debug {
+    let (text, area) = try!(String::decode(area));
+    let (result, area) = try!(LookupType::decode(area));
+    let (post_action, area) = try!(PostAction::decode(area));
+    Ok((LookupTask {
+        doc: Document::Text(text),
+        result: result,
+        post_action: post_action,
+    }, area))
+}
+
+fn decode_text_workload<'a, UD>(area: &'a [u8]) -> Result<(Workload<LookupTask<UD>>, &'a [u8]), Error> where UD: FromBin + Debug {
+    match try_get!(area, u8, read_u8) {
+        (1, area) => {
+            let (task, area) = try!(decode_text_task(area));
+            Ok((Workload::Single(task), area))
+        },
+        (2, area) => {
+            let (len, mut area) = try_get!(area, u32, read_u32);
+            let mut tasks = Vec::with_capacity(cmp::min(len as usize, area.len()));
+            for _ in 0 .. len {
+                let (task, next_area) = try!(decode_text_task(area));
+                tasks.push(task);
+                area = next_area;
+            }
+            Ok((Workload::Many(tasks), area))
+        },
+        (tag, _) =>
+            Err(Error::InvalidTag(tag)),
+    }
+}
+
 impl<UD> ToBin for LookupTask<UD> where UD: ToBin + Debug {
     fn encode_len(&self) -> usize {
-        self.text.encode_len() + self.result.encode_len() + self.post_action.encode_len()
+        self.doc.encode_len() + self.result.encode_len() + self.post_action.encode_len()
     }
 
     fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
-        let area = self.text.encode(area);
+        let area = self.doc.encode(area);
         let area = self.result.encode(area);
         let area = self.post_action.encode(area);
         area
@@ -287,77 +681,139 @@ impl<UD> ToBin for LookupTask<UD> where UD: ToBin + Debug {
 
 impl<UD> FromBin for LookupTask<UD> where UD: FromBin + Debug {
     fn decode<'a>(area: &'a [u8]) -> Result<(LookupTask<UD>, &'a [u8]), Error> {
-        let (text, area) = try!(String::decode(area));
+        let (doc, area) = try!(Document::decode(area));
         let (result, area) = try!(LookupType::decode(area));
         let (post_action, area) = try!(PostAction::decode(area));
         Ok((LookupTask {
-            text: text,
+            doc: doc,
             result: result,
             post_action: post_action,
         }, area))
     }
 }
 
-impl ToBin for LookupType {
+impl<UD> ToBin for Batch<UD> where UD: ToBin + Debug {
     fn encode_len(&self) -> usize {
-        size_of::<u8>()
+        self.result.encode_len() + self.insert_cond.encode_len() + self.assign.encode_len() + self.tasks.encode_len()
     }
 
     fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
-        match self {
-            &LookupType::All => put_adv!(area, u8, write_u8, 1),
-            &LookupType::Best => put_adv!(area, u8, write_u8, 2),
-            &LookupType::BestOrMine => put_adv!(area, u8, write_u8, 3),
-        }
+        let area = self.result.encode(area);
+        let area = self.insert_cond.encode(area);
+        let area = self.assign.encode(area);
+        self.tasks.encode(area)
     }
 }
 
-impl FromBin for LookupType {
-    fn decode<'a>(area: &'a [u8]) -> Result<(LookupType, &'a [u8]), Error> {
-        match try_get!(area, u8, read_u8) {
-            (1, area) => Ok((LookupType::All, area)),
-            (2, area) => Ok((LookupType::Best, area)),
-            (3, area) => Ok((LookupType::BestOrMine, area)),
-            (tag, _) => Err(Error::InvalidTag(tag)),
-        }
+impl<UD> FromBin for Batch<UD> where UD: FromBin + Debug {
+    fn decode<'a>(area: &'a [u8]) -> Result<(Batch<UD>, &'a [u8]), Error> {
+        let (result, area) = try!(LookupType::decode(area));
+        let (insert_cond, area) = try!(InsertCond::decode(area));
+        let (assign, area) = try!(ClusterAssign::decode(area));
+        let (tasks, area) = try!(Vec::decode(area));
+        Ok((Batch {
+            result: result,
+            insert_cond: insert_cond,
+            assign: assign,
+            tasks: tasks,
+        }, area))
     }
 }
 
-impl<UD> ToBin for PostAction<UD> where UD: ToBin + Debug {
+impl<UD> ToBin for BatchTask<UD> where UD: ToBin + Debug {
+    fn encode_len(&self) -> usize {
+        self.doc.encode_len() + self.result.encode_len() + self.insert.encode_len()
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        let area = self.doc.encode(area);
+        let area = self.result.encode(area);
+        self.insert.encode(area)
+    }
+}
+
+impl<UD> FromBin for BatchTask<UD> where UD: FromBin + Debug {
+    fn decode<'a>(area: &'a [u8]) -> Result<(BatchTask<UD>, &'a [u8]), Error> {
+        let (doc, area) = try!(Document::decode(area));
+        let (result, area) = try!(Option::decode(area));
+        let (insert, area) = try!(Option::decode(area));
+        Ok((BatchTask {
+            doc: doc,
+            result: result,
+            insert: insert,
+        }, area))
+    }
+}
+
+impl<UD> ToBin for BatchInsert<UD> where UD: ToBin + Debug {
+    fn encode_len(&self) -> usize {
+        self.user_data.encode_len() + self.idempotency_key.encode_len() + self.cond.encode_len() + self.assign.encode_len()
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        let area = self.user_data.encode(area);
+        let area = self.idempotency_key.encode(area);
+        let area = self.cond.encode(area);
+        self.assign.encode(area)
+    }
+}
+
+impl<UD> FromBin for BatchInsert<UD> where UD: FromBin + Debug {
+    fn decode<'a>(area: &'a [u8]) -> Result<(BatchInsert<UD>, &'a [u8]), Error> {
+        let (user_data, area) = try!(UD::decode(area));
+        let (idempotency_key, area) = try!(Option::decode(area));
+        let (cond, area) = try!(Option::decode(area));
+        let (assign, area) = try!(Option::decode(area));
+        Ok((BatchInsert {
+            user_data: user_data,
+            idempotency_key: idempotency_key,
+            cond: cond,
+            assign: assign,
+        }, area))
+    }
+}
+
+impl ToBin for Document {
     fn encode_len(&self) -> usize {
         size_of::<u8>() + match self {
-            &PostAction::None =>
-                0,
-            &PostAction::InsertNew { cond: ref c, assign: ref a, user_data: ref u, } =>
-                c.encode_len() + a.encode_len() + u.encode_len(),
+            &Document::Text(ref text) => text.encode_len(),
+            &Document::Bytes(ref bytes) => size_of::<u32>() + bytes.len(),
+            &Document::Signature(ref signature) => signature.encode_len(),
         }
     }
 
     fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
         match self {
-            &PostAction::None =>
-                put_adv!(area, u8, write_u8, 1),
-            &PostAction::InsertNew { cond: ref c, assign: ref a, user_data: ref u, } => {
+            &Document::Text(ref text) => {
+                let area = put_adv!(area, u8, write_u8, 1);
+                text.encode(area)
+            },
+            &Document::Bytes(ref bytes) => {
                 let area = put_adv!(area, u8, write_u8, 2);
-                let area = c.encode(area);
-                let area = a.encode(area);
-                let area = u.encode(area);
-                area
+                put_bytes_adv!(area, bytes)
+            },
+            &Document::Signature(ref signature) => {
+                let area = put_adv!(area, u8, write_u8, 3);
+                signature.encode(area)
             },
         }
     }
 }
 
-impl<UD> FromBin for PostAction<UD> where UD: FromBin + Debug {
-    fn decode<'a>(area: &'a [u8]) -> Result<(PostAction<UD>, &'a [u8]), Error> {
+impl FromBin for Document {
+    fn decode<'a>(area: &'a [u8]) -> Result<(Document, &'a [u8]), Error> {
         match try_get!(area, u8, read_u8) {
-            (1, area) =>
-                Ok((PostAction::None, area)),
+            (1, area) => {
+                let (text, area) = try!(String::decode(area));
+                Ok((Document::Text(text), area))
+            },
             (2, area) => {
-                let (cond, area) = try!(InsertCond::decode(area));
-                let (assign, area) = try!(ClusterAssign::decode(area));
-                let (user_data, area) = try!(UD::decode(area));
-                Ok((PostAction::InsertNew { cond: cond, assign: assign, user_data: user_data, }, area))
+                let (bytes, area) = try_get_bytes!(area);
+                Ok((Document::Bytes(bytes.to_vec()), area))
+            },
+            (3, area) => {
+                let (signature, area) = try!(Signature::decode(area));
+                Ok((Document::Signature(signature), area))
             },
             (tag, _) =>
                 Err(Error::InvalidTag(tag)),
@@ -365,34 +821,42 @@ impl<UD> FromBin for PostAction<UD> where UD: FromBin + Debug {
     }
 }
 
-impl ToBin for InsertCond {
+impl ToBin for Signature {
     fn encode_len(&self) -> usize {
         size_of::<u8>() + match self {
-            &InsertCond::Always => 0,
-            &InsertCond::BestSimLessThan(..) => size_of::<f64>(),
+            &Signature::MinHash { shingle_len: ref l, hashes: ref h, } | &Signature::Shingles { shingle_len: ref l, hashes: ref h, } =>
+                l.encode_len() + h.encode_len(),
         }
     }
 
     fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
         match self {
-            &InsertCond::Always =>
-                put_adv!(area, u8, write_u8, 1),
-            &InsertCond::BestSimLessThan(sim) => {
+            &Signature::MinHash { shingle_len: l, hashes: ref h, } => {
+                let area = put_adv!(area, u8, write_u8, 1);
+                let area = put_adv!(area, u32, write_u32, l);
+                h.encode(area)
+            },
+            &Signature::Shingles { shingle_len: l, hashes: ref h, } => {
                 let area = put_adv!(area, u8, write_u8, 2);
-                put_adv!(area, f64, write_f64, sim)
+                let area = put_adv!(area, u32, write_u32, l);
+                h.encode(area)
             },
         }
     }
 }
 
-impl FromBin for InsertCond {
-    fn decode<'a>(area: &'a [u8]) -> Result<(InsertCond, &'a [u8]), Error> {
+impl FromBin for Signature {
+    fn decode<'a>(area: &'a [u8]) -> Result<(Signature, &'a [u8]), Error> {
         match try_get!(area, u8, read_u8) {
-            (1, area) =>
-                Ok((InsertCond::Always, area)),
+            (1, area) => {
+                let (shingle_len, area) = try_get!(area, u32, read_u32);
+                let (hashes, area) = try!(Vec::decode(area));
+                Ok((Signature::MinHash { shingle_len: shingle_len, hashes: hashes, }, area))
+            },
             (2, area) => {
-                let (sim, area) = try_get!(area, f64, read_f64);
-                Ok((InsertCond::BestSimLessThan(sim), area))
+                let (shingle_len, area) = try_get!(area, u32, read_u32);
+                let (hashes, area) = try!(Vec::decode(area));
+                Ok((Signature::Shingles { shingle_len: shingle_len, hashes: hashes, }, area))
             },
             (tag, _) =>
                 Err(Error::InvalidTag(tag)),
@@ -400,57 +864,121 @@ impl FromBin for InsertCond {
     }
 }
 
-impl ToBin for ClusterAssign {
+impl ToBin for LookupType {
     fn encode_len(&self) -> usize {
-        self.cond.encode_len() + self.choice.encode_len()
+        size_of::<u8>() + match self {
+            &LookupType::All | &LookupType::Best | &LookupType::BestOrMine => 0,
+            &LookupType::TopK(..) => size_of::<u32>(),
+            &LookupType::AboveThreshold(..) => size_of::<f64>(),
+            &LookupType::TopKAboveThreshold(..) => size_of::<u32>() + size_of::<f64>(),
+        }
     }
 
     fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
-        let area = self.cond.encode(area);
-        let area = self.choice.encode(area);
-        area
-    }
-}
-
-impl FromBin for ClusterAssign {
-    fn decode<'a>(area: &'a [u8]) -> Result<(ClusterAssign, &'a [u8]), Error> {
-        let (cond, area) = try!(AssignCond::decode(area));
-        let (choice, area) = try!(ClusterChoice::decode(area));
-        Ok((ClusterAssign {
-            cond: cond,
-            choice: choice,
-        }, area))
+        match self {
+            &LookupType::All => put_adv!(area, u8, write_u8, 1),
+            &LookupType::Best => put_adv!(area, u8, write_u8, 2),
+            &LookupType::BestOrMine => put_adv!(area, u8, write_u8, 3),
+            &LookupType::TopK(k) => {
+                let area = put_adv!(area, u8, write_u8, 4);
+                put_adv!(area, u32, write_u32, k)
+            },
+            &LookupType::AboveThreshold(threshold) => {
+                let area = put_adv!(area, u8, write_u8, 5);
+                put_adv!(area, f64, write_f64, threshold)
+            },
+            &LookupType::TopKAboveThreshold(k, threshold) => {
+                let area = put_adv!(area, u8, write_u8, 6);
+                let area = put_adv!(area, u32, write_u32, k);
+                put_adv!(area, f64, write_f64, threshold)
+            },
+        }
+    }
+}
+
+impl FromBin for LookupType {
+    fn decode<'a>(area: &'a [u8]) -> Result<(LookupType, &'a [u8]), Error> {
+        match try_get!(area, u8, read_u8) {
+            (1, area) => Ok((LookupType::All, area)),
+            (2, area) => Ok((LookupType::Best, area)),
+            (3, area) => Ok((LookupType::BestOrMine, area)),
+            (4, area) => {
+                let (k, area) = try_get!(area, u32, read_u32);
+                Ok((LookupType::TopK(k), area))
+            },
+            (5, area) => {
+                let (threshold, area) = try_get!(area, f64, read_f64);
+                Ok((LookupType::AboveThreshold(try!(check_threshold(threshold))), area))
+            },
+            (6, area) => {
+                let (k, area) = try_get!(area, u32, read_u32);
+                let (threshold, area) = try_get!(area, f64, read_f64);
+                Ok((LookupType::TopKAboveThreshold(k, try!(check_threshold(threshold))), area))
+            },
+            (tag, _) => Err(Error::InvalidTag(tag)),
+        }
+    }
+}
+
+fn check_threshold(threshold: f64) -> Result<f64, Error> {
+    if threshold >= 0.0 && threshold <= 1.0 {
+        Ok(threshold)
+    } else {
+        Err(Error::InvalidThreshold(threshold))
     }
 }
 
-impl ToBin for AssignCond {
+impl<UD> ToBin for PostAction<UD> where UD: ToBin + Debug {
     fn encode_len(&self) -> usize {
         size_of::<u8>() + match self {
-            &AssignCond::Always => 0,
-            &AssignCond::BestSimLessThan(..) => size_of::<f64>(),
+            &PostAction::None =>
+                0,
+            &PostAction::InsertNew { cond: ref c, assign: ref a, user_data: ref u, idempotency_key: None, } =>
+                c.encode_len() + a.encode_len() + u.encode_len(),
+            &PostAction::InsertNew { cond: ref c, assign: ref a, user_data: ref u, idempotency_key: Some(ref k), } =>
+                c.encode_len() + a.encode_len() + u.encode_len() + k.encode_len(),
         }
     }
 
     fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
         match self {
-            &AssignCond::Always =>
+            &PostAction::None =>
                 put_adv!(area, u8, write_u8, 1),
-            &AssignCond::BestSimLessThan(sim) => {
+            &PostAction::InsertNew { cond: ref c, assign: ref a, user_data: ref u, idempotency_key: None, } => {
                 let area = put_adv!(area, u8, write_u8, 2);
-                put_adv!(area, f64, write_f64, sim)
+                let area = c.encode(area);
+                let area = a.encode(area);
+                let area = u.encode(area);
+                area
+            },
+            &PostAction::InsertNew { cond: ref c, assign: ref a, user_data: ref u, idempotency_key: Some(ref k), } => {
+                let area = put_adv!(area, u8, write_u8, 3);
+                let area = c.encode(area);
+                let area = a.encode(area);
+                let area = u.encode(area);
+                k.encode(area)
             },
         }
     }
 }
 
-impl FromBin for AssignCond {
-    fn decode<'a>(area: &'a [u8]) -> Result<(AssignCond, &'a [u8]), Error> {
+impl<UD> FromBin for PostAction<UD> where UD: FromBin + Debug {
+    fn decode<'a>(area: &'a [u8]) -> Result<(PostAction<UD>, &'a [u8]), Error> {
         match try_get!(area, u8, read_u8) {
             (1, area) =>
-                Ok((AssignCond::Always, area)),
+                Ok((PostAction::None, area)),
             (2, area) => {
-                let (sim, area) = try_get!(area, f64, read_f64);
-                Ok((AssignCond::BestSimLessThan(sim), area))
+                let (cond, area) = try!(InsertCond::decode(area));
+                let (assign, area) = try!(ClusterAssign::decode(area));
+                let (user_data, area) = try!(UD::decode(area));
+                Ok((PostAction::InsertNew { cond: cond, assign: assign, user_data: user_data, idempotency_key: None, }, area))
+            },
+            (3, area) => {
+                let (cond, area) = try!(InsertCond::decode(area));
+                let (assign, area) = try!(ClusterAssign::decode(area));
+                let (user_data, area) = try!(UD::decode(area));
+                let (key, area) = try!(String::decode(area));
+                Ok((PostAction::InsertNew { cond: cond, assign: assign, user_data: user_data, idempotency_key: Some(key), }, area))
             },
             (tag, _) =>
                 Err(Error::InvalidTag(tag)),
@@ -458,10 +986,141 @@ impl FromBin for AssignCond {
     }
 }
 
+impl ToBin for ClusterAssign {
+    fn encode_len(&self) -> usize {
+        self.cond.encode_len() + self.choice.encode_len()
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        let area = self.cond.encode(area);
+        let area = self.choice.encode(area);
+        area
+    }
+}
+
+impl FromBin for ClusterAssign {
+    fn decode<'a>(area: &'a [u8]) -> Result<(ClusterAssign, &'a [u8]), Error> {
+        let (cond, area) = try!(AssignCond::decode(area));
+        let (choice, area) = try!(ClusterChoice::decode(area));
+        Ok((ClusterAssign {
+            cond: cond,
+            choice: choice,
+        }, area))
+    }
+}
+
+impl ToBin for Cond {
+    fn encode_len(&self) -> usize {
+        size_of::<u8>() + match self {
+            &Cond::Always | &Cond::Never => 0,
+            &Cond::BestSimLessThan(..) | &Cond::BestSimGreaterThan(..) => size_of::<f64>(),
+            &Cond::TextLenGreaterThan(..) | &Cond::NoNeighbourInCluster(..) => size_of::<u64>(),
+            &Cond::And(ref conds) | &Cond::Or(ref conds) => conds.encode_len(),
+            &Cond::Not(ref cond) => cond.encode_len(),
+        }
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        match self {
+            &Cond::Always =>
+                put_adv!(area, u8, write_u8, 1),
+            &Cond::BestSimLessThan(sim) => {
+                let area = put_adv!(area, u8, write_u8, 2);
+                put_adv!(area, f64, write_f64, sim)
+            },
+            &Cond::Never =>
+                put_adv!(area, u8, write_u8, 3),
+            &Cond::BestSimGreaterThan(sim) => {
+                let area = put_adv!(area, u8, write_u8, 4);
+                put_adv!(area, f64, write_f64, sim)
+            },
+            &Cond::TextLenGreaterThan(len) => {
+                let area = put_adv!(area, u8, write_u8, 5);
+                put_adv!(area, u64, write_u64, len)
+            },
+            &Cond::NoNeighbourInCluster(cluster_id) => {
+                let area = put_adv!(area, u8, write_u8, 6);
+                put_adv!(area, u64, write_u64, cluster_id)
+            },
+            &Cond::And(ref conds) => {
+                let area = put_adv!(area, u8, write_u8, 7);
+                conds.encode(area)
+            },
+            &Cond::Or(ref conds) => {
+                let area = put_adv!(area, u8, write_u8, 8);
+                conds.encode(area)
+            },
+            &Cond::Not(ref cond) => {
+                let area = put_adv!(area, u8, write_u8, 9);
+                cond.encode(area)
+            },
+        }
+    }
+}
+
+impl FromBin for Cond {
+    fn decode<'a>(area: &'a [u8]) -> Result<(Cond, &'a [u8]), Error> {
+        decode_cond(area, MAX_COND_DEPTH)
+    }
+}
+
+fn decode_conds<'a>(area: &'a [u8], depth: usize) -> Result<(Vec<Cond>, &'a [u8]), Error> {
+    let (len, mut area) = try_get!(area, u32, read_u32);
+    let mut conds = Vec::with_capacity(cmp::min(len as usize, area.len()));
+    for _ in 0 .. len {
+        let (cond, next_area) = try!(decode_cond(area, depth));
+        conds.push(cond);
+        area = next_area;
+    }
+    Ok((conds, area))
+}
+
+/// Decodes a condition allowing at most `depth` levels of `And`, `Or` and `Not`.
+fn decode_cond<'a>(area: &'a [u8], depth: usize) -> Result<(Cond, &'a [u8]), Error> {
+    match try_get!(area, u8, read_u8) {
+        (tag, _) if depth == 0 && tag >= 7 && tag <= 9 =>
+            Err(Error::InvalidNesting(tag)),
+        (1, area) =>
+            Ok((Cond::Always, area)),
+        (2, area) => {
+            let (sim, area) = try_get!(area, f64, read_f64);
+            Ok((Cond::BestSimLessThan(sim), area))
+        },
+        (3, area) =>
+            Ok((Cond::Never, area)),
+        (4, area) => {
+            let (sim, area) = try_get!(area, f64, read_f64);
+            Ok((Cond::BestSimGreaterThan(sim), area))
+        },
+        (5, area) => {
+            let (len, area) = try_get!(area, u64, read_u64);
+            Ok((Cond::TextLenGreaterThan(len), area))
+        },
+        (6, area) => {
+            let (cluster_id, area) = try_get!(area, u64, read_u64);
+            Ok((Cond::NoNeighbourInCluster(cluster_id), area))
+        },
+        (7, area) => {
+            let (conds, area) = try!(decode_conds(area, depth - 1));
+            Ok((Cond::And(conds), area))
+        },
+        (8, area) => {
+            let (conds, area) = try!(decode_conds(area, depth - 1));
+            Ok((Cond::Or(conds), area))
+        },
+        (9, area) => {
+            let (cond, area) = try!(decode_cond(area, depth - 1));
+            Ok((Cond::Not(Box::new(cond)), area))
+        },
+        (tag, _) =>
+            Err(Error::InvalidTag(tag)),
+    }
+}
+
 impl ToBin for ClusterChoice {
     fn encode_len(&self) -> usize {
         size_of::<u8>() + match self {
-            &ClusterChoice::ServerChoice => 0,
+            &ClusterChoice::ServerChoice | &ClusterChoice::BestMatchCluster | &ClusterChoice::NewCluster => 0,
             &ClusterChoice::ClientChoice(..) => size_of::<u64>(),
         }
     }
@@ -474,6 +1133,10 @@ impl ToBin for ClusterChoice {
                 let area = put_adv!(area, u8, write_u8, 2);
                 put_adv!(area, u64, write_u64, cluster_id)
             },
+            &ClusterChoice::BestMatchCluster =>
+                put_adv!(area, u8, write_u8, 3),
+            &ClusterChoice::NewCluster =>
+                put_adv!(area, u8, write_u8, 4),
         }
     }
 }
@@ -487,6 +1150,10 @@ impl FromBin for ClusterChoice {
                 let (cluster_id, area) = try_get!(area, u64, read_u64);
                 Ok((ClusterChoice::ClientChoice(cluster_id), area))
             },
+            (3, area) =>
+                Ok((ClusterChoice::BestMatchCluster, area)),
+            (4, area) =>
+                Ok((ClusterChoice::NewCluster, area)),
             (tag, _) =>
                 Err(Error::InvalidTag(tag)),
         }
@@ -496,9 +1163,31 @@ impl FromBin for ClusterChoice {
 impl<UD> ToBin for Rep<UD> where UD: ToBin + Debug {
     fn encode_len(&self) -> usize {
         size_of::<u8>() + match self {
-            &Rep::InitAck | &Rep::TerminateAck | &Rep::TooBusy | &Rep::WantCrash => 0,
+            &Rep::InitAck | &Rep::TerminateAck | &Rep::WantCrash | &Rep::SnapshotAck | &Rep::RestoreAck => 0,
+            &Rep::CreateCollectionAck | &Rep::DropCollectionAck | &Rep::DeadlineExceeded => 0,
+            &Rep::TooBusy(ref info) if info.is_default() => 0,
+            &Rep::TooBusy(ref info) => info.encode_len(),
+            &Rep::Collections(ref names) => names.encode_len(),
+            &Rep::UnknownCollection(ref name) | &Rep::CollectionExists(ref name) => name.encode_len(),
             &Rep::Result(ref workload) => workload.encode_len(),
-            &Rep::Unexpected(ref req) => req.encode_len(),
+            &Rep::Unexpected { reason: UnexpectedReason::Unspecified, req: ReqEcho::Full(ref req), } => req.encode_len(),
+            &Rep::Unexpected { ref reason, ref req, } => reason.encode_len() + req.encode_len(),
+            &Rep::SnapshotError(ref e) => e.encode_len(),
+            &Rep::Pong(..) => size_of::<u64>(),
+            &Rep::Tagged(_, ref rep) => size_of::<u64>() + rep.encode_len(),
+            &Rep::ResultChunk { ref results, .. } => size_of::<u32>() + results.encode_len(),
+            &Rep::EndOfStream(..) => size_of::<u32>(),
+            &Rep::Cancelled(_, ref state) => size_of::<u64>() + state.encode_len(),
+            &Rep::AuthFailed(ref reason) => reason.encode_len(),
+            &Rep::AuthChallenge(ref nonce) => nonce.encode_len(),
+            &Rep::SubscribeAck => 0,
+            &Rep::Event(ref event) => event.encode_len(),
+            &Rep::Cluster(ref info) => info.encode_len(),
+            &Rep::ClusterNotFound(..) => size_of::<u64>(),
+            &Rep::ClustersMerged { .. } => size_of::<u64>() + size_of::<u64>(),
+            &Rep::DocumentMoved { ref doc, .. } => doc.encode_len() + size_of::<u64>(),
+            &Rep::DocumentNotFound(ref doc) => doc.encode_len(),
+            &Rep::UserDataUpdated(ref doc) => doc.encode_len(),
         }
     }
 
@@ -512,14 +1201,112 @@ impl<UD> ToBin for Rep<UD> where UD: ToBin + Debug {
             },
             &Rep::TerminateAck =>
                 put_adv!(area, u8, write_u8, 3),
-            &Rep::Unexpected(ref req) => {
+            &Rep::Unexpected { reason: UnexpectedReason::Unspecified, req: ReqEcho::Full(ref req), } => {
                 let area = put_adv!(area, u8, write_u8, 4);
                 req.encode(area)
             },
-            &Rep::TooBusy =>
+            &Rep::TooBusy(ref info) if info.is_default() =>
                 put_adv!(area, u8, write_u8, 5),
             &Rep::WantCrash =>
                 put_adv!(area, u8, write_u8, 6),
+            &Rep::SnapshotAck =>
+                put_adv!(area, u8, write_u8, 7),
+            &Rep::RestoreAck =>
+                put_adv!(area, u8, write_u8, 8),
+            &Rep::SnapshotError(ref e) => {
+                let area = put_adv!(area, u8, write_u8, 9);
+                e.encode(area)
+            },
+            &Rep::Pong(nonce) => {
+                let area = put_adv!(area, u8, write_u8, 10);
+                put_adv!(area, u64, write_u64, nonce)
+            },
+            &Rep::Tagged(id, ref rep) => {
+                let area = put_adv!(area, u8, write_u8, 11);
+                let area = put_adv!(area, u64, write_u64, id);
+                rep.encode(area)
+            },
+            &Rep::CreateCollectionAck =>
+                put_adv!(area, u8, write_u8, 12),
+            &Rep::Collections(ref names) => {
+                let area = put_adv!(area, u8, write_u8, 13);
+                names.encode(area)
+            },
+            &Rep::DropCollectionAck =>
+                put_adv!(area, u8, write_u8, 14),
+            &Rep::UnknownCollection(ref name) => {
+                let area = put_adv!(area, u8, write_u8, 15);
+                name.encode(area)
+            },
+            &Rep::CollectionExists(ref name) => {
+                let area = put_adv!(area, u8, write_u8, 16);
+                name.encode(area)
+            },
+            &Rep::DeadlineExceeded =>
+                put_adv!(area, u8, write_u8, 17),
+            &Rep::TooBusy(ref info) => {
+                let area = put_adv!(area, u8, write_u8, 18);
+                info.encode(area)
+            },
+            &Rep::Unexpected { ref reason, ref req, } => {
+                let area = put_adv!(area, u8, write_u8, 19);
+                let area = reason.encode(area);
+                req.encode(area)
+            },
+            &Rep::ResultChunk { start, ref results, } => {
+                let area = put_adv!(area, u8, write_u8, 20);
+                let area = put_adv!(area, u32, write_u32, start);
+                results.encode(area)
+            },
+            &Rep::EndOfStream(total) => {
+                let area = put_adv!(area, u8, write_u8, 21);
+                put_adv!(area, u32, write_u32, total)
+            },
+            &Rep::Cancelled(id, ref state) => {
+                let area = put_adv!(area, u8, write_u8, 22);
+                let area = put_adv!(area, u64, write_u64, id);
+                state.encode(area)
+            },
+            &Rep::AuthFailed(ref reason) => {
+                let area = put_adv!(area, u8, write_u8, 23);
+                reason.encode(area)
+            },
+            &Rep::AuthChallenge(ref nonce) => {
+                let area = put_adv!(area, u8, write_u8, 32);
+                nonce.encode(area)
+            },
+            &Rep::SubscribeAck =>
+                put_adv!(area, u8, write_u8, 24),
+            &Rep::Event(ref event) => {
+                let area = put_adv!(area, u8, write_u8, 25);
+                event.encode(area)
+            },
+            &Rep::Cluster(ref info) => {
+                let area = put_adv!(area, u8, write_u8, 26);
+                info.encode(area)
+            },
+            &Rep::ClusterNotFound(cluster_id) => {
+                let area = put_adv!(area, u8, write_u8, 27);
+                put_adv!(area, u64, write_u64, cluster_id)
+            },
+            &Rep::ClustersMerged { from, into, } => {
+                let area = put_adv!(area, u8, write_u8, 28);
+                let area = put_adv!(area, u64, write_u64, from);
+                put_adv!(area, u64, write_u64, into)
+            },
+            &Rep::DocumentMoved { ref doc, to_cluster, } => {
+                let area = put_adv!(area, u8, write_u8, 29);
+                let area = doc.encode(area);
+                put_adv!(area, u64, write_u64, to_cluster)
+            },
+            &Rep::DocumentNotFound(ref doc) => {
+                let area = put_adv!(area, u8, write_u8, 30);
+                doc.encode(area)
+            },
+            &Rep::UserDataUpdated(ref doc) => {
+                let area = put_adv!(area, u8, write_u8, 31);
+                doc.encode(area)
+            },
         }
     }
 }
@@ -537,158 +1324,1232 @@ impl<UD> FromBin for Rep<UD> where UD: FromBin + Debug {
                 Ok((Rep::TerminateAck, area)),
             (4, area) => {
                 let (req, area) = try!(Req::decode(area));
-                Ok((Rep::Unexpected(req), area))
+                Ok((Rep::Unexpected { reason: UnexpectedReason::Unspecified, req: ReqEcho::Full(req), }, area))
             },
             (5, area) =>
-                Ok((Rep::TooBusy, area)),
+                Ok((Rep::TooBusy(BusyInfo::default()), area)),
             (6, area) =>
                 Ok((Rep::WantCrash, area)),
+            (7, area) =>
+                Ok((Rep::SnapshotAck, area)),
+            (8, area) =>
+                Ok((Rep::RestoreAck, area)),
+            (9, area) => {
+                let (e, area) = try!(String::decode(area));
+                Ok((Rep::SnapshotError(e), area))
+            },
+            (10, area) => {
+                let (nonce, area) = try_get!(area, u64, read_u64);
+                Ok((Rep::Pong(nonce), area))
+            },
+            (11, area) => {
+                let (id, area) = try_get!(area, u64, read_u64);
+                if area.first() == Some(&11) {
+                    return Err(Error::InvalidNesting(11));
+                }
+                let (rep, area) = try!(Rep::decode(area));
+                Ok((Rep::Tagged(id, Box::new(rep)), area))
+            },
+            (12, area) =>
+                Ok((Rep::CreateCollectionAck, area)),
+            (13, area) => {
+                let (names, area) = try!(Vec::decode(area));
+                Ok((Rep::Collections(names), area))
+            },
+            (14, area) =>
+                Ok((Rep::DropCollectionAck, area)),
+            (15, area) => {
+                let (name, area) = try!(String::decode(area));
+                Ok((Rep::UnknownCollection(name), area))
+            },
+            (16, area) => {
+                let (name, area) = try!(String::decode(area));
+                Ok((Rep::CollectionExists(name), area))
+            },
+            (17, area) =>
+                Ok((Rep::DeadlineExceeded, area)),
+            (18, area) => {
+                let (info, area) = try!(BusyInfo::decode(area));
+                Ok((Rep::TooBusy(info), area))
+            },
+            (19, area) => {
+                let (reason, area) = try!(UnexpectedReason::decode(area));
+                let (req, area) = try!(ReqEcho::decode(area));
+                Ok((Rep::Unexpected { reason: reason, req: req, }, area))
+            },
+            (20, area) => {
+                let (start, area) = try_get!(area, u32, read_u32);
+                let (results, area) = try!(Vec::decode(area));
+                Ok((Rep::ResultChunk { start: start, results: results, }, area))
+            },
+            (21, area) => {
+                let (total, area) = try_get!(area, u32, read_u32);
+                Ok((Rep::EndOfStream(total), area))
+            },
+            (22, area) => {
+                let (id, area) = try_get!(area, u64, read_u64);
+                let (state, area) = try!(CancelState::decode(area));
+                Ok((Rep::Cancelled(id, state), area))
+            },
+            (23, area) => {
+                let (reason, area) = try!(String::decode(area));
+                Ok((Rep::AuthFailed(reason), area))
+            },
+            (24, area) =>
+                Ok((Rep::SubscribeAck, area)),
+            (25, area) => {
+                let (event, area) = try!(Event::decode(area));
+                Ok((Rep::Event(event), area))
+            },
+            (26, area) => {
+                let (info, area) = try!(ClusterInfo::decode(area));
+                Ok((Rep::Cluster(info), area))
+            },
+            (27, area) => {
+                let (cluster_id, area) = try_get!(area, u64, read_u64);
+                Ok((Rep::ClusterNotFound(cluster_id), area))
+            },
+            (28, area) => {
+                let (from, area) = try_get!(area, u64, read_u64);
+                let (into, area) = try_get!(area, u64, read_u64);
+                Ok((Rep::ClustersMerged { from: from, into: into, }, area))
+            },
+            (29, area) => {
+                let (doc, area) = try!(DocRef::decode(area));
+                let (to_cluster, area) = try_get!(area, u64, read_u64);
+                Ok((Rep::DocumentMoved { doc: doc, to_cluster: to_cluster, }, area))
+            },
+            (30, area) => {
+                let (doc, area) = try!(DocRef::decode(area));
+                Ok((Rep::DocumentNotFound(doc), area))
+            },
+            (31, area) => {
+                let (doc, area) = try!(DocRef::decode(area));
+                Ok((Rep::UserDataUpdated(doc), area))
+            },
+            (32, area) => {
+                let (nonce, area) = try!(Vec::decode(area));
+                Ok((Rep::AuthChallenge(nonce), area))
+            },
             (tag, _) =>
                 Err(Error::InvalidTag(tag)),
         }
     }
 }
 
-impl<UD> ToBin for LookupResult<UD> where UD: ToBin + Debug {
+impl ToBin for UnexpectedReason {
     fn encode_len(&self) -> usize {
-        size_of::<u8>() + match self {
-            &LookupResult::EmptySet => 0,
-            &LookupResult::Best(ref m) => m.encode_len(),
-            &LookupResult::Neighbours(ref workload) => workload.encode_len(),
-            &LookupResult::Error(ref e) => e.encode_len(),
-        }
+        size_of::<u8>()
     }
 
     fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
         match self {
-            &LookupResult::EmptySet =>
+            &UnexpectedReason::Unspecified =>
                 put_adv!(area, u8, write_u8, 1),
-            &LookupResult::Best(ref m) => {
-                let area = put_adv!(area, u8, write_u8, 2);
-                m.encode(area)
-            },
-            &LookupResult::Neighbours(ref workload) => {
-                let area = put_adv!(area, u8, write_u8, 3);
-                workload.encode(area)
-            },
-            &LookupResult::Error(ref e) => {
-                let area = put_adv!(area, u8, write_u8, 4);
-                e.encode(area)
-            },
+            &UnexpectedReason::NotInitialized =>
+                put_adv!(area, u8, write_u8, 2),
+            &UnexpectedReason::AlreadyInitialized =>
+                put_adv!(area, u8, write_u8, 3),
+            &UnexpectedReason::Terminated =>
+                put_adv!(area, u8, write_u8, 4),
+            &UnexpectedReason::Unsupported =>
+                put_adv!(area, u8, write_u8, 5),
         }
     }
 }
 
-impl<UD> FromBin for LookupResult<UD> where UD: FromBin + Debug {
-    fn decode<'a>(area: &'a [u8]) -> Result<(LookupResult<UD>, &'a [u8]), Error> {
+impl FromBin for UnexpectedReason {
+    fn decode<'a>(area: &'a [u8]) -> Result<(UnexpectedReason, &'a [u8]), Error> {
         match try_get!(area, u8, read_u8) {
             (1, area) =>
-                Ok((LookupResult::EmptySet, area)),
-            (2, area) => {
-                let (m, area) = try!(Match::decode(area));
-                Ok((LookupResult::Best(m), area))
-            },
-            (3, area) => {
-                let (workload, area) = try!(Workload::decode(area));
-                Ok((LookupResult::Neighbours(workload), area))
-            },
-            (4, area) => {
-                let (e, area) = try!(String::decode(area));
-                Ok((LookupResult::Error(e), area))
-            },
+                Ok((UnexpectedReason::Unspecified, area)),
+            (2, area) =>
+                Ok((UnexpectedReason::NotInitialized, area)),
+            (3, area) =>
+                Ok((UnexpectedReason::AlreadyInitialized, area)),
+            (4, area) =>
+                Ok((UnexpectedReason::Terminated, area)),
+            (5, area) =>
+                Ok((UnexpectedReason::Unsupported, area)),
             (tag, _) =>
                 Err(Error::InvalidTag(tag)),
         }
     }
 }
 
-impl<UD> ToBin for Match<UD> where UD: ToBin + Debug {
+impl<UD> ToBin for ReqEcho<UD> where UD: ToBin + Debug {
     fn encode_len(&self) -> usize {
-        size_of::<u64>() + size_of::<f64>() + self.user_data.encode_len()
+        size_of::<u8>() + match self {
+            &ReqEcho::Full(ref req) => req.encode_len(),
+            &ReqEcho::Kind(ref kind) => kind.encode_len(),
+        }
     }
 
     fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
-        let area = put_adv!(area, u64, write_u64, self.cluster_id);
-        let area = put_adv!(area, f64, write_f64, self.similarity);
-        let area = self.user_data.encode(area);
-        area
+        match self {
+            &ReqEcho::Full(ref req) => {
+                let area = put_adv!(area, u8, write_u8, 1);
+                req.encode(area)
+            },
+            &ReqEcho::Kind(ref kind) => {
+                let area = put_adv!(area, u8, write_u8, 2);
+                kind.encode(area)
+            },
+        }
     }
 }
 
-impl<UD> FromBin for Match<UD> where UD: FromBin + Debug {
-    fn decode<'a>(area: &'a [u8]) -> Result<(Match<UD>, &'a [u8]), Error> {
-        let (cluster_id, area) = try_get!(area, u64, read_u64);
-        let (similarity, area) = try_get!(area, f64, read_f64);
-        let (user_data, area) = try!(UD::decode(area));
-        Ok((Match {
-            cluster_id: cluster_id,
-            similarity: similarity,
-            user_data: user_data,
-        }, area))
+impl<UD> FromBin for ReqEcho<UD> where UD: FromBin + Debug {
+    fn decode<'a>(area: &'a [u8]) -> Result<(ReqEcho<UD>, &'a [u8]), Error> {
+        match try_get!(area, u8, read_u8) {
+            (1, area) => {
+                let (req, area) = try!(Req::decode(area));
+                Ok((ReqEcho::Full(req), area))
+            },
+            (2, area) => {
+                let (kind, area) = try!(ReqKind::decode(area));
+                Ok((ReqEcho::Kind(kind), area))
+            },
+            (tag, _) =>
+                Err(Error::InvalidTag(tag)),
+        }
     }
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl ToBin for ReqKind {
+    fn encode_len(&self) -> usize {
+        size_of::<u8>()
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
         match self {
-            &Error::Io(ref err) => write!(f, "I/O error {}", err),
-            &Error::Utf8(ref err) => write!(f, "utf8 related error: {}", err),
-            &Error::UnexpectedEOF => f.write_str("unexpected EOF"),
-            &Error::InvalidTag(tag) => write!(f, "invalid proto tag {}", tag),
+            &ReqKind::Init =>
+                put_adv!(area, u8, write_u8, 1),
+            &ReqKind::Lookup =>
+                put_adv!(area, u8, write_u8, 2),
+            &ReqKind::Terminate =>
+                put_adv!(area, u8, write_u8, 3),
+            &ReqKind::Snapshot =>
+                put_adv!(area, u8, write_u8, 4),
+            &ReqKind::Restore =>
+                put_adv!(area, u8, write_u8, 5),
+            &ReqKind::Ping =>
+                put_adv!(area, u8, write_u8, 6),
+            &ReqKind::CreateCollection =>
+                put_adv!(area, u8, write_u8, 7),
+            &ReqKind::ListCollections =>
+                put_adv!(area, u8, write_u8, 8),
+            &ReqKind::DropCollection =>
+                put_adv!(area, u8, write_u8, 9),
+            &ReqKind::LookupStream =>
+                put_adv!(area, u8, write_u8, 10),
+            &ReqKind::Cancel =>
+                put_adv!(area, u8, write_u8, 11),
+            &ReqKind::Subscribe =>
+                put_adv!(area, u8, write_u8, 12),
+            &ReqKind::FetchMore =>
+                put_adv!(area, u8, write_u8, 13),
+            &ReqKind::LookupBatch =>
+                put_adv!(area, u8, write_u8, 14),
+            &ReqKind::GetCluster =>
+                put_adv!(area, u8, write_u8, 15),
+            &ReqKind::MergeClusters =>
+                put_adv!(area, u8, write_u8, 16),
+            &ReqKind::MoveDocument =>
+                put_adv!(area, u8, write_u8, 17),
+            &ReqKind::UpdateUserData =>
+                put_adv!(area, u8, write_u8, 18),
         }
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::{ToBin, FromBin};
-    use super::super::{
-        Workload,
-        Trans, Req, LookupTask, PostAction, InsertCond, AssignCond, ClusterChoice, ClusterAssign, LookupType,
-        Rep, LookupResult, Match
-    };
-
-    fn encode_decode<T>(value: T) -> T where T: ToBin + FromBin {
-        let required = value.encode_len();
-        let mut packet: Vec<_> = (0 .. required).map(|_| 0).collect();
+impl FromBin for ReqKind {
+    fn decode<'a>(area: &'a [u8]) -> Result<(ReqKind, &'a [u8]), Error> {
+        match try_get!(area, u8, read_u8) {
+            (1, area) =>
+                Ok((ReqKind::Init, area)),
+            (2, area) =>
+                Ok((ReqKind::Lookup, area)),
+            (3, area) =>
+                Ok((ReqKind::Terminate, area)),
+            (4, area) =>
+                Ok((ReqKind::Snapshot, area)),
+            (5, area) =>
+                Ok((ReqKind::Restore, area)),
+            (6, area) =>
+                Ok((ReqKind::Ping, area)),
+            (7, area) =>
+                Ok((ReqKind::CreateCollection, area)),
+            (8, area) =>
+                Ok((ReqKind::ListCollections, area)),
+            (9, area) =>
+                Ok((ReqKind::DropCollection, area)),
+            (10, area) =>
+                Ok((ReqKind::LookupStream, area)),
+            (11, area) =>
+                Ok((ReqKind::Cancel, area)),
+            (12, area) =>
+                Ok((ReqKind::Subscribe, area)),
+            (13, area) =>
+                Ok((ReqKind::FetchMore, area)),
+            (14, area) =>
+                Ok((ReqKind::LookupBatch, area)),
+            (15, area) =>
+                Ok((ReqKind::GetCluster, area)),
+            (16, area) =>
+                Ok((ReqKind::MergeClusters, area)),
+            (17, area) =>
+                Ok((ReqKind::MoveDocument, area)),
+            (18, area) =>
+                Ok((ReqKind::UpdateUserData, area)),
+            (tag, _) =>
+                Err(Error::InvalidTag(tag)),
+        }
+    }
+}
+
+impl<UD> ToBin for LookupResult<UD> where UD: ToBin + Debug {
+    fn encode_len(&self) -> usize {
+        size_of::<u8>() + match self {
+            &LookupResult::EmptySet => 0,
+            &LookupResult::Best(ref m) if m.doc_id.is_none() => Legacy(m).encode_len(),
+            &LookupResult::Best(ref m) => m.encode_len(),
+            &LookupResult::Neighbours(ref workload) => match legacy_matches(workload) {
+                Some(ref legacy) => legacy.encode_len(),
+                None => workload.encode_len(),
+            },
+            &LookupResult::Error(ref e) if e.is_legacy() => e.message.encode_len(),
+            &LookupResult::Error(ref e) => e.encode_len(),
+            &LookupResult::WithOutcome(ref outcome, ref result) if !has_doc_id(outcome) => Legacy(outcome).encode_len() + result.encode_len(),
+            &LookupResult::WithOutcome(ref outcome, ref result) => outcome.encode_len() + result.encode_len(),
+            &LookupResult::NeighboursPage { ref matches, ref next, } if matches.iter().all(|m| m.doc_id.is_none()) =>
+                matches.iter().map(Legacy).collect::<Vec<_>>().encode_len() + next.encode_len(),
+            &LookupResult::NeighboursPage { ref matches, ref next, } => matches.encode_len() + next.encode_len(),
+        }
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        match self {
+            &LookupResult::EmptySet =>
+                put_adv!(area, u8, write_u8, 1),
+            &LookupResult::Best(ref m) if m.doc_id.is_none() => {
+                let area = put_adv!(area, u8, write_u8, 2);
+                Legacy(m).encode(area)
+            },
+            &LookupResult::Neighbours(ref workload) => match legacy_matches(workload) {
+                Some(ref legacy) => {
+                    let area = put_adv!(area, u8, write_u8, 3);
+                    legacy.encode(area)
+                },
+                None => {
+                    let area = put_adv!(area, u8, write_u8, 9);
+                    workload.encode(area)
+                },
+            },
+            &LookupResult::Error(ref e) if e.is_legacy() => {
+                let area = put_adv!(area, u8, write_u8, 4);
+                e.message.encode(area)
+            },
+            &LookupResult::WithOutcome(ref outcome, ref result) if !has_doc_id(outcome) => {
+                let area = put_adv!(area, u8, write_u8, 5);
+                let area = Legacy(outcome).encode(area);
+                result.encode(area)
+            },
+            &LookupResult::Error(ref e) => {
+                let area = put_adv!(area, u8, write_u8, 6);
+                e.encode(area)
+            },
+            &LookupResult::NeighboursPage { ref matches, ref next, } if matches.iter().all(|m| m.doc_id.is_none()) => {
+                let area = put_adv!(area, u8, write_u8, 7);
+                let area = matches.iter().map(Legacy).collect::<Vec<_>>().encode(area);
+                next.encode(area)
+            },
+            &LookupResult::Best(ref m) => {
+                let area = put_adv!(area, u8, write_u8, 8);
+                m.encode(area)
+            },
+            &LookupResult::WithOutcome(ref outcome, ref result) => {
+                let area = put_adv!(area, u8, write_u8, 10);
+                let area = outcome.encode(area);
+                result.encode(area)
+            },
+            &LookupResult::NeighboursPage { ref matches, ref next, } => {
+                let area = put_adv!(area, u8, write_u8, 11);
+                let area = matches.encode(area);
+                next.encode(area)
+            },
+        }
+    }
+}
+
+fn decode_with_outcome<'a, UD>(outcome: InsertOutcome, area: &'a [u8]) -> Result<(LookupResult<UD>, &'a [u8]), Error> where UD: FromBin + Debug {
+    match area.first() {
+        Some(&tag) if tag == 5 || tag == 10 =>
+            Err(Error::InvalidNesting(tag)),
+        _ => {
+            let (result, area) = try!(LookupResult::decode(area));
+            Ok((LookupResult::WithOutcome(outcome, Box::new(result)), area))
+        },
+    }
+}
+
+impl<UD> FromBin for LookupResult<UD> where UD: FromBin + Debug {
+    fn decode<'a>(area: &'a [u8]) -> Result<(LookupResult<UD>, &'a [u8]), Error> {
+        match try_get!(area, u8, read_u8) {
+            (1, area) =>
+                Ok((LookupResult::EmptySet, area)),
+            (2, area) => {
+                let (m, area) = try!(Legacy::<Match<UD>>::decode(area));
+                Ok((LookupResult::Best(m.unwrap()), area))
+            },
+            (3, area) => {
+                let (workload, area) = try!(Workload::decode(area));
+                Ok((LookupResult::Neighbours(from_legacy_matches(workload)), area))
+            },
+            (4, area) => {
+                let (message, area) = try!(String::decode(area));
+                Ok((LookupResult::Error(LookupError::other(message)), area))
+            },
+            (5, area) => {
+                let (outcome, area) = try!(Legacy::<InsertOutcome>::decode(area));
+                decode_with_outcome(outcome.unwrap(), area)
+            },
+            (6, area) => {
+                let (e, area) = try!(LookupError::decode(area));
+                Ok((LookupResult::Error(e), area))
+            },
+            (7, area) => {
+                let (matches, area) = try!(Vec::<Legacy<Match<UD>>>::decode(area));
+                let (next, area) = try!(Option::decode(area));
+                Ok((LookupResult::NeighboursPage { matches: matches.into_iter().map(Legacy::unwrap).collect(), next: next, }, area))
+            },
+            (8, area) => {
+                let (m, area) = try!(Match::decode(area));
+                Ok((LookupResult::Best(m), area))
+            },
+            (9, area) => {
+                let (workload, area) = try!(Workload::decode(area));
+                Ok((LookupResult::Neighbours(workload), area))
+            },
+            (10, area) => {
+                let (outcome, area) = try!(InsertOutcome::decode(area));
+                decode_with_outcome(outcome, area)
+            },
+            (11, area) => {
+                let (matches, area) = try!(Vec::decode(area));
+                let (next, area) = try!(Option::decode(area));
+                Ok((LookupResult::NeighboursPage { matches: matches, next: next, }, area))
+            },
+            (tag, _) =>
+                Err(Error::InvalidTag(tag)),
+        }
+    }
+}
+
+impl<UD> ToBin for ClusterInfo<UD> where UD: ToBin + Debug {
+    fn encode_len(&self) -> usize {
+        size_of::<u64>() + size_of::<u64>() + self.representative.encode_len() + self.members.encode_len()
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        let area = put_adv!(area, u64, write_u64, self.cluster_id);
+        let area = put_adv!(area, u64, write_u64, self.size);
+        let area = self.representative.encode(area);
+        self.members.encode(area)
+    }
+}
+
+impl<UD> FromBin for ClusterInfo<UD> where UD: FromBin + Debug {
+    fn decode<'a>(area: &'a [u8]) -> Result<(ClusterInfo<UD>, &'a [u8]), Error> {
+        let (cluster_id, area) = try_get!(area, u64, read_u64);
+        let (size, area) = try_get!(area, u64, read_u64);
+        let (representative, area) = try!(Option::decode(area));
+        let (members, area) = try!(Vec::decode(area));
+        Ok((ClusterInfo {
+            cluster_id: cluster_id,
+            size: size,
+            representative: representative,
+            members: members,
+        }, area))
+    }
+}
+
+impl<UD> ToBin for Member<UD> where UD: ToBin + Debug {
+    fn encode_len(&self) -> usize {
+        size_of::<u64>() + self.user_data.encode_len()
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        let area = put_adv!(area, u64, write_u64, self.doc_id);
+        self.user_data.encode(area)
+    }
+}
+
+impl<UD> FromBin for Member<UD> where UD: FromBin + Debug {
+    fn decode<'a>(area: &'a [u8]) -> Result<(Member<UD>, &'a [u8]), Error> {
+        let (doc_id, area) = try_get!(area, u64, read_u64);
+        let (user_data, area) = try!(UD::decode(area));
+        Ok((Member {
+            doc_id: doc_id,
+            user_data: user_data,
+        }, area))
+    }
+}
+
+impl ToBin for DocRef {
+    fn encode_len(&self) -> usize {
+        size_of::<u64>() + size_of::<u64>()
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        let area = put_adv!(area, u64, write_u64, self.cluster_id);
+        put_adv!(area, u64, write_u64, self.doc_id)
+    }
+}
+
+impl FromBin for DocRef {
+    fn decode<'a>(area: &'a [u8]) -> Result<(DocRef, &'a [u8]), Error> {
+        let (cluster_id, area) = try_get!(area, u64, read_u64);
+        let (doc_id, area) = try_get!(area, u64, read_u64);
+        Ok((DocRef { cluster_id: cluster_id, doc_id: doc_id, }, area))
+    }
+}
+
+impl ToBin for EventFilter {
+    fn encode_len(&self) -> usize {
+        self.kinds.encode_len() + self.cluster_ids.encode_len()
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        let area = self.kinds.encode(area);
+        self.cluster_ids.encode(area)
+    }
+}
+
+impl FromBin for EventFilter {
+    fn decode<'a>(area: &'a [u8]) -> Result<(EventFilter, &'a [u8]), Error> {
+        let (kinds, area) = try!(Vec::decode(area));
+        let (cluster_ids, area) = try!(Option::decode(area));
+        Ok((EventFilter {
+            kinds: kinds,
+            cluster_ids: cluster_ids,
+        }, area))
+    }
+}
+
+impl ToBin for EventKind {
+    fn encode_len(&self) -> usize {
+        size_of::<u8>()
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        match self {
+            &EventKind::DocumentInserted =>
+                put_adv!(area, u8, write_u8, 1),
+            &EventKind::ClusterCreated =>
+                put_adv!(area, u8, write_u8, 2),
+            &EventKind::ClusterDeleted =>
+                put_adv!(area, u8, write_u8, 3),
+            &EventKind::DocumentMoved =>
+                put_adv!(area, u8, write_u8, 4),
+        }
+    }
+}
+
+impl FromBin for EventKind {
+    fn decode<'a>(area: &'a [u8]) -> Result<(EventKind, &'a [u8]), Error> {
+        match try_get!(area, u8, read_u8) {
+            (1, area) =>
+                Ok((EventKind::DocumentInserted, area)),
+            (2, area) =>
+                Ok((EventKind::ClusterCreated, area)),
+            (3, area) =>
+                Ok((EventKind::ClusterDeleted, area)),
+            (4, area) =>
+                Ok((EventKind::DocumentMoved, area)),
+            (tag, _) =>
+                Err(Error::InvalidTag(tag)),
+        }
+    }
+}
+
+impl<UD> ToBin for Event<UD> where UD: ToBin + Debug {
+    fn encode_len(&self) -> usize {
+        size_of::<u8>() + size_of::<u64>() + match self {
+            &Event::DocumentInserted { doc_id: None, ref user_data, .. } | &Event::ClusterCreated { doc_id: None, ref user_data, .. } =>
+                user_data.encode_len(),
+            &Event::DocumentInserted { ref user_data, .. } | &Event::ClusterCreated { ref user_data, .. } =>
+                size_of::<u64>() + user_data.encode_len(),
+            &Event::ClusterDeleted { .. } => 0,
+            &Event::DocumentMoved { ref doc, .. } => doc.encode_len(),
+        }
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        match self {
+            &Event::DocumentInserted { cluster_id, doc_id: None, ref user_data, } => {
+                let area = put_adv!(area, u8, write_u8, 1);
+                let area = put_adv!(area, u64, write_u64, cluster_id);
+                user_data.encode(area)
+            },
+            &Event::ClusterCreated { cluster_id, doc_id: None, ref user_data, } => {
+                let area = put_adv!(area, u8, write_u8, 2);
+                let area = put_adv!(area, u64, write_u64, cluster_id);
+                user_data.encode(area)
+            },
+            &Event::ClusterDeleted { cluster_id, } => {
+                let area = put_adv!(area, u8, write_u8, 3);
+                put_adv!(area, u64, write_u64, cluster_id)
+            },
+            &Event::DocumentInserted { cluster_id, doc_id: Some(doc_id), ref user_data, } => {
+                let area = put_adv!(area, u8, write_u8, 4);
+                let area = put_adv!(area, u64, write_u64, cluster_id);
+                let area = put_adv!(area, u64, write_u64, doc_id);
+                user_data.encode(area)
+            },
+            &Event::ClusterCreated { cluster_id, doc_id: Some(doc_id), ref user_data, } => {
+                let area = put_adv!(area, u8, write_u8, 5);
+                let area = put_adv!(area, u64, write_u64, cluster_id);
+                let area = put_adv!(area, u64, write_u64, doc_id);
+                user_data.encode(area)
+            },
+            &Event::DocumentMoved { ref doc, from_cluster, } => {
+                let area = put_adv!(area, u8, write_u8, 6);
+                let area = doc.encode(area);
+                put_adv!(area, u64, write_u64, from_cluster)
+            },
+        }
+    }
+}
+
+impl<UD> FromBin for Event<UD> where UD: FromBin + Debug {
+    fn decode<'a>(area: &'a [u8]) -> Result<(Event<UD>, &'a [u8]), Error> {
+        match try_get!(area, u8, read_u8) {
+            (1, area) => {
+                let (cluster_id, area) = try_get!(area, u64, read_u64);
+                let (user_data, area) = try!(UD::decode(area));
+                Ok((Event::DocumentInserted { cluster_id: cluster_id, doc_id: None, user_data: user_data, }, area))
+            },
+            (2, area) => {
+                let (cluster_id, area) = try_get!(area, u64, read_u64);
+                let (user_data, area) = try!(UD::decode(area));
+                Ok((Event::ClusterCreated { cluster_id: cluster_id, doc_id: None, user_data: user_data, }, area))
+            },
+            (3, area) => {
+                let (cluster_id, area) = try_get!(area, u64, read_u64);
+                Ok((Event::ClusterDeleted { cluster_id: cluster_id, }, area))
+            },
+            (4, area) => {
+                let (cluster_id, area) = try_get!(area, u64, read_u64);
+                let (doc_id, area) = try_get!(area, u64, read_u64);
+                let (user_data, area) = try!(UD::decode(area));
+                Ok((Event::DocumentInserted { cluster_id: cluster_id, doc_id: Some(doc_id), user_data: user_data, }, area))
+            },
+            (5, area) => {
+                let (cluster_id, area) = try_get!(area, u64, read_u64);
+                let (doc_id, area) = try_get!(area, u64, read_u64);
+                let (user_data, area) = try!(UD::decode(area));
+                Ok((Event::ClusterCreated { cluster_id: cluster_id, doc_id: Some(doc_id), user_data: user_data, }, area))
+            },
+            (6, area) => {
+                let (doc, area) = try!(DocRef::decode(area));
+                let (from_cluster, area) = try_get!(area, u64, read_u64);
+                Ok((Event::DocumentMoved { doc: doc, from_cluster: from_cluster, }, area))
+            },
+            (tag, _) =>
+                Err(Error::InvalidTag(tag)),
+        }
+    }
+}
+
+impl ToBin for CancelState {
+    fn encode_len(&self) -> usize {
+        size_of::<u8>() + match self {
+            &CancelState::NotFound => 0,
+            &CancelState::Cancelled { .. } => size_of::<u32>(),
+        }
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        match self {
+            &CancelState::NotFound =>
+                put_adv!(area, u8, write_u8, 1),
+            &CancelState::Cancelled { completed, } => {
+                let area = put_adv!(area, u8, write_u8, 2);
+                put_adv!(area, u32, write_u32, completed)
+            },
+        }
+    }
+}
+
+impl FromBin for CancelState {
+    fn decode<'a>(area: &'a [u8]) -> Result<(CancelState, &'a [u8]), Error> {
+        match try_get!(area, u8, read_u8) {
+            (1, area) =>
+                Ok((CancelState::NotFound, area)),
+            (2, area) => {
+                let (completed, area) = try_get!(area, u32, read_u32);
+                Ok((CancelState::Cancelled { completed: completed, }, area))
+            },
+            (tag, _) =>
+                Err(Error::InvalidTag(tag)),
+        }
+    }
+}
+
+impl ToBin for LookupError {
+    fn encode_len(&self) -> usize {
+        self.code.encode_len() + size_of::<u8>() + self.message.encode_len()
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        let area = self.code.encode(area);
+        let area = put_adv!(area, u8, write_u8, if self.retryable { 1 } else { 0 });
+        self.message.encode(area)
+    }
+}
+
+impl FromBin for LookupError {
+    fn decode<'a>(area: &'a [u8]) -> Result<(LookupError, &'a [u8]), Error> {
+        let (code, area) = try!(ErrorCode::decode(area));
+        let (retryable, area) = match try_get!(area, u8, read_u8) {
+            (0, area) => (false, area),
+            (1, area) => (true, area),
+            (tag, _) => return Err(Error::InvalidTag(tag)),
+        };
+        let (message, area) = try!(String::decode(area));
+        Ok((LookupError {
+            code: code,
+            retryable: retryable,
+            message: message,
+        }, area))
+    }
+}
+
+impl ToBin for ErrorCode {
+    fn encode_len(&self) -> usize {
+        size_of::<u8>()
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        match self {
+            &ErrorCode::Other =>
+                put_adv!(area, u8, write_u8, 1),
+            &ErrorCode::DeadlineExceeded =>
+                put_adv!(area, u8, write_u8, 2),
+            &ErrorCode::Overloaded =>
+                put_adv!(area, u8, write_u8, 3),
+            &ErrorCode::TextTooLong =>
+                put_adv!(area, u8, write_u8, 4),
+            &ErrorCode::InvalidClusterChoice =>
+                put_adv!(area, u8, write_u8, 5),
+            &ErrorCode::UnsupportedDocument =>
+                put_adv!(area, u8, write_u8, 6),
+            &ErrorCode::InvalidCursor =>
+                put_adv!(area, u8, write_u8, 7),
+        }
+    }
+}
+
+impl FromBin for ErrorCode {
+    fn decode<'a>(area: &'a [u8]) -> Result<(ErrorCode, &'a [u8]), Error> {
+        match try_get!(area, u8, read_u8) {
+            (1, area) =>
+                Ok((ErrorCode::Other, area)),
+            (2, area) =>
+                Ok((ErrorCode::DeadlineExceeded, area)),
+            (3, area) =>
+                Ok((ErrorCode::Overloaded, area)),
+            (4, area) =>
+                Ok((ErrorCode::TextTooLong, area)),
+            (5, area) =>
+                Ok((ErrorCode::InvalidClusterChoice, area)),
+            (6, area) =>
+                Ok((ErrorCode::UnsupportedDocument, area)),
+            (7, area) =>
+                Ok((ErrorCode::InvalidCursor, area)),
+            (tag, _) =>
+                Err(Error::InvalidTag(tag)),
+        }
+    }
+}
+
+impl ToBin for BusyInfo {
+    fn encode_len(&self) -> usize {
+        self.retry_after_ms.encode_len() + self.queue_depth.encode_len() + self.reason.encode_len()
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        let area = self.retry_after_ms.encode(area);
+        let area = self.queue_depth.encode(area);
+        self.reason.encode(area)
+    }
+}
+
+impl FromBin for BusyInfo {
+    fn decode<'a>(area: &'a [u8]) -> Result<(BusyInfo, &'a [u8]), Error> {
+        let (retry_after_ms, area) = try!(Option::decode(area));
+        let (queue_depth, area) = try!(Option::decode(area));
+        let (reason, area) = try!(Option::decode(area));
+        Ok((BusyInfo {
+            retry_after_ms: retry_after_ms,
+            queue_depth: queue_depth,
+            reason: reason,
+        }, area))
+    }
+}
+
+impl ToBin for BusyReason {
+    fn encode_len(&self) -> usize {
+        size_of::<u8>()
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        match self {
+            &BusyReason::QueueFull =>
+                put_adv!(area, u8, write_u8, 1),
+            &BusyReason::MemoryPressure =>
+                put_adv!(area, u8, write_u8, 2),
+            &BusyReason::ShuttingDown =>
+                put_adv!(area, u8, write_u8, 3),
+        }
+    }
+}
+
+impl FromBin for BusyReason {
+    fn decode<'a>(area: &'a [u8]) -> Result<(BusyReason, &'a [u8]), Error> {
+        match try_get!(area, u8, read_u8) {
+            (1, area) =>
+                Ok((BusyReason::QueueFull, area)),
+            (2, area) =>
+                Ok((BusyReason::MemoryPressure, area)),
+            (3, area) =>
+                Ok((BusyReason::ShuttingDown, area)),
+            (tag, _) =>
+                Err(Error::InvalidTag(tag)),
+        }
+    }
+}
+
+impl ToBin for InsertOutcome {
+    fn encode_len(&self) -> usize {
+        size_of::<u8>() + match self {
+            &InsertOutcome::NotInserted => 0,
+            &InsertOutcome::InsertedIntoCluster { ref doc_id, .. } | &InsertOutcome::InsertedAsNewCluster { ref doc_id, .. } =>
+                size_of::<u64>() + doc_id.encode_len(),
+        }
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        match self {
+            &InsertOutcome::NotInserted =>
+                put_adv!(area, u8, write_u8, 1),
+            &InsertOutcome::InsertedIntoCluster { cluster_id, ref doc_id, } => {
+                let area = put_adv!(area, u8, write_u8, 2);
+                let area = put_adv!(area, u64, write_u64, cluster_id);
+                doc_id.encode(area)
+            },
+            &InsertOutcome::InsertedAsNewCluster { cluster_id, ref doc_id, } => {
+                let area = put_adv!(area, u8, write_u8, 3);
+                let area = put_adv!(area, u64, write_u64, cluster_id);
+                doc_id.encode(area)
+            },
+        }
+    }
+}
+
+impl FromBin for InsertOutcome {
+    fn decode<'a>(area: &'a [u8]) -> Result<(InsertOutcome, &'a [u8]), Error> {
+        match try_get!(area, u8, read_u8) {
+            (1, area) =>
+                Ok((InsertOutcome::NotInserted, area)),
+            (2, area) => {
+                let (cluster_id, area) = try_get!(area, u64, read_u64);
+                let (doc_id, area) = try!(Option::decode(area));
+                Ok((InsertOutcome::InsertedIntoCluster { cluster_id: cluster_id, doc_id: doc_id, }, area))
+            },
+            (3, area) => {
+                let (cluster_id, area) = try_get!(area, u64, read_u64);
+                let (doc_id, area) = try!(Option::decode(area));
+                Ok((InsertOutcome::InsertedAsNewCluster { cluster_id: cluster_id, doc_id: doc_id, }, area))
+            },
+            (tag, _) =>
+                Err(Error::InvalidTag(tag)),
+        }
+    }
+}
+
+impl<UD> ToBin for Match<UD> where UD: ToBin + Debug {
+    fn encode_len(&self) -> usize {
+        size_of::<u64>() + self.doc_id.encode_len() + size_of::<f64>() + self.user_data.encode_len()
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        let area = put_adv!(area, u64, write_u64, self.cluster_id);
+        let area = self.doc_id.encode(area);
+        let area = put_adv!(area, f64, write_f64, self.similarity);
+        let area = self.user_data.encode(area);
+        area
+    }
+}
+
+impl<UD> FromBin for Match<UD> where UD: FromBin + Debug {
+    fn decode<'a>(area: &'a [u8]) -> Result<(Match<UD>, &'a [u8]), Error> {
+        let (cluster_id, area) = try_get!(area, u64, read_u64);
+        let (doc_id, area) = try!(Option::decode(area));
+        let (similarity, area) = try_get!(area, f64, read_f64);
+        let (user_data, area) = try!(UD::decode(area));
+        Ok((Match {
+            cluster_id: cluster_id,
+            doc_id: doc_id,
+            similarity: similarity,
+            user_data: user_data,
+        }, area))
+    }
+}
+
+/// `Match` or `InsertOutcome` in the layout from before document ids were reported, still used under
+/// the old `LookupResult` tags when no id is known so that older peers keep decoding them.
+#[derive(Debug)]
+struct Legacy<T>(T);
+
+impl<T> Legacy<T> {
+    fn unwrap(self) -> T {
+        self.0
+    }
+}
+
+impl<'b> ToBin for Legacy<&'b InsertOutcome> {
+    fn encode_len(&self) -> usize {
+        size_of::<u8>() + match self.0 {
+            &InsertOutcome::NotInserted => 0,
+            &InsertOutcome::InsertedIntoCluster { .. } | &InsertOutcome::InsertedAsNewCluster { .. } => size_of::<u64>(),
+        }
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        match self.0 {
+            &InsertOutcome::NotInserted =>
+                put_adv!(area, u8, write_u8, 1),
+            &InsertOutcome::InsertedIntoCluster { cluster_id, .. } => {
+                let area = put_adv!(area, u8, write_u8, 2);
+                put_adv!(area, u64, write_u64, cluster_id)
+            },
+            &InsertOutcome::InsertedAsNewCluster { cluster_id, .. } => {
+                let area = put_adv!(area, u8, write_u8, 3);
+                put_adv!(area, u64, write_u64, cluster_id)
+            },
+        }
+    }
+}
+
+impl FromBin for Legacy<InsertOutcome> {
+    fn decode<'a>(area: &'a [u8]) -> Result<(Legacy<InsertOutcome>, &'a [u8]), Error> {
+        match try_get!(area, u8, read_u8) {
+            (1, area) =>
+                Ok((Legacy(InsertOutcome::NotInserted), area)),
+            (2, area) => {
+                let (cluster_id, area) = try_get!(area, u64, read_u64);
+                Ok((Legacy(InsertOutcome::InsertedIntoCluster { cluster_id: cluster_id, doc_id: None, }), area))
+            },
+            (3, area) => {
+                let (cluster_id, area) = try_get!(area, u64, read_u64);
+                Ok((Legacy(InsertOutcome::InsertedAsNewCluster { cluster_id: cluster_id, doc_id: None, }), area))
+            },
+            (tag, _) =>
+                Err(Error::InvalidTag(tag)),
+        }
+    }
+}
+
+impl<'b, UD> ToBin for Legacy<&'b Match<UD>> where UD: ToBin + Debug {
+    fn encode_len(&self) -> usize {
+        size_of::<u64>() + size_of::<f64>() + self.0.user_data.encode_len()
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        let area = put_adv!(area, u64, write_u64, self.0.cluster_id);
+        let area = put_adv!(area, f64, write_f64, self.0.similarity);
+        self.0.user_data.encode(area)
+    }
+}
+
+impl<UD> FromBin for Legacy<Match<UD>> where UD: FromBin + Debug {
+    fn decode<'a>(area: &'a [u8]) -> Result<(Legacy<Match<UD>>, &'a [u8]), Error> {
+        let (cluster_id, area) = try_get!(area, u64, read_u64);
+        let (similarity, area) = try_get!(area, f64, read_f64);
+        let (user_data, area) = try!(UD::decode(area));
+        Ok((Legacy(Match {
+            cluster_id: cluster_id,
+            doc_id: None,
+            similarity: similarity,
+            user_data: user_data,
+        }), area))
+    }
+}
+
+fn legacy_matches<'a, UD>(workload: &'a Workload<Match<UD>>) -> Option<Workload<Legacy<&'a Match<UD>>>> where UD: Debug {
+    match workload {
+        &Workload::Single(ref m) if m.doc_id.is_none() =>
+            Some(Workload::Single(Legacy(m))),
+        &Workload::Many(ref matches) if matches.iter().all(|m| m.doc_id.is_none()) =>
+            Some(Workload::Many(matches.iter().map(Legacy).collect())),
+        _ =>
+            None,
+    }
+}
+
+fn from_legacy_matches<UD>(workload: Workload<Legacy<Match<UD>>>) -> Workload<Match<UD>> where UD: Debug {
+    match workload {
+        Workload::Single(m) => Workload::Single(m.unwrap()),
+        Workload::Many(matches) => Workload::Many(matches.into_iter().map(Legacy::unwrap).collect()),
+    }
+}
+
+fn has_doc_id(outcome: &InsertOutcome) -> bool {
+    match outcome {
+        &InsertOutcome::InsertedIntoCluster { doc_id: Some(..), .. } | &InsertOutcome::InsertedAsNewCluster { doc_id: Some(..), .. } => true,
+        _ => false,
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::Io(ref err) => write!(f, "I/O error {}", err),
+            &Error::Utf8(ref err) => write!(f, "utf8 related error: {}", err),
+            &Error::UnexpectedEOF => f.write_str("unexpected EOF"),
+            &Error::InvalidTag(tag) => write!(f, "invalid proto tag {}", tag),
+            &Error::InvalidThreshold(value) => write!(f, "similarity threshold {} is out of [0, 1] range", value),
+            &Error::InvalidNesting(tag) => write!(f, "proto tag {} is not allowed at this nesting level", tag),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use byteorder::{ByteOrder, NativeEndian};
+    use super::{ToBin, FromBin, Error};
+    use super::super::signature;
+    use super::super::{
+        Workload,
+        Trans, Req, InitParams, Credentials, Batch, BatchTask, BatchInsert, LookupTask, Document, PostAction, InsertCond, AssignCond, Cond, ClusterChoice, ClusterAssign, LookupType,
+        Rep, ReqKind, ClusterInfo, Member, DocRef, CancelState, EventFilter, EventKind, Event, UnexpectedReason, ReqEcho, BusyInfo, BusyReason, LookupResult, LookupError, ErrorCode, InsertOutcome, Match, MAX_COND_DEPTH
+    };
+
+    fn encode_decode<T>(value: T) -> T where T: ToBin + FromBin {
+        let required = value.encode_len();
+        let mut packet: Vec<_> = (0 .. required).map(|_| 0).collect();
         {
             let area = value.encode(&mut packet);
             assert_eq!(area.len(), 0);
         }
-        let (decoded, area) = <T as FromBin>::decode(&packet).unwrap();
-        assert_eq!(area.len(), 0);
-        decoded
+        let (decoded, area) = <T as FromBin>::decode(&packet).unwrap();
+        assert_eq!(area.len(), 0);
+        decoded
+    }
+
+    fn encode_decode_req(req: Trans<String>) -> Trans<String> { encode_decode(req) }
+    fn encode_decode_rep(rep: Rep<String>) -> Rep<String> { encode_decode(rep) }
+
+    #[test]
+    fn req_00_async() {
+        match encode_decode_req(Trans::Async(Req::Init(InitParams::default()))) {
+            Trans::Async(Req::Init(InitParams { collection: None, credentials: None, })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_00_sync() {
+        match encode_decode_req(Trans::Sync(Req::Init(InitParams::default()))) {
+            Trans::Sync(Req::Init(InitParams { collection: None, credentials: None, })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_01() {
+        match encode_decode_req(Trans::Async(Req::Lookup(Workload::Single(LookupTask {
+            doc: Document::Text("hello world".to_owned()),
+            result: LookupType::All,
+            post_action: PostAction::None,
+        })))) {
+            Trans::Async(Req::Lookup(Workload::Single(LookupTask {
+                doc: Document::Text(ref lookup_text),
+                result: LookupType::All,
+                post_action: PostAction::None,
+            }))) if lookup_text == "hello world" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_02() {
+        match encode_decode_req(Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+            doc: Document::Text("hello world".to_owned()),
+            result: LookupType::BestOrMine,
+            post_action: PostAction::InsertNew {
+                cond: InsertCond::Always,
+                assign: ClusterAssign {
+                    cond: AssignCond::Always,
+                    choice: ClusterChoice::ServerChoice,
+                },
+                user_data: "some data".to_owned(),
+                idempotency_key: None,
+            },
+        })))) {
+            Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+                doc: Document::Text(ref lookup_text),
+                result: LookupType::BestOrMine,
+                post_action: PostAction::InsertNew {
+                    cond: InsertCond::Always,
+                    assign: ClusterAssign {
+                        cond: AssignCond::Always,
+                        choice: ClusterChoice::ServerChoice,
+                    },
+                    user_data: ref lookup_user_data,
+                    idempotency_key: None,
+                },
+            }))) if lookup_text == "hello world" && lookup_user_data == "some data" => (),
+            other => panic!("bad result: {:?}", other),
+        }
     }
 
-    fn encode_decode_req(req: Trans<String>) -> Trans<String> { encode_decode(req) }
-    fn encode_decode_rep(rep: Rep<String>) -> Rep<String> { encode_decode(rep) }
+    #[test]
+    fn req_03() {
+        match encode_decode_req(Trans::Async(Req::Lookup(Workload::Single(LookupTask {
+            doc: Document::Text("hello world".to_owned()),
+            result: LookupType::Best,
+            post_action: PostAction::InsertNew {
+                cond: InsertCond::BestSimLessThan(0.5),
+                assign: ClusterAssign {
+                    cond: AssignCond::Always,
+                    choice: ClusterChoice::ClientChoice(177),
+                },
+                user_data: "some data".to_owned(),
+                idempotency_key: None,
+            },
+        })))) {
+            Trans::Async(Req::Lookup(Workload::Single(LookupTask {
+                doc: Document::Text(ref lookup_text),
+                result: LookupType::Best,
+                post_action: PostAction::InsertNew {
+                    cond: InsertCond::BestSimLessThan(0.5),
+                    assign: ClusterAssign {
+                        cond: AssignCond::Always,
+                        choice: ClusterChoice::ClientChoice(177),
+                    },
+                    user_data: ref lookup_user_data,
+                    idempotency_key: None,
+                },
+            }))) if lookup_text == "hello world" && lookup_user_data == "some data" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
 
     #[test]
-    fn req_00_async() {
-        match encode_decode_req(Trans::Async(Req::Init)) {
-            Trans::Async(Req::Init) => (),
+    fn req_04() {
+        match encode_decode_req(Trans::Sync(Req::Terminate)) {
+            Trans::Sync(Req::Terminate) => (),
             other => panic!("bad result: {:?}", other),
         }
     }
 
     #[test]
-    fn req_00_sync() {
-        match encode_decode_req(Trans::Sync(Req::Init)) {
-            Trans::Sync(Req::Init) => (),
+    fn req_05() {
+        match encode_decode_req(Trans::Async(Req::Lookup(Workload::Many(vec![LookupTask {
+            doc: Document::Text("hello, world".to_owned()),
+            result: LookupType::All,
+            post_action: PostAction::None,
+        }, LookupTask {
+            doc: Document::Text("hello, cat".to_owned()),
+            result: LookupType::Best,
+            post_action: PostAction::None,
+        }, LookupTask {
+            doc: Document::Text("hello, dog".to_owned()),
+            result: LookupType::BestOrMine,
+            post_action: PostAction::None,
+        }])))) {
+            Trans::Async(Req::Lookup(Workload::Many(ref workloads))) => {
+                match workloads.get(0) {
+                    Some(&LookupTask { doc: Document::Text(ref t), result: LookupType::All, post_action: PostAction::None, }) if t == "hello, world" => (),
+                    other => panic!("bad workload 0: {:?}", other),
+                }
+                match workloads.get(1) {
+                    Some(&LookupTask { doc: Document::Text(ref t), result: LookupType::Best, post_action: PostAction::None, }) if t == "hello, cat" => (),
+                    other => panic!("bad workload 1: {:?}", other),
+                }
+                match workloads.get(2) {
+                    Some(&LookupTask { doc: Document::Text(ref t), result: LookupType::BestOrMine, post_action: PostAction::None, }) if t == "hello, dog" => (),
+                    other => panic!("bad workload 2: {:?}", other),
+                }
+            },
             other => panic!("bad result: {:?}", other),
         }
     }
 
     #[test]
-    fn req_01() {
-        match encode_decode_req(Trans::Async(Req::Lookup(Workload::Single(LookupTask {
-            text: "hello world".to_owned(),
+    fn req_06() {
+        match encode_decode_req(Trans::Sync(Req::Snapshot { path: "/var/lib/dupl/index.snap".to_owned(), })) {
+            Trans::Sync(Req::Snapshot { path: ref p, }) if p == "/var/lib/dupl/index.snap" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_07() {
+        match encode_decode_req(Trans::Sync(Req::Restore { path: "/var/lib/dupl/index.snap".to_owned(), })) {
+            Trans::Sync(Req::Restore { path: ref p, }) if p == "/var/lib/dupl/index.snap" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_08() {
+        match encode_decode_req(Trans::Async(Req::Ping(0xdeadbeef))) {
+            Trans::Async(Req::Ping(0xdeadbeef)) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_09() {
+        let trans = encode_decode_req(Trans::Tagged(42, Box::new(Trans::Async(Req::Lookup(Workload::Single(LookupTask {
+            doc: Document::Text("hello world".to_owned()),
             result: LookupType::All,
             post_action: PostAction::None,
+        }))))));
+        assert_eq!(trans.id(), Some(42));
+        match trans {
+            Trans::Tagged(42, ref trans) => match **trans {
+                Trans::Async(Req::Lookup(Workload::Single(LookupTask { doc: Document::Text(ref t), result: LookupType::All, post_action: PostAction::None, })))
+                    if t == "hello world" => (),
+                ref other => panic!("bad inner result: {:?}", other),
+            },
+            ref other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_10() {
+        let packet = [2u8, 3u8];
+        match <Trans<String> as FromBin>::decode(&packet) {
+            Ok((ref trans @ Trans::Sync(Req::Terminate), _)) if trans.id().is_none() => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_11() {
+        match encode_decode_req(Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+            doc: Document::Text("hello world".to_owned()),
+            result: LookupType::TopK(10),
+            post_action: PostAction::None,
         })))) {
-            Trans::Async(Req::Lookup(Workload::Single(LookupTask {
-                text: ref lookup_text,
-                result: LookupType::All,
+            Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+                doc: Document::Text(ref lookup_text),
+                result: LookupType::TopK(10),
                 post_action: PostAction::None,
             }))) if lookup_text == "hello world" => (),
             other => panic!("bad result: {:?}", other),
@@ -696,101 +2557,402 @@ mod test {
     }
 
     #[test]
-    fn req_02() {
-        match encode_decode_req(Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
-            text: "hello world".to_owned(),
-            result: LookupType::BestOrMine,
-            post_action: PostAction::InsertNew {
-                cond: InsertCond::Always,
-                assign: ClusterAssign {
-                    cond: AssignCond::Always,
-                    choice: ClusterChoice::ServerChoice,
-                },
-                user_data: "some data".to_owned(),
-            },
-        })))) {
-            Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
-                text: ref lookup_text,
-                result: LookupType::BestOrMine,
-                post_action: PostAction::InsertNew {
-                    cond: InsertCond::Always,
-                    assign: ClusterAssign {
-                        cond: AssignCond::Always,
-                        choice: ClusterChoice::ServerChoice,
-                    },
-                    user_data: ref lookup_user_data,
-                },
-            }))) if lookup_text == "hello world" && lookup_user_data == "some data" => (),
+    fn req_12() {
+        match encode_decode::<LookupType>(LookupType::AboveThreshold(0.75)) {
+            LookupType::AboveThreshold(0.75) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode::<LookupType>(LookupType::TopKAboveThreshold(5, 1.0)) {
+            LookupType::TopKAboveThreshold(5, 1.0) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_13() {
+        let value = LookupType::AboveThreshold(1.5);
+        let mut packet: Vec<_> = (0 .. value.encode_len()).map(|_| 0).collect();
+        value.encode(&mut packet);
+        match <LookupType as FromBin>::decode(&packet) {
+            Err(Error::InvalidThreshold(v)) if v == 1.5 => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_14() {
+        match encode_decode::<InsertCond>(Cond::And(vec![
+            Cond::BestSimLessThan(0.9),
+            Cond::TextLenGreaterThan(200),
+            Cond::Or(vec![Cond::NoNeighbourInCluster(177), Cond::BestSimGreaterThan(0.1)]),
+            Cond::Not(Box::new(Cond::Never)),
+        ])) {
+            Cond::And(ref conds) => {
+                match conds.get(0) { Some(&Cond::BestSimLessThan(0.9)) => (), other => panic!("bad cond 0: {:?}", other), }
+                match conds.get(1) { Some(&Cond::TextLenGreaterThan(200)) => (), other => panic!("bad cond 1: {:?}", other), }
+                match conds.get(2) {
+                    Some(&Cond::Or(ref conds)) => match (conds.get(0), conds.get(1)) {
+                        (Some(&Cond::NoNeighbourInCluster(177)), Some(&Cond::BestSimGreaterThan(0.1))) => (),
+                        other => panic!("bad cond 2: {:?}", other),
+                    },
+                    other => panic!("bad cond 2: {:?}", other),
+                }
+                match conds.get(3) {
+                    Some(&Cond::Not(ref cond)) => match **cond { Cond::Never => (), ref other => panic!("bad cond 3: {:?}", other), },
+                    other => panic!("bad cond 3: {:?}", other),
+                }
+            },
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_15() {
+        match encode_decode::<ClusterAssign>(ClusterAssign { cond: Cond::BestSimGreaterThan(0.7), choice: ClusterChoice::BestMatchCluster, }) {
+            ClusterAssign { cond: Cond::BestSimGreaterThan(0.7), choice: ClusterChoice::BestMatchCluster, } => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode::<ClusterChoice>(ClusterChoice::NewCluster) {
+            ClusterChoice::NewCluster => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_16() {
+        match encode_decode_req(Trans::Sync(Req::Init(InitParams { collection: Some("news".to_owned()), credentials: None, }))) {
+            Trans::Sync(Req::Init(InitParams { collection: Some(ref c), credentials: None, })) if c == "news" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        let mut packet = [0u8; 2];
+        Trans::Sync::<String>(Req::Init(InitParams::default())).encode(&mut packet);
+        assert_eq!(packet, [2, 1]);
+    }
+
+    #[test]
+    fn req_17() {
+        match encode_decode_req(Trans::Sync(Req::CreateCollection("news".to_owned()))) {
+            Trans::Sync(Req::CreateCollection(ref c)) if c == "news" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_req(Trans::Sync(Req::ListCollections)) {
+            Trans::Sync(Req::ListCollections) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_req(Trans::Sync(Req::DropCollection("news".to_owned()))) {
+            Trans::Sync(Req::DropCollection(ref c)) if c == "news" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_18() {
+        match encode_decode_req(Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+            doc: Document::Bytes(vec![0xff, 0x00, 0xfe, 0x80]),
+            result: LookupType::Best,
+            post_action: PostAction::None,
+        })))) {
+            Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+                doc: Document::Bytes(ref bytes),
+                result: LookupType::Best,
+                post_action: PostAction::None,
+            }))) if *bytes == [0xff, 0x00, 0xfe, 0x80] => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_19() {
+        let signature = signature::minhash("hello, world", 2, 16);
+        match encode_decode_req(Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+            doc: Document::Signature(signature.clone()),
+            result: LookupType::Best,
+            post_action: PostAction::None,
+        })))) {
+            Trans::Sync(Req::Lookup(Workload::Single(LookupTask { doc: Document::Signature(ref s), .. }))) if *s == signature => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        let signature = signature::shingles("hello, world", 1);
+        match encode_decode::<Document>(Document::Signature(signature.clone())) {
+            Document::Signature(ref s) if *s == signature => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_20() {
+        let trans = encode_decode_req(Trans::Tagged(42, Box::new(Trans::Deadline(1500, Box::new(Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+            doc: Document::Text("hello world".to_owned()),
+            result: LookupType::Best,
+            post_action: PostAction::None,
+        }))))))));
+        assert_eq!(trans.id(), Some(42));
+        assert_eq!(trans.deadline_ms(), Some(1500));
+        match trans.req() {
+            &Req::Lookup(Workload::Single(LookupTask { doc: Document::Text(ref t), .. })) if t == "hello world" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_21() {
+        let task = |text: &str| LookupTask {
+            doc: Document::Text(text.to_owned()),
+            result: LookupType::Best,
+            post_action: PostAction::None,
+        };
+        match encode_decode_req(Trans::Sync(Req::LookupStream { tasks: vec![task("first"), task("second")], chunk_size: 100, })) {
+            Trans::Sync(Req::LookupStream { ref tasks, chunk_size: 100, }) => {
+                assert_eq!(tasks.len(), 2);
+                assert_eq!(tasks[1].doc, Document::Text("second".to_owned()));
+            },
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_22() {
+        match encode_decode_req(Trans::Async(Req::Cancel(42))) {
+            Trans::Async(Req::Cancel(42)) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_23() {
+        let params = InitParams {
+            collection: Some("news".to_owned()),
+            credentials: Some(Credentials::Hmac { key_id: "client-1".to_owned(), nonce: vec![1, 2, 3], mac: vec![0xff, 0x00], }),
+        };
+        match encode_decode_req(Trans::Sync(Req::Init(params))) {
+            Trans::Sync(Req::Init(InitParams { collection: Some(ref c), credentials: Some(Credentials::Hmac { ref key_id, ref nonce, ref mac, }), }))
+                if c == "news" && key_id == "client-1" && *nonce == [1, 2, 3] && *mac == [0xff, 0x00] => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_req(Trans::Sync(Req::Init(InitParams { collection: None, credentials: Some(Credentials::Token("secret".to_owned())), }))) {
+            Trans::Sync(Req::Init(InitParams { collection: None, credentials: Some(Credentials::Token(ref t)), })) if t == "secret" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        // collection only params keep the tag 7 layout
+        let init = Trans::Sync::<String>(Req::Init(InitParams { collection: Some("news".to_owned()), credentials: None, }));
+        let mut packet = vec![0u8; init.encode_len()];
+        init.encode(&mut packet);
+        assert_eq!(&packet[.. 2], &[2, 7]);
+    }
+
+    #[test]
+    fn req_24() {
+        let filter = EventFilter { kinds: vec![EventKind::ClusterCreated, EventKind::ClusterDeleted], cluster_ids: Some(vec![1, 2]), };
+        match encode_decode_req(Trans::Async(Req::Subscribe(filter))) {
+            Trans::Async(Req::Subscribe(EventFilter { ref kinds, cluster_ids: Some(ref cluster_ids), }))
+                if *kinds == [EventKind::ClusterCreated, EventKind::ClusterDeleted] && *cluster_ids == [1, 2] => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_25() {
+        match encode_decode_req(Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+            doc: Document::Text("hello world".to_owned()),
+            result: LookupType::Best,
+            post_action: PostAction::InsertNew {
+                cond: InsertCond::Always,
+                assign: ClusterAssign { cond: AssignCond::Always, choice: ClusterChoice::ServerChoice, },
+                user_data: "some data".to_owned(),
+                idempotency_key: Some("upload-17".to_owned()),
+            },
+        })))) {
+            Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+                post_action: PostAction::InsertNew { idempotency_key: Some(ref key), ref user_data, .. },
+                ..
+            }))) if key == "upload-17" && user_data == "some data" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_26() {
+        match encode_decode_req(Trans::Sync(Req::FetchMore("page-2".to_owned()))) {
+            Trans::Sync(Req::FetchMore(ref cursor)) if cursor == "page-2" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_27() {
+        let batch = Batch {
+            result: LookupType::TopK(5),
+            insert_cond: InsertCond::BestSimLessThan(0.5),
+            assign: ClusterAssign { cond: AssignCond::Always, choice: ClusterChoice::BestMatchCluster, },
+            tasks: vec![BatchTask {
+                doc: Document::Text("first".to_owned()),
+                result: None,
+                insert: Some(BatchInsert { user_data: "doc 1".to_owned(), idempotency_key: None, cond: None, assign: None, }),
+            }, BatchTask {
+                doc: Document::Text("second".to_owned()),
+                result: Some(LookupType::Best),
+                insert: None,
+            }],
+        };
+        match encode_decode_req(Trans::Sync(Req::LookupBatch(batch))) {
+            Trans::Sync(Req::LookupBatch(batch)) => {
+                let tasks = batch.expand();
+                match (&tasks[0].result, &tasks[0].post_action) {
+                    (&LookupType::TopK(5), &PostAction::InsertNew { cond: Cond::BestSimLessThan(..), ref user_data, .. }) if user_data == "doc 1" => (),
+                    other => panic!("bad task: {:?}", other),
+                }
+                match (&tasks[1].result, &tasks[1].post_action) {
+                    (&LookupType::Best, &PostAction::None) => (),
+                    other => panic!("bad task: {:?}", other),
+                }
+            },
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_28() {
+        match encode_decode_req(Trans::Sync(Req::GetCluster { cluster_id: 177, offset: 100, limit: Some(50), })) {
+            Trans::Sync(Req::GetCluster { cluster_id: 177, offset: 100, limit: Some(50), }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_29() {
+        match encode_decode_req(Trans::Sync(Req::MergeClusters { from: 177, into: 12, })) {
+            Trans::Sync(Req::MergeClusters { from: 177, into: 12, }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        let doc = DocRef { cluster_id: 177, doc_id: 5, };
+        match encode_decode_req(Trans::Sync(Req::MoveDocument { doc: doc, to_cluster: Some(12), })) {
+            Trans::Sync(Req::MoveDocument { doc: DocRef { cluster_id: 177, doc_id: 5, }, to_cluster: Some(12), }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_req(Trans::Sync(Req::MoveDocument { doc: doc, to_cluster: None, })) {
+            Trans::Sync(Req::MoveDocument { doc: DocRef { cluster_id: 177, doc_id: 5, }, to_cluster: None, }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_30() {
+        let doc = DocRef { cluster_id: 177, doc_id: 5, };
+        match encode_decode_req(Trans::Sync(Req::UpdateUserData { doc: doc, user_data: "new url".to_owned(), })) {
+            Trans::Sync(Req::UpdateUserData { doc: DocRef { cluster_id: 177, doc_id: 5, }, ref user_data, }) =>
+                assert_eq!(user_data, "new url"),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_31() {
+        let nested = Trans::Tagged(1, Box::new(Trans::Tagged(2, Box::new(Trans::Sync::<String>(Req::Terminate)))));
+        let mut packet = vec![0u8; nested.encode_len()];
+        nested.encode(&mut packet);
+        match <Trans<String> as FromBin>::decode(&packet) {
+            Err(Error::InvalidNesting(3)) => (),
             other => panic!("bad result: {:?}", other),
         }
+        let mut packet = Vec::new();
+        for _ in 0 .. 500000 {
+            packet.extend_from_slice(&[3, 0, 0, 0, 0, 0, 0, 0, 0]);
+        }
+        packet.extend_from_slice(&[2, 3]);
+        assert!(<Trans<String> as FromBin>::decode(&packet).is_err());
     }
 
     #[test]
-    fn req_03() {
-        match encode_decode_req(Trans::Async(Req::Lookup(Workload::Single(LookupTask {
-            text: "hello world".to_owned(),
-            result: LookupType::Best,
-            post_action: PostAction::InsertNew {
-                cond: InsertCond::BestSimLessThan(0.5),
-                assign: ClusterAssign {
-                    cond: AssignCond::Always,
-                    choice: ClusterChoice::ClientChoice(177),
-                },
-                user_data: "some data".to_owned(),
-            },
-        })))) {
-            Trans::Async(Req::Lookup(Workload::Single(LookupTask {
-                text: ref lookup_text,
-                result: LookupType::Best,
-                post_action: PostAction::InsertNew {
-                    cond: InsertCond::BestSimLessThan(0.5),
-                    assign: ClusterAssign {
-                        cond: AssignCond::Always,
-                        choice: ClusterChoice::ClientChoice(177),
-                    },
-                    user_data: ref lookup_user_data,
-                },
-            }))) if lookup_text == "hello world" && lookup_user_data == "some data" => (),
+    fn req_32() {
+        let mut packet = vec![9u8; 2000000];
+        packet.push(1);
+        match <Cond as FromBin>::decode(&packet) {
+            Err(Error::InvalidNesting(9)) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        let nested = (0 .. MAX_COND_DEPTH + 1).fold(Cond::Always, |cond, _| Cond::And(vec![Cond::Never, cond]));
+        let mut packet = vec![0u8; nested.encode_len()];
+        nested.encode(&mut packet);
+        match <Cond as FromBin>::decode(&packet) {
+            Err(Error::InvalidNesting(7)) => (),
             other => panic!("bad result: {:?}", other),
         }
+        let mut packet = vec![9u8; MAX_COND_DEPTH];
+        packet.push(1);
+        assert!(<Cond as FromBin>::decode(&packet).is_ok());
     }
 
     #[test]
-    fn req_04() {
-        match encode_decode_req(Trans::Sync(Req::Terminate)) {
-            Trans::Sync(Req::Terminate) => (),
+    fn req_33() {
+        let terminate = || Box::new(Trans::Sync::<String>(Req::Terminate));
+        let decode = |trans: Trans<String>| {
+            let mut packet = vec![0u8; trans.encode_len()];
+            trans.encode(&mut packet);
+            <Trans<String> as FromBin>::decode(&packet).map(|(trans, _)| trans)
+        };
+        match decode(Trans::Deadline(1500, Box::new(Trans::Tagged(42, terminate())))) {
+            Err(Error::InvalidNesting(3)) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match decode(Trans::Deadline(1500, Box::new(Trans::Deadline(500, terminate())))) {
+            Err(Error::InvalidNesting(4)) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match decode(Trans::Tagged(42, Box::new(Trans::Deadline(1500, Box::new(Trans::Tagged(43, terminate())))))) {
+            Err(Error::InvalidNesting(3)) => (),
             other => panic!("bad result: {:?}", other),
         }
+        let mut packet = Vec::new();
+        for i in 0 .. 500000 {
+            packet.push(if i % 2 == 0 { 3 } else { 4 });
+            packet.extend_from_slice(&[0; 8]);
+        }
+        packet.extend_from_slice(&[2, 3]);
+        assert!(<Trans<String> as FromBin>::decode(&packet).is_err());
     }
 
     #[test]
-    fn req_05() {
-        match encode_decode_req(Trans::Async(Req::Lookup(Workload::Many(vec![LookupTask {
-            text: "hello, world".to_owned(),
+    fn req_34() {
+        let mut packet = vec![1, 2, 1];
+        let mut len = [0; 4];
+        NativeEndian::write_u32(&mut len, 11);
+        packet.extend_from_slice(&len);
+        packet.extend_from_slice(b"hello world");
+        packet.extend_from_slice(&[1, 1]);
+        let (trans, area) = <Trans<String> as FromBin>::decode(&packet).unwrap();
+        assert_eq!(area.len(), 0);
+        match trans {
+            Trans::Async(Req::Lookup(Workload::Single(LookupTask {
+                doc: Document::Text(ref text),
+                result: LookupType::All,
+                post_action: PostAction::None,
+            }))) if text == "hello world" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        let text_req = Trans::Async(Req::<String>::Lookup(Workload::Single(LookupTask {
+            doc: Document::Text("hello world".to_owned()),
             result: LookupType::All,
             post_action: PostAction::None,
-        }, LookupTask {
-            text: "hello, cat".to_owned(),
-            result: LookupType::Best,
+        })));
+        let mut encoded = vec![0; text_req.encode_len()];
+        text_req.encode(&mut encoded);
+        assert_eq!(encoded, packet);
+        let bytes_req = Trans::Async(Req::<String>::Lookup(Workload::Many(vec![LookupTask {
+            doc: Document::Text("hello".to_owned()),
+            result: LookupType::All,
             post_action: PostAction::None,
         }, LookupTask {
-            text: "hello, dog".to_owned(),
-            result: LookupType::BestOrMine,
+            doc: Document::Bytes(vec![0, 159, 146, 150]),
+            result: LookupType::All,
             post_action: PostAction::None,
-        }])))) {
-            Trans::Async(Req::Lookup(Workload::Many(ref workloads))) => {
-                match workloads.get(0) {
-                    Some(&LookupTask { text: ref t, result: LookupType::All, post_action: PostAction::None, }) if t == "hello, world" => (),
-                    other => panic!("bad workload 0: {:?}", other),
-                }
-                match workloads.get(1) {
-                    Some(&LookupTask { text: ref t, result: LookupType::Best, post_action: PostAction::None, }) if t == "hello, cat" => (),
-                    other => panic!("bad workload 1: {:?}", other),
-                }
-                match workloads.get(2) {
-                    Some(&LookupTask { text: ref t, result: LookupType::BestOrMine, post_action: PostAction::None, }) if t == "hello, dog" => (),
-                    other => panic!("bad workload 2: {:?}", other),
-                }
+        }])));
+        let mut encoded = vec![0; bytes_req.encode_len()];
+        bytes_req.encode(&mut encoded);
+        assert_eq!(encoded[1], 21);
+        match encode_decode_req(bytes_req) {
+            Trans::Async(Req::Lookup(Workload::Many(ref tasks))) => match (&tasks[0].doc, &tasks[1].doc) {
+                (&Document::Text(ref text), &Document::Bytes(ref bytes)) if text == "hello" && bytes == &[0, 159, 146, 150] => (),
+                other => panic!("bad result: {:?}", other),
             },
             other => panic!("bad result: {:?}", other),
         }
@@ -814,8 +2976,8 @@ mod test {
 
     #[test]
     fn rep_02() {
-        match encode_decode_rep(Rep::TooBusy) {
-            Rep::TooBusy => (),
+        match encode_decode_rep(Rep::TooBusy(BusyInfo::default())) {
+            Rep::TooBusy(ref info) if info.is_default() => (),
             other => panic!("bad result: {:?}", other),
         }
     }
@@ -840,11 +3002,13 @@ mod test {
     fn rep_05() {
         match encode_decode_rep(Rep::Result(Workload::Single(LookupResult::Best(Match {
             cluster_id: 177,
+            doc_id: Some(5),
             similarity: 0.5,
             user_data: "some data".to_owned(),
         })))) {
             Rep::Result(Workload::Single(LookupResult::Best(Match {
                 cluster_id: 177,
+                doc_id: Some(5),
                 similarity: 0.5,
                 user_data: ref match_user_data,
             }))) if match_user_data == "some data" => (),
@@ -856,15 +3020,404 @@ mod test {
     fn rep_f64() {
         match encode_decode::<Rep<f64>>(Rep::Result(Workload::Single(LookupResult::Best(Match {
             cluster_id: 177,
+            doc_id: Some(5),
             similarity: 0.5,
             user_data: 0.1,
         })))) {
             Rep::Result(Workload::Single(LookupResult::Best(Match {
                 cluster_id: 177,
+                doc_id: Some(5),
                 similarity: 0.5,
                 user_data: 0.1,
             }))) => (),
             other => panic!("bad result: {:?}", other),
         }
     }
+
+    #[test]
+    fn rep_06() {
+        match encode_decode_rep(Rep::SnapshotAck) {
+            Rep::SnapshotAck => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::RestoreAck) {
+            Rep::RestoreAck => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_07() {
+        match encode_decode_rep(Rep::SnapshotError("permission denied".to_owned())) {
+            Rep::SnapshotError(ref e) if e == "permission denied" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_08() {
+        match encode_decode_rep(Rep::Pong(0xdeadbeef)) {
+            Rep::Pong(0xdeadbeef) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_09() {
+        match encode_decode_rep(Rep::reply_to(Some(42), Rep::Result(Workload::Single(LookupResult::EmptySet)))) {
+            Rep::Tagged(42, ref rep) => match **rep {
+                Rep::Result(Workload::Single(LookupResult::EmptySet)) => (),
+                ref other => panic!("bad inner result: {:?}", other),
+            },
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_10() {
+        match encode_decode_rep(Rep::Result(Workload::Many(vec![
+            LookupResult::WithOutcome(InsertOutcome::NotInserted, Box::new(LookupResult::Best(Match {
+                cluster_id: 177,
+                doc_id: Some(5),
+                similarity: 0.95,
+                user_data: "some data".to_owned(),
+            }))),
+            LookupResult::WithOutcome(InsertOutcome::InsertedIntoCluster { cluster_id: 177, doc_id: Some(5), }, Box::new(LookupResult::EmptySet)),
+            LookupResult::WithOutcome(InsertOutcome::InsertedAsNewCluster { cluster_id: 178, doc_id: Some(6), }, Box::new(LookupResult::EmptySet)),
+        ]))) {
+            Rep::Result(Workload::Many(ref results)) => {
+                match results.get(0) {
+                    Some(&LookupResult::WithOutcome(InsertOutcome::NotInserted, ref result)) => match **result {
+                        LookupResult::Best(Match { cluster_id: 177, .. }) => (),
+                        ref other => panic!("bad result 0: {:?}", other),
+                    },
+                    other => panic!("bad result 0: {:?}", other),
+                }
+                match results.get(1).and_then(|r| r.insert_outcome()) {
+                    Some(&InsertOutcome::InsertedIntoCluster { cluster_id: 177, doc_id: Some(5), }) => (),
+                    other => panic!("bad result 1: {:?}", other),
+                }
+                match results.get(2).and_then(|r| r.insert_outcome()) {
+                    Some(&InsertOutcome::InsertedAsNewCluster { cluster_id: 178, doc_id: Some(6), }) => (),
+                    other => panic!("bad result 2: {:?}", other),
+                }
+            },
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_11() {
+        match encode_decode_rep(Rep::Collections(vec!["news".to_owned(), "blogs".to_owned()])) {
+            Rep::Collections(ref names) if *names == ["news".to_owned(), "blogs".to_owned()] => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::UnknownCollection("forums".to_owned())) {
+            Rep::UnknownCollection(ref name) if name == "forums" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::CollectionExists("news".to_owned())) {
+            Rep::CollectionExists(ref name) if name == "news" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::CreateCollectionAck) {
+            Rep::CreateCollectionAck => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::DropCollectionAck) {
+            Rep::DropCollectionAck => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_12() {
+        match encode_decode_rep(Rep::reply_to(Some(42), Rep::DeadlineExceeded)) {
+            Rep::Tagged(42, ref rep) => match **rep {
+                Rep::DeadlineExceeded => (),
+                ref other => panic!("bad inner result: {:?}", other),
+            },
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_13() {
+        let info = BusyInfo { retry_after_ms: Some(250), queue_depth: Some(1024), reason: Some(BusyReason::MemoryPressure), };
+        match encode_decode_rep(Rep::TooBusy(info)) {
+            Rep::TooBusy(BusyInfo { retry_after_ms: Some(250), queue_depth: Some(1024), reason: Some(BusyReason::MemoryPressure), }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        let mut packet = [0u8; 1];
+        Rep::TooBusy::<String>(BusyInfo::default()).encode(&mut packet);
+        assert_eq!(packet, [5]);
+    }
+
+    #[test]
+    fn rep_14() {
+        let error = LookupError::new(ErrorCode::TextTooLong, "text exceeds 1 MiB".to_owned());
+        match encode_decode_rep(Rep::Result(Workload::Single(LookupResult::Error(error)))) {
+            Rep::Result(Workload::Single(LookupResult::Error(LookupError { code: ErrorCode::TextTooLong, retryable: false, ref message, })))
+                if message == "text exceeds 1 MiB" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::Result(Workload::Single(LookupResult::Error(LookupError::new(ErrorCode::Overloaded, String::new()))))) {
+            Rep::Result(Workload::Single(LookupResult::Error(LookupError { code: ErrorCode::Overloaded, retryable: true, .. }))) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        // legacy string form: tag 4 followed by the message
+        let legacy = LookupResult::Error::<String>(LookupError::other("oops".to_owned()));
+        let mut packet = vec![0u8; legacy.encode_len()];
+        legacy.encode(&mut packet);
+        assert_eq!(packet[0], 4);
+        match encode_decode(legacy) {
+            LookupResult::Error(ref e) if e.is_legacy() && e.message == "oops" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_15() {
+        match encode_decode_rep(Rep::Unexpected { reason: UnexpectedReason::NotInitialized, req: ReqEcho::Kind(ReqKind::Lookup), }) {
+            Rep::Unexpected { reason: UnexpectedReason::NotInitialized, req: ReqEcho::Kind(ReqKind::Lookup), } => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::Unexpected { reason: UnexpectedReason::AlreadyInitialized, req: ReqEcho::Full(Req::Init(InitParams::default())), }) {
+            Rep::Unexpected { reason: UnexpectedReason::AlreadyInitialized, req: ReqEcho::Full(Req::Init(..)), } => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        let legacy = Rep::Unexpected::<String> { reason: UnexpectedReason::Unspecified, req: ReqEcho::Full(Req::Terminate), };
+        let mut packet = [0u8; 2];
+        legacy.encode(&mut packet);
+        assert_eq!(packet, [4, 3]);
+        match encode_decode_rep(legacy) {
+            Rep::Unexpected { reason: UnexpectedReason::Unspecified, ref req, } => assert_eq!(req.kind(), ReqKind::Terminate),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_16() {
+        match encode_decode_rep(Rep::ResultChunk { start: 100, results: vec![LookupResult::EmptySet, LookupResult::Best(Match {
+            cluster_id: 7,
+            doc_id: Some(5),
+            similarity: 0.5,
+            user_data: "hello".to_owned(),
+        })], }) {
+            Rep::ResultChunk { start: 100, ref results, } => match results.as_slice() {
+                &[LookupResult::EmptySet, LookupResult::Best(Match { cluster_id: 7, .. })] => (),
+                other => panic!("bad results: {:?}", other),
+            },
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::EndOfStream(102)) {
+            Rep::EndOfStream(102) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_17() {
+        match encode_decode_rep(Rep::Cancelled(42, CancelState::Cancelled { completed: 17, })) {
+            Rep::Cancelled(42, CancelState::Cancelled { completed: 17, }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::Cancelled(43, CancelState::NotFound)) {
+            Rep::Cancelled(43, CancelState::NotFound) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_18() {
+        match encode_decode_rep(Rep::AuthFailed("unknown token".to_owned())) {
+            Rep::AuthFailed(ref reason) if reason == "unknown token" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_19() {
+        match encode_decode_rep(Rep::Event(Event::DocumentInserted { cluster_id: 7, doc_id: Some(5), user_data: "doc 1".to_owned(), })) {
+            Rep::Event(Event::DocumentInserted { cluster_id: 7, doc_id: Some(5), ref user_data, }) if user_data == "doc 1" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::Event(Event::ClusterCreated { cluster_id: 7, doc_id: None, user_data: "doc 1".to_owned(), })) {
+            Rep::Event(Event::ClusterCreated { cluster_id: 7, doc_id: None, ref user_data, }) if user_data == "doc 1" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::Event(Event::DocumentMoved { doc: DocRef { cluster_id: 9, doc_id: 5, }, from_cluster: 7, })) {
+            Rep::Event(Event::DocumentMoved { doc: DocRef { cluster_id: 9, doc_id: 5, }, from_cluster: 7, }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        let mut packet = vec![1];
+        let mut word = [0; 8];
+        NativeEndian::write_u64(&mut word, 7);
+        packet.extend_from_slice(&word);
+        packet.extend_from_slice(&[0; 4]);
+        match <Event<String> as FromBin>::decode(&packet) {
+            Ok((Event::DocumentInserted { cluster_id: 7, doc_id: None, ref user_data, }, _)) if user_data.is_empty() => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::Event(Event::ClusterDeleted { cluster_id: 8, })) {
+            Rep::Event(Event::ClusterDeleted { cluster_id: 8, }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::SubscribeAck) {
+            Rep::SubscribeAck => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_20() {
+        let page = LookupResult::NeighboursPage {
+            matches: vec![Match { cluster_id: 1, doc_id: Some(5), similarity: 0.9, user_data: "doc 1".to_owned(), }],
+            next: Some("page-2".to_owned()),
+        };
+        match encode_decode_rep(Rep::Result(Workload::Single(page))) {
+            Rep::Result(Workload::Single(ref result @ LookupResult::NeighboursPage { .. })) => {
+                assert_eq!(result.next_cursor().map(|c| &c[..]), Some("page-2"));
+            },
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::Result(Workload::Single(LookupResult::NeighboursPage { matches: vec![], next: None, }))) {
+            Rep::Result(Workload::Single(LookupResult::NeighboursPage { ref matches, next: None, })) if matches.is_empty() => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_21() {
+        let info = ClusterInfo {
+            cluster_id: 177,
+            size: 3,
+            representative: Some(Document::Text("hello world".to_owned())),
+            members: vec![Member { doc_id: 1, user_data: "doc 1".to_owned(), }, Member { doc_id: 5, user_data: "doc 5".to_owned(), }],
+        };
+        match encode_decode_rep(Rep::Cluster(info)) {
+            Rep::Cluster(ClusterInfo { cluster_id: 177, size: 3, representative: Some(Document::Text(ref text)), ref members, }) => {
+                assert_eq!(text, "hello world");
+                assert_eq!(members.iter().map(|m| m.doc_id).collect::<Vec<_>>(), vec![1, 5]);
+                assert_eq!(members[1].user_data, "doc 5");
+            },
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::ClusterNotFound(178)) {
+            Rep::ClusterNotFound(178) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_22() {
+        let doc = DocRef { cluster_id: 177, doc_id: 5, };
+        match encode_decode_rep(Rep::ClustersMerged { from: 177, into: 12, }) {
+            Rep::ClustersMerged { from: 177, into: 12, } => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::DocumentMoved { doc: doc, to_cluster: 178, }) {
+            Rep::DocumentMoved { doc: DocRef { cluster_id: 177, doc_id: 5, }, to_cluster: 178, } => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::DocumentNotFound(doc)) {
+            Rep::DocumentNotFound(DocRef { cluster_id: 177, doc_id: 5, }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_23() {
+        match encode_decode_rep(Rep::UserDataUpdated(DocRef { cluster_id: 177, doc_id: 5, })) {
+            Rep::UserDataUpdated(DocRef { cluster_id: 177, doc_id: 5, }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_24() {
+        let nested = Rep::Tagged(1, Box::new(Rep::Tagged(2, Box::new(Rep::InitAck::<String>))));
+        let mut packet = vec![0u8; nested.encode_len()];
+        nested.encode(&mut packet);
+        match <Rep<String> as FromBin>::decode(&packet) {
+            Err(Error::InvalidNesting(11)) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_25() {
+        let decode = |doc_id| {
+            let nested = LookupResult::WithOutcome(InsertOutcome::NotInserted, Box::new(
+                LookupResult::WithOutcome(InsertOutcome::InsertedAsNewCluster { cluster_id: 1, doc_id: doc_id, }, Box::new(LookupResult::EmptySet::<String>))));
+            let mut packet = vec![0u8; nested.encode_len()];
+            nested.encode(&mut packet);
+            <LookupResult<String> as FromBin>::decode(&packet).map(|(result, _)| result)
+        };
+        match decode(Some(1)) {
+            Err(Error::InvalidNesting(10)) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match decode(None) {
+            Err(Error::InvalidNesting(5)) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_26() {
+        match encode_decode_rep(Rep::AuthChallenge(vec![7, 0, 255])) {
+            Rep::AuthChallenge(ref nonce) if *nonce == [7, 0, 255] => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_27() {
+        let mut packet = vec![2];
+        let mut word = [0; 8];
+        NativeEndian::write_u64(&mut word, 177);
+        packet.extend_from_slice(&word);
+        NativeEndian::write_f64(&mut word, 0.5);
+        packet.extend_from_slice(&word);
+        let mut len = [0; 4];
+        NativeEndian::write_u32(&mut len, 1);
+        packet.extend_from_slice(&len);
+        packet.push(b'x');
+        match <LookupResult<String> as FromBin>::decode(&packet) {
+            Ok((LookupResult::Best(Match { cluster_id: 177, doc_id: None, similarity, ref user_data, }), area)) =>
+                assert!(similarity == 0.5 && user_data == "x" && area.is_empty()),
+            other => panic!("bad result: {:?}", other),
+        }
+        let legacy = LookupResult::Best(Match { cluster_id: 177, doc_id: None, similarity: 0.5, user_data: "x".to_owned(), });
+        let mut encoded = vec![0; legacy.encode_len()];
+        legacy.encode(&mut encoded);
+        assert_eq!(encoded, packet);
+
+        let mut packet = vec![5, 3];
+        NativeEndian::write_u64(&mut word, 178);
+        packet.extend_from_slice(&word);
+        packet.push(1);
+        match <LookupResult<String> as FromBin>::decode(&packet) {
+            Ok((LookupResult::WithOutcome(InsertOutcome::InsertedAsNewCluster { cluster_id: 178, doc_id: None, }, ref result), _)) =>
+                match **result {
+                    LookupResult::EmptySet => (),
+                    ref other => panic!("bad result: {:?}", other),
+                },
+            other => panic!("bad result: {:?}", other),
+        }
+
+        let neighbours = LookupResult::Neighbours(Workload::Many(vec![
+            Match { cluster_id: 1, doc_id: None, similarity: 0.5, user_data: "a".to_owned(), },
+            Match { cluster_id: 2, doc_id: Some(7), similarity: 0.4, user_data: "b".to_owned(), },
+        ]));
+        let mut encoded = vec![0; neighbours.encode_len()];
+        neighbours.encode(&mut encoded);
+        assert_eq!(encoded[0], 9);
+        match <LookupResult<String> as FromBin>::decode(&encoded) {
+            Ok((LookupResult::Neighbours(Workload::Many(ref matches)), _)) =>
+                assert_eq!(matches.iter().map(|m| m.doc_id).collect::<Vec<_>>(), vec![None, Some(7)]),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
 }
@@ -0,0 +1,153 @@
+//! Evaluation of `InsertCond` / `AssignCond` expressions.
+//!
+//! Servers evaluate a `Cond` against the neighbours found for the task document. The similarity
+//! of the best match is taken as `0.0` when no neighbours were found, so
+//! `BestSimLessThan(x)` holds and `BestSimGreaterThan(x)` fails for an empty neighbour set.
+//! An empty `And` is true and an empty `Or` is false.
+//!
+//! `TextLenGreaterThan` needs the document length, which a `Document::Signature` does not carry.
+//! A task with a signature document and a condition for which `Cond::uses_text_len` holds is
+//! answered with `ErrorCode::UnsupportedDocument` rather than evaluated.
+//!
+//! `ClusterAssign::resolve` turns the `AssignCond` / `ClusterChoice` pair of an insert into
+//! the cluster the document should go to, so that all servers agree on its meaning.
+
+use std::fmt::Debug;
+use super::{Cond, ClusterAssign, ClusterChoice, Match};
+
+#[derive(Debug)]
+pub struct Context<'a, UD> where UD: 'a + Debug {
+    /// Length of the task document in bytes, see `Document::text_len`.
+    pub text_len: usize,
+    /// Neighbours found for the task document, in any order.
+    pub neighbours: &'a [Match<UD>],
+}
+
+impl<'a, UD> Context<'a, UD> where UD: Debug {
+    pub fn best_match(&self) -> Option<&'a Match<UD>> {
+        self.neighbours.iter().fold(None, |best: Option<&'a Match<UD>>, m| match best {
+            Some(b) if b.similarity >= m.similarity => Some(b),
+            _ => Some(m),
+        })
+    }
+
+    pub fn best_similarity(&self) -> f64 {
+        self.best_match().map(|m| m.similarity).unwrap_or(0.0)
+    }
+}
+
+/// Where a document inserted with `PostAction::InsertNew` should go.
+#[derive(Debug, PartialEq)]
+pub enum Assignment {
+    /// Add the document to the existing cluster with this id.
+    Cluster(u64),
+    /// Start a new cluster with a server allocated id.
+    NewCluster,
+    /// Let the server apply its own clustering policy.
+    ServerDefault,
+}
+
+impl ClusterAssign {
+    pub fn resolve<UD>(&self, ctx: &Context<UD>) -> Assignment where UD: Debug {
+        if !self.cond.eval(ctx) {
+            return Assignment::ServerDefault;
+        }
+        match self.choice {
+            ClusterChoice::ServerChoice => Assignment::ServerDefault,
+            ClusterChoice::ClientChoice(cluster_id) => Assignment::Cluster(cluster_id),
+            ClusterChoice::BestMatchCluster => match ctx.best_match() {
+                Some(m) => Assignment::Cluster(m.cluster_id),
+                None => Assignment::NewCluster,
+            },
+            ClusterChoice::NewCluster => Assignment::NewCluster,
+        }
+    }
+}
+
+impl Cond {
+    pub fn eval<UD>(&self, ctx: &Context<UD>) -> bool where UD: Debug {
+        match self {
+            &Cond::Always => true,
+            &Cond::Never => false,
+            &Cond::BestSimLessThan(sim) => ctx.best_similarity() < sim,
+            &Cond::BestSimGreaterThan(sim) => ctx.best_similarity() > sim,
+            &Cond::TextLenGreaterThan(len) => ctx.text_len as u64 > len,
+            &Cond::NoNeighbourInCluster(cluster_id) => ctx.neighbours.iter().all(|m| m.cluster_id != cluster_id),
+            &Cond::And(ref conds) => conds.iter().all(|c| c.eval(ctx)),
+            &Cond::Or(ref conds) => conds.iter().any(|c| c.eval(ctx)),
+            &Cond::Not(ref cond) => !cond.eval(ctx),
+        }
+    }
+
+    /// Whether the condition depends on the document length, anywhere in the expression.
+    pub fn uses_text_len(&self) -> bool {
+        match self {
+            &Cond::TextLenGreaterThan(..) => true,
+            &Cond::And(ref conds) | &Cond::Or(ref conds) => conds.iter().any(|c| c.uses_text_len()),
+            &Cond::Not(ref cond) => cond.uses_text_len(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Context, Assignment};
+    use super::super::{Cond, ClusterAssign, ClusterChoice, Match};
+
+    fn m(cluster_id: u64, similarity: f64) -> Match<()> {
+        Match { cluster_id: cluster_id, doc_id: None, similarity: similarity, user_data: (), }
+    }
+
+    #[test]
+    fn simple() {
+        let neighbours = [m(1, 0.4), m(2, 0.8)];
+        let ctx = Context { text_len: 300, neighbours: &neighbours, };
+        assert!(Cond::Always.eval(&ctx));
+        assert!(!Cond::Never.eval(&ctx));
+        assert!(Cond::BestSimLessThan(0.9).eval(&ctx));
+        assert!(!Cond::BestSimLessThan(0.8).eval(&ctx));
+        assert!(Cond::BestSimGreaterThan(0.5).eval(&ctx));
+        assert!(Cond::TextLenGreaterThan(200).eval(&ctx));
+        assert!(!Cond::TextLenGreaterThan(300).eval(&ctx));
+        assert!(Cond::NoNeighbourInCluster(3).eval(&ctx));
+        assert!(!Cond::NoNeighbourInCluster(2).eval(&ctx));
+    }
+
+    #[test]
+    fn empty_neighbours() {
+        let ctx: Context<()> = Context { text_len: 0, neighbours: &[], };
+        assert!(Cond::BestSimLessThan(0.1).eval(&ctx));
+        assert!(!Cond::BestSimGreaterThan(0.0).eval(&ctx));
+        assert!(Cond::And(vec![]).eval(&ctx));
+        assert!(!Cond::Or(vec![]).eval(&ctx));
+    }
+
+    #[test]
+    fn combinators() {
+        let neighbours = [m(1, 0.5)];
+        let ctx = Context { text_len: 250, neighbours: &neighbours, };
+        let cond = Cond::And(vec![Cond::BestSimLessThan(0.9), Cond::TextLenGreaterThan(200)]);
+        assert!(cond.eval(&ctx));
+        assert!(!Cond::Not(Box::new(cond)).eval(&ctx));
+        assert!(Cond::Or(vec![Cond::Never, Cond::NoNeighbourInCluster(2)]).eval(&ctx));
+        assert!(Cond::Not(Box::new(Cond::Or(vec![Cond::Never, Cond::TextLenGreaterThan(200)]))).uses_text_len());
+        assert!(!Cond::Or(vec![Cond::Never, Cond::NoNeighbourInCluster(2)]).uses_text_len());
+        assert!(!Cond::Or(vec![Cond::Never, Cond::NoNeighbourInCluster(1)]).eval(&ctx));
+    }
+
+    #[test]
+    fn resolve() {
+        let neighbours = [m(1, 0.4), m(2, 0.8)];
+        let ctx = Context { text_len: 100, neighbours: &neighbours, };
+        let assign = |cond, choice| ClusterAssign { cond: cond, choice: choice, };
+        assert_eq!(assign(Cond::Always, ClusterChoice::ServerChoice).resolve(&ctx), Assignment::ServerDefault);
+        assert_eq!(assign(Cond::Always, ClusterChoice::ClientChoice(177)).resolve(&ctx), Assignment::Cluster(177));
+        assert_eq!(assign(Cond::Always, ClusterChoice::BestMatchCluster).resolve(&ctx), Assignment::Cluster(2));
+        assert_eq!(assign(Cond::Always, ClusterChoice::NewCluster).resolve(&ctx), Assignment::NewCluster);
+        assert_eq!(assign(Cond::BestSimLessThan(0.5), ClusterChoice::BestMatchCluster).resolve(&ctx), Assignment::ServerDefault);
+
+        let ctx: Context<()> = Context { text_len: 100, neighbours: &[], };
+        assert_eq!(assign(Cond::Always, ClusterChoice::BestMatchCluster).resolve(&ctx), Assignment::NewCluster);
+    }
+}
@@ -1,9 +1,10 @@
 use std::fmt::Debug;
 use rustc_serialize::json::{Json, Object, ToJson};
+use rustc_serialize::base64::{ToBase64, FromBase64, STANDARD};
 use super::{
     Workload,
-    Trans, Req, LookupTask, PostAction, InsertCond, ClusterAssign, AssignCond, ClusterChoice, LookupType,
-    Rep, LookupResult, Match
+    Trans, Req, ReqKind, ClusterInfo, Member, DocRef, Batch, BatchTask, BatchInsert, CancelState, InitParams, Credentials, EventFilter, EventKind, Event, UnexpectedReason, ReqEcho, BusyInfo, BusyReason, LookupError, ErrorCode, LookupTask, Document, Signature, PostAction, ClusterAssign, Cond, ClusterChoice, LookupType,
+    Rep, LookupResult, InsertOutcome, Match, MAX_COND_DEPTH
 };
 
 pub fn req_to_json<UD>(trans: &Trans<UD>) -> Json where UD: Debug + ToJson { trans.to_json() }
@@ -38,18 +39,20 @@ impl ToJson for LookupType {
             LookupType::All => Json::String("all".to_string()),
             LookupType::Best => Json::String("best".to_string()),
             LookupType::BestOrMine => Json::String("best_or_mine".to_string()),
-        }
-    }
-}
-
-impl ToJson for AssignCond {
-    fn to_json(&self) -> Json {
-        match *self {
-            AssignCond::Always =>
-                Json::String("always".to_string()),
-            AssignCond::BestSimLessThan(sim) => {
+            LookupType::TopK(k) => {
+                let mut o = Object::new();
+                o.insert("top_k".to_string(), k.to_json());
+                Json::Object(o)
+            },
+            LookupType::AboveThreshold(threshold) => {
+                let mut o = Object::new();
+                o.insert("above_threshold".to_string(), threshold.to_json());
+                Json::Object(o)
+            },
+            LookupType::TopKAboveThreshold(k, threshold) => {
                 let mut o = Object::new();
-                o.insert("best_sim_less_than".to_string(), sim.to_json());
+                o.insert("top_k".to_string(), k.to_json());
+                o.insert("above_threshold".to_string(), threshold.to_json());
                 Json::Object(o)
             },
         }
@@ -61,6 +64,10 @@ impl ToJson for ClusterChoice {
         match *self {
             ClusterChoice::ServerChoice =>
                 Json::String("server_choice".to_string()),
+            ClusterChoice::BestMatchCluster =>
+                Json::String("best_match_cluster".to_string()),
+            ClusterChoice::NewCluster =>
+                Json::String("new_cluster".to_string()),
             ClusterChoice::ClientChoice(cluster_id) => {
                 let mut o = Object::new();
                 o.insert("client_choice".to_string(), cluster_id.to_json());
@@ -79,17 +86,31 @@ impl ToJson for ClusterAssign {
     }
 }
 
-impl ToJson for InsertCond {
+impl ToJson for Cond {
     fn to_json(&self) -> Json {
-        match *self {
-            InsertCond::Always =>
-                Json::String("always".to_string()),
-            InsertCond::BestSimLessThan(sim) => {
-                let mut o = Object::new();
-                o.insert("best_sim_less_than".to_string(), sim.to_json());
-                Json::Object(o)
-            },
-        }
+        let (key, value) = match *self {
+            Cond::Always =>
+                return Json::String("always".to_string()),
+            Cond::Never =>
+                return Json::String("never".to_string()),
+            Cond::BestSimLessThan(sim) =>
+                ("best_sim_less_than", sim.to_json()),
+            Cond::BestSimGreaterThan(sim) =>
+                ("best_sim_greater_than", sim.to_json()),
+            Cond::TextLenGreaterThan(len) =>
+                ("text_len_greater_than", len.to_json()),
+            Cond::NoNeighbourInCluster(cluster_id) =>
+                ("no_neighbour_in_cluster", cluster_id.to_json()),
+            Cond::And(ref conds) =>
+                ("and", conds.to_json()),
+            Cond::Or(ref conds) =>
+                ("or", conds.to_json()),
+            Cond::Not(ref cond) =>
+                ("not", cond.to_json()),
+        };
+        let mut o = Object::new();
+        o.insert(key.to_string(), value);
+        Json::Object(o)
     }
 }
 
@@ -98,27 +119,100 @@ impl<UD> ToJson for PostAction<UD> where UD: Debug + ToJson {
         match self {
             &PostAction::None =>
                 Json::String("none".to_string()),
-            &PostAction::InsertNew { cond: ref cond_value, assign: ref assign_value, user_data: ref user_data_value } => {
+            &PostAction::InsertNew { cond: ref cond_value, assign: ref assign_value, user_data: ref user_data_value, idempotency_key: ref key_value } => {
                 let mut o = Object::new();
                 o.insert("cond".to_string(), cond_value.to_json());
                 o.insert("assign".to_string(), assign_value.to_json());
                 o.insert("user_data".to_string(), user_data_value.to_json());
+                if let &Some(ref key) = key_value {
+                    o.insert("idempotency_key".to_string(), key.to_json());
+                }
                 Json::Object(o)
             },
         }
     }
 }
 
+fn doc_entry(doc: &Document) -> (&'static str, Json) {
+    match doc {
+        &Document::Text(ref text) => ("text", text.to_json()),
+        &Document::Bytes(ref bytes) => ("bytes", Json::String(bytes.to_base64(STANDARD))),
+        &Document::Signature(Signature::MinHash { shingle_len: l, hashes: ref h, }) => ("minhash", signature_to_json(l, h)),
+        &Document::Signature(Signature::Shingles { shingle_len: l, hashes: ref h, }) => ("shingles", signature_to_json(l, h)),
+    }
+}
+
+/// Hashes are sent as decimal strings, JSON numbers lose precision above 2^53 in most clients.
+fn signature_to_json(shingle_len: u32, hashes: &[u64]) -> Json {
+    let mut o = Object::new();
+    o.insert("shingle_len".to_string(), shingle_len.to_json());
+    o.insert("hashes".to_string(), Json::Array(hashes.iter().map(|h| Json::String(h.to_string())).collect()));
+    Json::Object(o)
+}
+
+impl ToJson for Document {
+    fn to_json(&self) -> Json {
+        let (key, doc) = doc_entry(self);
+        let mut o = Object::new();
+        o.insert(key.to_string(), doc);
+        Json::Object(o)
+    }
+}
+
 impl<UD> ToJson for LookupTask<UD> where UD: Debug + ToJson {
     fn to_json(&self) -> Json {
         let mut o = Object::new();
-        o.insert("text".to_string(), self.text.to_json());
+        let (key, doc) = doc_entry(&self.doc);
+        o.insert(key.to_string(), doc);
         o.insert("result".to_string(), self.result.to_json());
         o.insert("post_action".to_string(), self.post_action.to_json());
         Json::Object(o)
     }
 }
 
+impl<UD> ToJson for Batch<UD> where UD: Debug + ToJson {
+    fn to_json(&self) -> Json {
+        let mut o = Object::new();
+        o.insert("result".to_string(), self.result.to_json());
+        o.insert("insert_cond".to_string(), self.insert_cond.to_json());
+        o.insert("assign".to_string(), self.assign.to_json());
+        o.insert("tasks".to_string(), self.tasks.to_json());
+        Json::Object(o)
+    }
+}
+
+impl<UD> ToJson for BatchTask<UD> where UD: Debug + ToJson {
+    fn to_json(&self) -> Json {
+        let mut o = Object::new();
+        let (key, doc) = doc_entry(&self.doc);
+        o.insert(key.to_string(), doc);
+        if let Some(ref result) = self.result {
+            o.insert("result".to_string(), result.to_json());
+        }
+        if let Some(ref insert) = self.insert {
+            o.insert("insert".to_string(), insert.to_json());
+        }
+        Json::Object(o)
+    }
+}
+
+impl<UD> ToJson for BatchInsert<UD> where UD: Debug + ToJson {
+    fn to_json(&self) -> Json {
+        let mut o = Object::new();
+        o.insert("user_data".to_string(), self.user_data.to_json());
+        if let Some(ref key) = self.idempotency_key {
+            o.insert("idempotency_key".to_string(), key.to_json());
+        }
+        if let Some(ref cond) = self.cond {
+            o.insert("cond".to_string(), cond.to_json());
+        }
+        if let Some(ref assign) = self.assign {
+            o.insert("assign".to_string(), assign.to_json());
+        }
+        Json::Object(o)
+    }
+}
+
 impl<T> ToJson for Workload<T> where T: Debug + ToJson {
     fn to_json(&self) -> Json {
         match self {
@@ -128,11 +222,48 @@ impl<T> ToJson for Workload<T> where T: Debug + ToJson {
     }
 }
 
+impl ToJson for InitParams {
+    fn to_json(&self) -> Json {
+        let mut o = Object::new();
+        if let Some(ref collection) = self.collection {
+            o.insert("collection".to_string(), collection.to_json());
+        }
+        if let Some(ref credentials) = self.credentials {
+            o.insert("credentials".to_string(), credentials.to_json());
+        }
+        Json::Object(o)
+    }
+}
+
+impl ToJson for Credentials {
+    fn to_json(&self) -> Json {
+        let mut o = Object::new();
+        match self {
+            &Credentials::Token(ref token) => {
+                o.insert("token".to_string(), token.to_json());
+            },
+            &Credentials::Hmac { ref key_id, ref nonce, ref mac, } => {
+                let mut h = Object::new();
+                h.insert("key_id".to_string(), key_id.to_json());
+                h.insert("nonce".to_string(), Json::String(nonce.to_base64(STANDARD)));
+                h.insert("mac".to_string(), Json::String(mac.to_base64(STANDARD)));
+                o.insert("hmac".to_string(), Json::Object(h));
+            },
+        }
+        Json::Object(o)
+    }
+}
+
 impl<UD> ToJson for Req<UD> where UD: Debug + ToJson {
     fn to_json(&self) -> Json {
         match self {
-            &Req::Init =>
+            &Req::Init(ref params) if params.is_default() =>
                 Json::String("init".to_string()),
+            &Req::Init(ref params) => {
+                let mut o = Object::new();
+                o.insert("init".to_string(), params.to_json());
+                Json::Object(o)
+            },
             &Req::Lookup(ref workload) => {
                 let mut o = Object::new();
                 o.insert("lookup".to_string(), workload.to_json());
@@ -140,6 +271,98 @@ impl<UD> ToJson for Req<UD> where UD: Debug + ToJson {
             },
             &Req::Terminate =>
                 Json::String("terminate".to_string()),
+            &Req::Snapshot { path: ref p, } => {
+                let mut o = Object::new();
+                o.insert("snapshot".to_string(), p.to_json());
+                Json::Object(o)
+            },
+            &Req::Restore { path: ref p, } => {
+                let mut o = Object::new();
+                o.insert("restore".to_string(), p.to_json());
+                Json::Object(o)
+            },
+            &Req::Ping(nonce) => {
+                let mut o = Object::new();
+                o.insert("ping".to_string(), nonce.to_json());
+                Json::Object(o)
+            },
+            &Req::CreateCollection(ref name) => {
+                let mut o = Object::new();
+                o.insert("create_collection".to_string(), name.to_json());
+                Json::Object(o)
+            },
+            &Req::ListCollections =>
+                Json::String("list_collections".to_string()),
+            &Req::DropCollection(ref name) => {
+                let mut o = Object::new();
+                o.insert("drop_collection".to_string(), name.to_json());
+                Json::Object(o)
+            },
+            &Req::LookupStream { ref tasks, chunk_size, } => {
+                let mut l = Object::new();
+                l.insert("tasks".to_string(), tasks.to_json());
+                l.insert("chunk_size".to_string(), chunk_size.to_json());
+                let mut o = Object::new();
+                o.insert("lookup_stream".to_string(), Json::Object(l));
+                Json::Object(o)
+            },
+            &Req::Cancel(id) => {
+                let mut o = Object::new();
+                o.insert("cancel".to_string(), id.to_json());
+                Json::Object(o)
+            },
+            &Req::Subscribe(ref filter) => {
+                let mut o = Object::new();
+                o.insert("subscribe".to_string(), filter.to_json());
+                Json::Object(o)
+            },
+            &Req::FetchMore(ref cursor) => {
+                let mut o = Object::new();
+                o.insert("fetch_more".to_string(), cursor.to_json());
+                Json::Object(o)
+            },
+            &Req::LookupBatch(ref batch) => {
+                let mut o = Object::new();
+                o.insert("lookup_batch".to_string(), batch.to_json());
+                Json::Object(o)
+            },
+            &Req::GetCluster { cluster_id, offset, ref limit, } => {
+                let mut g = Object::new();
+                g.insert("cluster_id".to_string(), cluster_id.to_json());
+                g.insert("offset".to_string(), offset.to_json());
+                if let &Some(limit) = limit {
+                    g.insert("limit".to_string(), limit.to_json());
+                }
+                let mut o = Object::new();
+                o.insert("get_cluster".to_string(), Json::Object(g));
+                Json::Object(o)
+            },
+            &Req::MergeClusters { from, into, } => {
+                let mut m = Object::new();
+                m.insert("from".to_string(), from.to_json());
+                m.insert("into".to_string(), into.to_json());
+                let mut o = Object::new();
+                o.insert("merge_clusters".to_string(), Json::Object(m));
+                Json::Object(o)
+            },
+            &Req::MoveDocument { ref doc, ref to_cluster, } => {
+                let mut m = Object::new();
+                m.insert("doc".to_string(), doc.to_json());
+                if let &Some(to_cluster) = to_cluster {
+                    m.insert("to_cluster".to_string(), to_cluster.to_json());
+                }
+                let mut o = Object::new();
+                o.insert("move_document".to_string(), Json::Object(m));
+                Json::Object(o)
+            },
+            &Req::UpdateUserData { ref doc, ref user_data, } => {
+                let mut u = Object::new();
+                u.insert("doc".to_string(), doc.to_json());
+                u.insert("user_data".to_string(), user_data.to_json());
+                let mut o = Object::new();
+                o.insert("update_user_data".to_string(), Json::Object(u));
+                Json::Object(o)
+            },
         }
     }
 }
@@ -157,6 +380,22 @@ impl<UD> ToJson for Trans<UD> where UD: Debug + ToJson {
                 o.insert("sync".to_string(), req.to_json());
                 Json::Object(o)
             },
+            &Trans::Tagged(id, ref trans) => {
+                let mut t = Object::new();
+                t.insert("id".to_string(), id.to_json());
+                t.insert("trans".to_string(), trans.to_json());
+                let mut o = Object::new();
+                o.insert("tagged".to_string(), Json::Object(t));
+                Json::Object(o)
+            },
+            &Trans::Deadline(ms, ref trans) => {
+                let mut d = Object::new();
+                d.insert("ms".to_string(), ms.to_json());
+                d.insert("trans".to_string(), trans.to_json());
+                let mut o = Object::new();
+                o.insert("deadline".to_string(), Json::Object(d));
+                Json::Object(o)
+            },
         }
     }
 }
@@ -165,6 +404,9 @@ impl<UD> ToJson for Match<UD> where UD: Debug + ToJson {
     fn to_json(&self) -> Json {
         let mut o = Object::new();
         o.insert("cluster_id".to_string(), self.cluster_id.to_json());
+        if let Some(doc_id) = self.doc_id {
+            o.insert("doc_id".to_string(), doc_id.to_json());
+        }
         o.insert("similarity".to_string(), self.similarity.to_json());
         o.insert("user_data".to_string(), self.user_data.to_json());
         Json::Object(o)
@@ -185,183 +427,827 @@ impl<UD> ToJson for LookupResult<UD> where UD: Debug + ToJson {
                 o.insert("neighbours".to_string(), neighbours.to_json());
                 Json::Object(o)
             },
-            &LookupResult::Error(ref message) => {
+            &LookupResult::Error(ref e) => {
                 let mut o = Object::new();
-                o.insert("error".to_string(), message.to_json());
+                o.insert("error".to_string(), e.to_json());
                 Json::Object(o)
-            }
-        }
-    }
-}
-
-impl<UD> ToJson for Rep<UD> where UD: Debug + ToJson {
-    fn to_json(&self) -> Json {
-        match self {
-            &Rep::InitAck => Json::String("init_ack".to_string()),
-            &Rep::Result(ref result) => {
+            },
+            &LookupResult::WithOutcome(ref outcome, ref result) => {
+                let mut w = Object::new();
+                w.insert("outcome".to_string(), outcome.to_json());
+                w.insert("result".to_string(), result.to_json());
                 let mut o = Object::new();
-                o.insert("result".to_string(), result.to_json());
+                o.insert("with_outcome".to_string(), Json::Object(w));
                 Json::Object(o)
             },
-            &Rep::TerminateAck => Json::String("terminate_ack".to_string()),
-            &Rep::Unexpected(ref req) => {
+            &LookupResult::NeighboursPage { ref matches, ref next, } => {
+                let mut p = Object::new();
+                p.insert("matches".to_string(), matches.to_json());
+                if let &Some(ref cursor) = next {
+                    p.insert("next".to_string(), cursor.to_json());
+                }
                 let mut o = Object::new();
-                o.insert("unexpected".to_string(), req.to_json());
+                o.insert("neighbours_page".to_string(), Json::Object(p));
                 Json::Object(o)
             },
-            &Rep::TooBusy => Json::String("too_busy".to_string()),
-            &Rep::WantCrash => Json::String("want_crash".to_string()),
         }
     }
 }
 
-#[derive(Debug)]
-pub enum JsonDecodeError<'a> {
-    UnexpectedToken(&'a Json),
-    MalformedObject(&'a Json),
+impl ToJson for UnexpectedReason {
+    fn to_json(&self) -> Json {
+        Json::String(match self {
+            &UnexpectedReason::Unspecified => "unspecified",
+            &UnexpectedReason::NotInitialized => "not_initialized",
+            &UnexpectedReason::AlreadyInitialized => "already_initialized",
+            &UnexpectedReason::Terminated => "terminated",
+            &UnexpectedReason::Unsupported => "unsupported",
+        }.to_string())
+    }
 }
 
-pub trait FromJson: Sized {
-    fn from_json<'a>(json: &'a Json) -> Result<Self, JsonDecodeError<'a>>;
+impl ToJson for ReqKind {
+    fn to_json(&self) -> Json {
+        Json::String(match self {
+            &ReqKind::Init => "init",
+            &ReqKind::Lookup => "lookup",
+            &ReqKind::Terminate => "terminate",
+            &ReqKind::Snapshot => "snapshot",
+            &ReqKind::Restore => "restore",
+            &ReqKind::Ping => "ping",
+            &ReqKind::CreateCollection => "create_collection",
+            &ReqKind::ListCollections => "list_collections",
+            &ReqKind::DropCollection => "drop_collection",
+            &ReqKind::LookupStream => "lookup_stream",
+            &ReqKind::Cancel => "cancel",
+            &ReqKind::Subscribe => "subscribe",
+            &ReqKind::FetchMore => "fetch_more",
+            &ReqKind::LookupBatch => "lookup_batch",
+            &ReqKind::GetCluster => "get_cluster",
+            &ReqKind::MergeClusters => "merge_clusters",
+            &ReqKind::MoveDocument => "move_document",
+            &ReqKind::UpdateUserData => "update_user_data",
+        }.to_string())
+    }
 }
 
-impl FromJson for String {
-    fn from_json<'a>(json: &'a Json) -> Result<String, JsonDecodeError<'a>> {
-        match json {
-            &Json::String(ref value) => Ok(value.clone()),
-            _ => Err(JsonDecodeError::UnexpectedToken(json)),
+impl<UD> ToJson for ClusterInfo<UD> where UD: Debug + ToJson {
+    fn to_json(&self) -> Json {
+        let mut o = Object::new();
+        o.insert("cluster_id".to_string(), self.cluster_id.to_json());
+        o.insert("size".to_string(), self.size.to_json());
+        if let Some(ref representative) = self.representative {
+            o.insert("representative".to_string(), representative.to_json());
         }
+        o.insert("members".to_string(), self.members.to_json());
+        Json::Object(o)
     }
 }
 
-impl FromJson for LookupType {
-    fn from_json<'a>(json: &'a Json) -> Result<LookupType, JsonDecodeError<'a>> {
-        match json {
-            &Json::String(ref token) if *token == "all" => Ok(LookupType::All),
-            &Json::String(ref token) if *token == "best" => Ok(LookupType::Best),
-            &Json::String(ref token) if *token == "best_or_mine" => Ok(LookupType::BestOrMine),
-            token => Err(JsonDecodeError::UnexpectedToken(token)),
-        }
+impl<UD> ToJson for Member<UD> where UD: Debug + ToJson {
+    fn to_json(&self) -> Json {
+        let mut o = Object::new();
+        o.insert("doc_id".to_string(), self.doc_id.to_json());
+        o.insert("user_data".to_string(), self.user_data.to_json());
+        Json::Object(o)
     }
 }
 
-impl FromJson for InsertCond {
-    fn from_json<'a>(json: &'a Json) -> Result<InsertCond, JsonDecodeError<'a>> {
-        match json {
-            &Json::String(ref token) if *token == "always" =>
-                Ok(InsertCond::Always),
-            &Json::Object(ref obj) => match obj.get("best_sim_less_than") {
-                Some(&Json::F64(sim)) => Ok(InsertCond::BestSimLessThan(sim)),
-                _ => Err(JsonDecodeError::MalformedObject(json)),
-            },
-            _ => Err(JsonDecodeError::UnexpectedToken(json)),
-        }
+impl ToJson for DocRef {
+    fn to_json(&self) -> Json {
+        let mut o = Object::new();
+        o.insert("cluster_id".to_string(), self.cluster_id.to_json());
+        o.insert("doc_id".to_string(), self.doc_id.to_json());
+        Json::Object(o)
     }
 }
 
-impl FromJson for AssignCond {
-    fn from_json<'a>(json: &'a Json) -> Result<AssignCond, JsonDecodeError<'a>> {
-        match json {
-            &Json::String(ref token) if *token == "always" =>
-                Ok(AssignCond::Always),
-            &Json::Object(ref obj) => match obj.get("best_sim_less_than") {
-                Some(&Json::F64(sim)) => Ok(AssignCond::BestSimLessThan(sim)),
-                _ => Err(JsonDecodeError::MalformedObject(json)),
-            },
-            _ => Err(JsonDecodeError::UnexpectedToken(json)),
+impl ToJson for EventFilter {
+    fn to_json(&self) -> Json {
+        let mut o = Object::new();
+        o.insert("kinds".to_string(), self.kinds.to_json());
+        if let Some(ref cluster_ids) = self.cluster_ids {
+            o.insert("cluster_ids".to_string(), cluster_ids.to_json());
         }
+        Json::Object(o)
     }
 }
 
-impl FromJson for ClusterChoice {
-    fn from_json<'a>(json: &'a Json) -> Result<ClusterChoice, JsonDecodeError<'a>> {
-        let decoded = match json {
-            &Json::String(ref token) if *token == "server_choice" =>
-                Some(ClusterChoice::ServerChoice),
-            &Json::Object(ref obj) => match obj.get("client_choice") {
-                Some(&Json::U64(cluster_id)) => Some(ClusterChoice::ClientChoice(cluster_id)),
-                _ => None,
+impl ToJson for EventKind {
+    fn to_json(&self) -> Json {
+        Json::String(match self {
+            &EventKind::DocumentInserted => "document_inserted",
+            &EventKind::ClusterCreated => "cluster_created",
+            &EventKind::ClusterDeleted => "cluster_deleted",
+            &EventKind::DocumentMoved => "document_moved",
+        }.to_string())
+    }
+}
+
+impl<UD> ToJson for Event<UD> where UD: Debug + ToJson {
+    fn to_json(&self) -> Json {
+        let mut e = Object::new();
+        e.insert("cluster_id".to_string(), self.cluster_id().to_json());
+        let key = match self {
+            &Event::DocumentInserted { doc_id, ref user_data, .. } => {
+                if let Some(doc_id) = doc_id {
+                    e.insert("doc_id".to_string(), doc_id.to_json());
+                }
+                e.insert("user_data".to_string(), user_data.to_json());
+                "document_inserted"
+            },
+            &Event::ClusterCreated { doc_id, ref user_data, .. } => {
+                if let Some(doc_id) = doc_id {
+                    e.insert("doc_id".to_string(), doc_id.to_json());
+                }
+                e.insert("user_data".to_string(), user_data.to_json());
+                "cluster_created"
+            },
+            &Event::ClusterDeleted { .. } =>
+                "cluster_deleted",
+            &Event::DocumentMoved { ref doc, from_cluster, } => {
+                e.insert("doc_id".to_string(), doc.doc_id.to_json());
+                e.insert("from_cluster".to_string(), from_cluster.to_json());
+                "document_moved"
             },
-            _ => None,
         };
-
-        match decoded { Some(value) => Ok(value), None => Err(JsonDecodeError::UnexpectedToken(json)), }
+        let mut o = Object::new();
+        o.insert(key.to_string(), Json::Object(e));
+        Json::Object(o)
     }
 }
 
-impl FromJson for ClusterAssign {
-    fn from_json<'a>(json: &'a Json) -> Result<ClusterAssign, JsonDecodeError<'a>> {
-        match json {
-            &Json::Object(ref obj) => match (obj.get("cond"), obj.get("choice")) {
-                (Some(cond), Some(choice)) =>
-                    Ok(ClusterAssign {
-                        cond: try!(<AssignCond as FromJson>::from_json(cond)),
-                        choice: try!(<ClusterChoice as FromJson>::from_json(choice)),
-                    }),
-                _ => Err(JsonDecodeError::MalformedObject(json)),
+impl ToJson for CancelState {
+    fn to_json(&self) -> Json {
+        match self {
+            &CancelState::NotFound =>
+                Json::String("not_found".to_string()),
+            &CancelState::Cancelled { completed, } => {
+                let mut c = Object::new();
+                c.insert("completed".to_string(), completed.to_json());
+                let mut o = Object::new();
+                o.insert("cancelled".to_string(), Json::Object(c));
+                Json::Object(o)
             },
-            _ => Err(JsonDecodeError::UnexpectedToken(json)),
         }
     }
 }
 
-impl<UD> FromJson for PostAction<UD> where UD: Debug + FromJson {
-    fn from_json<'a>(json: &'a Json) -> Result<PostAction<UD>, JsonDecodeError<'a>> {
-        match json {
-            &Json::String(ref token) if *token == "none" =>
-                Ok(PostAction::None),
-            &Json::Object(ref obj) => match (obj.get("cond"), obj.get("assign"), obj.get("user_data")) {
-                (Some(cond), Some(assign), Some(user_data)) =>
-                    Ok(PostAction::InsertNew {
-                        cond: try!(<InsertCond as FromJson>::from_json(cond)),
-                        assign: try!(<ClusterAssign as FromJson>::from_json(assign)),
-                        user_data: try!(<UD as FromJson>::from_json(user_data)),
-                    }),
-                _ => Err(JsonDecodeError::MalformedObject(json)),
-            },
-            _ => Err(JsonDecodeError::UnexpectedToken(json)),
+impl ToJson for LookupError {
+    fn to_json(&self) -> Json {
+        if self.is_legacy() {
+            return self.message.to_json();
         }
+        let mut o = Object::new();
+        o.insert("code".to_string(), self.code.to_json());
+        o.insert("retryable".to_string(), Json::Boolean(self.retryable));
+        o.insert("message".to_string(), self.message.to_json());
+        Json::Object(o)
     }
 }
 
-impl<UD> FromJson for LookupTask<UD> where UD: Debug + FromJson {
-    fn from_json<'a>(json: &'a Json) -> Result<LookupTask<UD>, JsonDecodeError<'a>> {
-        match json {
-            &Json::Object(ref obj) => match (obj.get("text"), obj.get("result"), obj.get("post_action")) {
-                (Some(text), Some(result), Some(post_action)) =>
-                    Ok(LookupTask {
-                        text: try!(<String as FromJson>::from_json(text)),
-                        result: try!(<LookupType as FromJson>::from_json(result)),
-                        post_action: try!(<PostAction<UD> as FromJson>::from_json(post_action)),
-                    }),
-                _ => Err(JsonDecodeError::MalformedObject(json)),
-            },
-            _ => Err(JsonDecodeError::UnexpectedToken(json)),
-        }
+impl ToJson for ErrorCode {
+    fn to_json(&self) -> Json {
+        Json::String(match self {
+            &ErrorCode::Other => "other",
+            &ErrorCode::DeadlineExceeded => "deadline_exceeded",
+            &ErrorCode::Overloaded => "overloaded",
+            &ErrorCode::TextTooLong => "text_too_long",
+            &ErrorCode::InvalidClusterChoice => "invalid_cluster_choice",
+            &ErrorCode::UnsupportedDocument => "unsupported_document",
+            &ErrorCode::InvalidCursor => "invalid_cursor",
+        }.to_string())
     }
 }
 
-impl<T> FromJson for Workload<T> where T: Debug + FromJson {
-    fn from_json<'a>(json: &'a Json) -> Result<Workload<T>, JsonDecodeError<'a>> {
-        match json {
-            &Json::Array(ref obj) =>
-                Ok(Workload::Many(try!(obj.iter().map(|o| <T as FromJson>::from_json(o)).collect()))),
-            obj =>
+impl ToJson for InsertOutcome {
+    fn to_json(&self) -> Json {
+        match *self {
+            InsertOutcome::NotInserted =>
+                Json::String("not_inserted".to_string()),
+            InsertOutcome::InsertedIntoCluster { cluster_id, doc_id, } => {
+                let mut o = Object::new();
+                o.insert("inserted_into_cluster".to_string(), inserted_to_json(cluster_id, doc_id));
+                Json::Object(o)
+            },
+            InsertOutcome::InsertedAsNewCluster { cluster_id, doc_id, } => {
+                let mut o = Object::new();
+                o.insert("inserted_as_new_cluster".to_string(), inserted_to_json(cluster_id, doc_id));
+                Json::Object(o)
+            },
+        }
+    }
+}
+
+/// A bare cluster id as before document ids were reported, or a `DocRef` once the id is known.
+fn inserted_to_json(cluster_id: u64, doc_id: Option<u64>) -> Json {
+    match doc_id {
+        Some(doc_id) => DocRef { cluster_id: cluster_id, doc_id: doc_id, }.to_json(),
+        None => cluster_id.to_json(),
+    }
+}
+
+impl<UD> ToJson for Rep<UD> where UD: Debug + ToJson {
+    fn to_json(&self) -> Json {
+        match self {
+            &Rep::InitAck => Json::String("init_ack".to_string()),
+            &Rep::Result(ref result) => {
+                let mut o = Object::new();
+                o.insert("result".to_string(), result.to_json());
+                Json::Object(o)
+            },
+            &Rep::TerminateAck => Json::String("terminate_ack".to_string()),
+            &Rep::Unexpected { reason: UnexpectedReason::Unspecified, req: ReqEcho::Full(ref req), } => {
+                let mut o = Object::new();
+                o.insert("unexpected".to_string(), req.to_json());
+                Json::Object(o)
+            },
+            &Rep::Unexpected { ref reason, ref req, } => {
+                let mut u = Object::new();
+                u.insert("reason".to_string(), reason.to_json());
+                match req {
+                    &ReqEcho::Full(ref req) => u.insert("req".to_string(), req.to_json()),
+                    &ReqEcho::Kind(ref kind) => u.insert("kind".to_string(), kind.to_json()),
+                };
+                let mut o = Object::new();
+                o.insert("unexpected".to_string(), Json::Object(u));
+                Json::Object(o)
+            },
+            &Rep::TooBusy(ref info) if info.is_default() => Json::String("too_busy".to_string()),
+            &Rep::TooBusy(ref info) => {
+                let mut o = Object::new();
+                o.insert("too_busy".to_string(), info.to_json());
+                Json::Object(o)
+            },
+            &Rep::WantCrash => Json::String("want_crash".to_string()),
+            &Rep::SnapshotAck => Json::String("snapshot_ack".to_string()),
+            &Rep::RestoreAck => Json::String("restore_ack".to_string()),
+            &Rep::SnapshotError(ref message) => {
+                let mut o = Object::new();
+                o.insert("snapshot_error".to_string(), message.to_json());
+                Json::Object(o)
+            },
+            &Rep::Pong(nonce) => {
+                let mut o = Object::new();
+                o.insert("pong".to_string(), nonce.to_json());
+                Json::Object(o)
+            },
+            &Rep::Tagged(id, ref rep) => {
+                let mut t = Object::new();
+                t.insert("id".to_string(), id.to_json());
+                t.insert("rep".to_string(), rep.to_json());
+                let mut o = Object::new();
+                o.insert("tagged".to_string(), Json::Object(t));
+                Json::Object(o)
+            },
+            &Rep::CreateCollectionAck => Json::String("create_collection_ack".to_string()),
+            &Rep::Collections(ref names) => {
+                let mut o = Object::new();
+                o.insert("collections".to_string(), names.to_json());
+                Json::Object(o)
+            },
+            &Rep::DropCollectionAck => Json::String("drop_collection_ack".to_string()),
+            &Rep::DeadlineExceeded => Json::String("deadline_exceeded".to_string()),
+            &Rep::UnknownCollection(ref name) => {
+                let mut o = Object::new();
+                o.insert("unknown_collection".to_string(), name.to_json());
+                Json::Object(o)
+            },
+            &Rep::CollectionExists(ref name) => {
+                let mut o = Object::new();
+                o.insert("collection_exists".to_string(), name.to_json());
+                Json::Object(o)
+            },
+            &Rep::ResultChunk { start, ref results, } => {
+                let mut c = Object::new();
+                c.insert("start".to_string(), start.to_json());
+                c.insert("results".to_string(), results.to_json());
+                let mut o = Object::new();
+                o.insert("result_chunk".to_string(), Json::Object(c));
+                Json::Object(o)
+            },
+            &Rep::EndOfStream(total) => {
+                let mut o = Object::new();
+                o.insert("end_of_stream".to_string(), total.to_json());
+                Json::Object(o)
+            },
+            &Rep::Cancelled(id, ref state) => {
+                let mut c = Object::new();
+                c.insert("id".to_string(), id.to_json());
+                c.insert("state".to_string(), state.to_json());
+                let mut o = Object::new();
+                o.insert("cancelled".to_string(), Json::Object(c));
+                Json::Object(o)
+            },
+            &Rep::AuthFailed(ref reason) => {
+                let mut o = Object::new();
+                o.insert("auth_failed".to_string(), reason.to_json());
+                Json::Object(o)
+            },
+            &Rep::AuthChallenge(ref nonce) => {
+                let mut o = Object::new();
+                o.insert("auth_challenge".to_string(), Json::String(nonce.to_base64(STANDARD)));
+                Json::Object(o)
+            },
+            &Rep::SubscribeAck => Json::String("subscribe_ack".to_string()),
+            &Rep::Cluster(ref info) => {
+                let mut o = Object::new();
+                o.insert("cluster".to_string(), info.to_json());
+                Json::Object(o)
+            },
+            &Rep::ClusterNotFound(cluster_id) => {
+                let mut o = Object::new();
+                o.insert("cluster_not_found".to_string(), cluster_id.to_json());
+                Json::Object(o)
+            },
+            &Rep::ClustersMerged { from, into, } => {
+                let mut m = Object::new();
+                m.insert("from".to_string(), from.to_json());
+                m.insert("into".to_string(), into.to_json());
+                let mut o = Object::new();
+                o.insert("clusters_merged".to_string(), Json::Object(m));
+                Json::Object(o)
+            },
+            &Rep::DocumentMoved { ref doc, to_cluster, } => {
+                let mut m = Object::new();
+                m.insert("doc".to_string(), doc.to_json());
+                m.insert("to_cluster".to_string(), to_cluster.to_json());
+                let mut o = Object::new();
+                o.insert("document_moved".to_string(), Json::Object(m));
+                Json::Object(o)
+            },
+            &Rep::DocumentNotFound(ref doc) => {
+                let mut o = Object::new();
+                o.insert("document_not_found".to_string(), doc.to_json());
+                Json::Object(o)
+            },
+            &Rep::UserDataUpdated(ref doc) => {
+                let mut o = Object::new();
+                o.insert("user_data_updated".to_string(), doc.to_json());
+                Json::Object(o)
+            },
+            &Rep::Event(ref event) => {
+                let mut o = Object::new();
+                o.insert("event".to_string(), event.to_json());
+                Json::Object(o)
+            },
+        }
+    }
+}
+
+impl ToJson for BusyInfo {
+    fn to_json(&self) -> Json {
+        let mut o = Object::new();
+        if let Some(retry_after_ms) = self.retry_after_ms {
+            o.insert("retry_after_ms".to_string(), retry_after_ms.to_json());
+        }
+        if let Some(queue_depth) = self.queue_depth {
+            o.insert("queue_depth".to_string(), queue_depth.to_json());
+        }
+        if let Some(ref reason) = self.reason {
+            o.insert("reason".to_string(), reason.to_json());
+        }
+        Json::Object(o)
+    }
+}
+
+impl ToJson for BusyReason {
+    fn to_json(&self) -> Json {
+        match self {
+            &BusyReason::QueueFull => Json::String("queue_full".to_string()),
+            &BusyReason::MemoryPressure => Json::String("memory_pressure".to_string()),
+            &BusyReason::ShuttingDown => Json::String("shutting_down".to_string()),
+        }
+    }
+}
+
+fn is_threshold(value: f64) -> bool {
+    value >= 0.0 && value <= 1.0
+}
+
+/// Picks the variant entry of an object out of the known `keys`, other keys are ignored so
+/// clients may add their own fields. Fails if none or several of the known keys are present.
+fn single_entry<'a>(obj: &'a Object, keys: &[&'static str]) -> Option<(&'static str, &'a Json)> {
+    let mut found = keys.iter().filter_map(|&key| obj.get(key).map(|value| (key, value)));
+    match (found.next(), found.next()) {
+        (Some(entry), None) => Some(entry),
+        _ => None,
+    }
+}
+
+/// Checks whether `json` is the `key` variant, used to reject nested wrappers before decoding them.
+fn is_entry(json: &Json, keys: &[&'static str], key: &str) -> bool {
+    match json {
+        &Json::Object(ref obj) => match single_entry(obj, keys) {
+            Some((found, _)) => found == key,
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+#[derive(Debug)]
+pub enum JsonDecodeError<'a> {
+    UnexpectedToken(&'a Json),
+    MalformedObject(&'a Json),
+}
+
+pub trait FromJson: Sized {
+    fn from_json<'a>(json: &'a Json) -> Result<Self, JsonDecodeError<'a>>;
+}
+
+impl FromJson for String {
+    fn from_json<'a>(json: &'a Json) -> Result<String, JsonDecodeError<'a>> {
+        match json {
+            &Json::String(ref value) => Ok(value.clone()),
+            _ => Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl FromJson for LookupType {
+    fn from_json<'a>(json: &'a Json) -> Result<LookupType, JsonDecodeError<'a>> {
+        match json {
+            &Json::String(ref token) if *token == "all" => Ok(LookupType::All),
+            &Json::String(ref token) if *token == "best" => Ok(LookupType::Best),
+            &Json::String(ref token) if *token == "best_or_mine" => Ok(LookupType::BestOrMine),
+            &Json::Object(ref obj) => match (obj.get("top_k"), obj.get("above_threshold").map(Json::as_f64)) {
+                (Some(&Json::U64(k)), None) if k <= u32::MAX as u64 =>
+                    Ok(LookupType::TopK(k as u32)),
+                (None, Some(Some(threshold))) if is_threshold(threshold) =>
+                    Ok(LookupType::AboveThreshold(threshold)),
+                (Some(&Json::U64(k)), Some(Some(threshold))) if k <= u32::MAX as u64 && is_threshold(threshold) =>
+                    Ok(LookupType::TopKAboveThreshold(k as u32, threshold)),
+                _ =>
+                    Err(JsonDecodeError::MalformedObject(json)),
+            },
+            token => Err(JsonDecodeError::UnexpectedToken(token)),
+        }
+    }
+}
+
+const COND_KEYS: &'static [&'static str] = &["best_sim_less_than", "best_sim_greater_than", "text_len_greater_than", "no_neighbour_in_cluster", "and", "or", "not"];
+
+impl FromJson for Cond {
+    fn from_json<'a>(json: &'a Json) -> Result<Cond, JsonDecodeError<'a>> {
+        cond_from_json(json, MAX_COND_DEPTH)
+    }
+}
+
+/// Decodes a condition allowing at most `depth` levels of `and`, `or` and `not`.
+fn cond_from_json<'a>(json: &'a Json, depth: usize) -> Result<Cond, JsonDecodeError<'a>> {
+    match json {
+        &Json::String(ref token) if *token == "always" =>
+            Ok(Cond::Always),
+        &Json::String(ref token) if *token == "never" =>
+            Ok(Cond::Never),
+        &Json::Object(ref obj) => match single_entry(obj, COND_KEYS) {
+            Some(("and", _)) | Some(("or", _)) | Some(("not", _)) if depth == 0 =>
+                Err(JsonDecodeError::MalformedObject(json)),
+            Some(("best_sim_less_than", &Json::F64(sim))) =>
+                Ok(Cond::BestSimLessThan(sim)),
+            Some(("best_sim_greater_than", &Json::F64(sim))) =>
+                Ok(Cond::BestSimGreaterThan(sim)),
+            Some(("text_len_greater_than", &Json::U64(len))) =>
+                Ok(Cond::TextLenGreaterThan(len)),
+            Some(("no_neighbour_in_cluster", &Json::U64(cluster_id))) =>
+                Ok(Cond::NoNeighbourInCluster(cluster_id)),
+            Some(("and", &Json::Array(ref conds))) =>
+                Ok(Cond::And(try!(conds.iter().map(|c| cond_from_json(c, depth - 1)).collect()))),
+            Some(("or", &Json::Array(ref conds))) =>
+                Ok(Cond::Or(try!(conds.iter().map(|c| cond_from_json(c, depth - 1)).collect()))),
+            Some(("not", cond)) =>
+                Ok(Cond::Not(Box::new(try!(cond_from_json(cond, depth - 1))))),
+            _ =>
+                Err(JsonDecodeError::MalformedObject(json)),
+        },
+        _ => Err(JsonDecodeError::UnexpectedToken(json)),
+    }
+}
+
+impl FromJson for ClusterChoice {
+    fn from_json<'a>(json: &'a Json) -> Result<ClusterChoice, JsonDecodeError<'a>> {
+        let decoded = match json {
+            &Json::String(ref token) if *token == "server_choice" =>
+                Some(ClusterChoice::ServerChoice),
+            &Json::String(ref token) if *token == "best_match_cluster" =>
+                Some(ClusterChoice::BestMatchCluster),
+            &Json::String(ref token) if *token == "new_cluster" =>
+                Some(ClusterChoice::NewCluster),
+            &Json::Object(ref obj) => match obj.get("client_choice") {
+                Some(&Json::U64(cluster_id)) => Some(ClusterChoice::ClientChoice(cluster_id)),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        match decoded { Some(value) => Ok(value), None => Err(JsonDecodeError::UnexpectedToken(json)), }
+    }
+}
+
+impl FromJson for ClusterAssign {
+    fn from_json<'a>(json: &'a Json) -> Result<ClusterAssign, JsonDecodeError<'a>> {
+        match json {
+            &Json::Object(ref obj) => match (obj.get("cond"), obj.get("choice")) {
+                (Some(cond), Some(choice)) =>
+                    Ok(ClusterAssign {
+                        cond: try!(<Cond as FromJson>::from_json(cond)),
+                        choice: try!(<ClusterChoice as FromJson>::from_json(choice)),
+                    }),
+                _ => Err(JsonDecodeError::MalformedObject(json)),
+            },
+            _ => Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl<UD> FromJson for PostAction<UD> where UD: Debug + FromJson {
+    fn from_json<'a>(json: &'a Json) -> Result<PostAction<UD>, JsonDecodeError<'a>> {
+        match json {
+            &Json::String(ref token) if *token == "none" =>
+                Ok(PostAction::None),
+            &Json::Object(ref obj) => match (obj.get("cond"), obj.get("assign"), obj.get("user_data")) {
+                (Some(cond), Some(assign), Some(user_data)) =>
+                    Ok(PostAction::InsertNew {
+                        cond: try!(<Cond as FromJson>::from_json(cond)),
+                        assign: try!(<ClusterAssign as FromJson>::from_json(assign)),
+                        user_data: try!(<UD as FromJson>::from_json(user_data)),
+                        idempotency_key: match obj.get("idempotency_key") {
+                            Some(key) => Some(try!(<String as FromJson>::from_json(key))),
+                            None => None,
+                        },
+                    }),
+                _ => Err(JsonDecodeError::MalformedObject(json)),
+            },
+            _ => Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+const DOC_KEYS: &'static [&'static str] = &["text", "bytes", "minhash", "shingles"];
+
+fn doc_from_object<'a>(json: &'a Json, obj: &'a Object) -> Option<Result<Document, JsonDecodeError<'a>>> {
+    single_entry(obj, DOC_KEYS).map(|entry| match entry {
+        ("text", text) =>
+            <String as FromJson>::from_json(text).map(Document::Text),
+        ("bytes", &Json::String(ref bytes)) =>
+            bytes.from_base64().map(Document::Bytes).map_err(|_| JsonDecodeError::MalformedObject(json)),
+        ("minhash", signature) =>
+            signature_from_json(signature).map(|(l, h)| Document::Signature(Signature::MinHash { shingle_len: l, hashes: h, })),
+        ("shingles", signature) =>
+            signature_from_json(signature).map(|(l, h)| Document::Signature(Signature::Shingles { shingle_len: l, hashes: h, })),
+        _ =>
+            Err(JsonDecodeError::MalformedObject(json)),
+    })
+}
+
+fn signature_from_json<'a>(json: &'a Json) -> Result<(u32, Vec<u64>), JsonDecodeError<'a>> {
+    match json {
+        &Json::Object(ref obj) => match (obj.get("shingle_len"), obj.get("hashes")) {
+            (Some(&Json::U64(shingle_len)), Some(&Json::Array(ref hashes))) if shingle_len <= u32::MAX as u64 =>
+                Ok((shingle_len as u32, try!(hashes.iter().map(|h| match h {
+                    &Json::String(ref hash) => hash.parse().map_err(|_| JsonDecodeError::UnexpectedToken(h)),
+                    other => Err(JsonDecodeError::UnexpectedToken(other)),
+                }).collect()))),
+            _ =>
+                Err(JsonDecodeError::MalformedObject(json)),
+        },
+        _ =>
+            Err(JsonDecodeError::UnexpectedToken(json)),
+    }
+}
+
+impl FromJson for Document {
+    fn from_json<'a>(json: &'a Json) -> Result<Document, JsonDecodeError<'a>> {
+        match json {
+            &Json::Object(ref obj) => match doc_from_object(json, obj) {
+                Some(doc) => doc,
+                None => Err(JsonDecodeError::MalformedObject(json)),
+            },
+            _ =>
+                Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl<UD> FromJson for LookupTask<UD> where UD: Debug + FromJson {
+    fn from_json<'a>(json: &'a Json) -> Result<LookupTask<UD>, JsonDecodeError<'a>> {
+        match json {
+            &Json::Object(ref obj) => match (doc_from_object(json, obj), obj.get("result"), obj.get("post_action")) {
+                (Some(doc), Some(result), Some(post_action)) =>
+                    Ok(LookupTask {
+                        doc: try!(doc),
+                        result: try!(<LookupType as FromJson>::from_json(result)),
+                        post_action: try!(<PostAction<UD> as FromJson>::from_json(post_action)),
+                    }),
+                _ => Err(JsonDecodeError::MalformedObject(json)),
+            },
+            _ => Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl<UD> FromJson for Batch<UD> where UD: Debug + FromJson {
+    fn from_json<'a>(json: &'a Json) -> Result<Batch<UD>, JsonDecodeError<'a>> {
+        match json {
+            &Json::Object(ref obj) => match (obj.get("result"), obj.get("insert_cond"), obj.get("assign"), obj.get("tasks")) {
+                (Some(result), Some(insert_cond), Some(assign), Some(&Json::Array(ref tasks))) =>
+                    Ok(Batch {
+                        result: try!(<LookupType as FromJson>::from_json(result)),
+                        insert_cond: try!(<Cond as FromJson>::from_json(insert_cond)),
+                        assign: try!(<ClusterAssign as FromJson>::from_json(assign)),
+                        tasks: try!(tasks.iter().map(|t| <BatchTask<UD> as FromJson>::from_json(t)).collect()),
+                    }),
+                _ => Err(JsonDecodeError::MalformedObject(json)),
+            },
+            _ => Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl<UD> FromJson for BatchTask<UD> where UD: Debug + FromJson {
+    fn from_json<'a>(json: &'a Json) -> Result<BatchTask<UD>, JsonDecodeError<'a>> {
+        match json {
+            &Json::Object(ref obj) => match doc_from_object(json, obj) {
+                Some(doc) =>
+                    Ok(BatchTask {
+                        doc: try!(doc),
+                        result: match obj.get("result") {
+                            Some(result) => Some(try!(<LookupType as FromJson>::from_json(result))),
+                            None => None,
+                        },
+                        insert: match obj.get("insert") {
+                            Some(insert) => Some(try!(<BatchInsert<UD> as FromJson>::from_json(insert))),
+                            None => None,
+                        },
+                    }),
+                None => Err(JsonDecodeError::MalformedObject(json)),
+            },
+            _ => Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl<UD> FromJson for BatchInsert<UD> where UD: Debug + FromJson {
+    fn from_json<'a>(json: &'a Json) -> Result<BatchInsert<UD>, JsonDecodeError<'a>> {
+        match json {
+            &Json::Object(ref obj) => match obj.get("user_data") {
+                Some(user_data) =>
+                    Ok(BatchInsert {
+                        user_data: try!(<UD as FromJson>::from_json(user_data)),
+                        idempotency_key: match obj.get("idempotency_key") {
+                            Some(key) => Some(try!(<String as FromJson>::from_json(key))),
+                            None => None,
+                        },
+                        cond: match obj.get("cond") {
+                            Some(cond) => Some(try!(<Cond as FromJson>::from_json(cond))),
+                            None => None,
+                        },
+                        assign: match obj.get("assign") {
+                            Some(assign) => Some(try!(<ClusterAssign as FromJson>::from_json(assign))),
+                            None => None,
+                        },
+                    }),
+                None => Err(JsonDecodeError::MalformedObject(json)),
+            },
+            _ => Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl<T> FromJson for Workload<T> where T: Debug + FromJson {
+    fn from_json<'a>(json: &'a Json) -> Result<Workload<T>, JsonDecodeError<'a>> {
+        match json {
+            &Json::Array(ref obj) =>
+                Ok(Workload::Many(try!(obj.iter().map(|o| <T as FromJson>::from_json(o)).collect()))),
+            obj =>
                 Ok(Workload::Single(try!(<T as FromJson>::from_json(obj)))),
         }
     }
 }
 
+impl FromJson for InitParams {
+    fn from_json<'a>(json: &'a Json) -> Result<InitParams, JsonDecodeError<'a>> {
+        match json {
+            &Json::Object(ref obj) =>
+                Ok(InitParams {
+                    collection: match obj.get("collection") {
+                        Some(collection) => Some(try!(<String as FromJson>::from_json(collection))),
+                        None => None,
+                    },
+                    credentials: match obj.get("credentials") {
+                        Some(credentials) => Some(try!(<Credentials as FromJson>::from_json(credentials))),
+                        None => None,
+                    },
+                }),
+            _ =>
+                Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+const CREDENTIALS_KEYS: &'static [&'static str] = &["token", "hmac"];
+
+impl FromJson for Credentials {
+    fn from_json<'a>(json: &'a Json) -> Result<Credentials, JsonDecodeError<'a>> {
+        match json {
+            &Json::Object(ref obj) => match single_entry(obj, CREDENTIALS_KEYS) {
+                Some(("token", token)) =>
+                    Ok(Credentials::Token(try!(<String as FromJson>::from_json(token)))),
+                Some(("hmac", &Json::Object(ref hmac))) => match (hmac.get("key_id"), hmac.get("nonce"), hmac.get("mac")) {
+                    (Some(key_id), Some(&Json::String(ref nonce)), Some(&Json::String(ref mac))) =>
+                        match (nonce.from_base64(), mac.from_base64()) {
+                            (Ok(nonce), Ok(mac)) =>
+                                Ok(Credentials::Hmac { key_id: try!(<String as FromJson>::from_json(key_id)), nonce: nonce, mac: mac, }),
+                            _ =>
+                                Err(JsonDecodeError::MalformedObject(json)),
+                        },
+                    _ =>
+                        Err(JsonDecodeError::MalformedObject(json)),
+                },
+                _ =>
+                    Err(JsonDecodeError::MalformedObject(json)),
+            },
+            _ =>
+                Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+const REQ_KEYS: &'static [&'static str] = &["lookup", "snapshot", "restore", "init", "ping", "create_collection", "drop_collection", "cancel", "get_cluster", "merge_clusters", "move_document", "update_user_data", "lookup_batch", "fetch_more", "subscribe", "lookup_stream"];
+
 impl<UD> FromJson for Req<UD> where UD: Debug + FromJson {
     fn from_json<'a>(json: &'a Json) -> Result<Req<UD>, JsonDecodeError<'a>> {
         match json {
             &Json::String(ref token) if *token == "init" =>
-                Ok(Req::Init),
+                Ok(Req::Init(InitParams::default())),
+            &Json::String(ref token) if *token == "list_collections" =>
+                Ok(Req::ListCollections),
             &Json::String(ref token) if *token == "terminate" =>
                 Ok(Req::Terminate),
-            &Json::Object(ref obj) => match obj.get("lookup") {
-                Some(workload) =>
+            &Json::Object(ref obj) => match single_entry(obj, REQ_KEYS) {
+                Some(("lookup", workload)) =>
                     Ok(Req::Lookup(try!(<Workload<LookupTask<UD>> as FromJson>::from_json(workload)))),
+                Some(("snapshot", path)) =>
+                    Ok(Req::Snapshot { path: try!(<String as FromJson>::from_json(path)), }),
+                Some(("restore", path)) =>
+                    Ok(Req::Restore { path: try!(<String as FromJson>::from_json(path)), }),
+                Some(("init", params)) =>
+                    Ok(Req::Init(try!(<InitParams as FromJson>::from_json(params)))),
+                Some(("ping", &Json::U64(nonce))) =>
+                    Ok(Req::Ping(nonce)),
+                Some(("create_collection", name)) =>
+                    Ok(Req::CreateCollection(try!(<String as FromJson>::from_json(name)))),
+                Some(("drop_collection", name)) =>
+                    Ok(Req::DropCollection(try!(<String as FromJson>::from_json(name)))),
+                Some(("cancel", &Json::U64(id))) =>
+                    Ok(Req::Cancel(id)),
+                Some(("get_cluster", &Json::Object(ref get))) => match (get.get("cluster_id"), get.get("offset"), get.get("limit")) {
+                    (Some(&Json::U64(cluster_id)), Some(&Json::U64(offset)), None) =>
+                        Ok(Req::GetCluster { cluster_id: cluster_id, offset: offset, limit: None, }),
+                    (Some(&Json::U64(cluster_id)), Some(&Json::U64(offset)), Some(&Json::U64(limit))) if limit <= u32::MAX as u64 =>
+                        Ok(Req::GetCluster { cluster_id: cluster_id, offset: offset, limit: Some(limit as u32), }),
+                    _ =>
+                        Err(JsonDecodeError::MalformedObject(json)),
+                },
+                Some(("merge_clusters", &Json::Object(ref merge))) => match (merge.get("from"), merge.get("into")) {
+                    (Some(&Json::U64(from)), Some(&Json::U64(into))) =>
+                        Ok(Req::MergeClusters { from: from, into: into, }),
+                    _ =>
+                        Err(JsonDecodeError::MalformedObject(json)),
+                },
+                Some(("move_document", &Json::Object(ref move_doc))) => match (move_doc.get("doc"), move_doc.get("to_cluster")) {
+                    (Some(doc), None) =>
+                        Ok(Req::MoveDocument { doc: try!(<DocRef as FromJson>::from_json(doc)), to_cluster: None, }),
+                    (Some(doc), Some(&Json::U64(to_cluster))) =>
+                        Ok(Req::MoveDocument { doc: try!(<DocRef as FromJson>::from_json(doc)), to_cluster: Some(to_cluster), }),
+                    _ =>
+                        Err(JsonDecodeError::MalformedObject(json)),
+                },
+                Some(("update_user_data", &Json::Object(ref update))) => match (update.get("doc"), update.get("user_data")) {
+                    (Some(doc), Some(user_data)) =>
+                        Ok(Req::UpdateUserData {
+                            doc: try!(<DocRef as FromJson>::from_json(doc)),
+                            user_data: try!(<UD as FromJson>::from_json(user_data)),
+                        }),
+                    _ =>
+                        Err(JsonDecodeError::MalformedObject(json)),
+                },
+                Some(("lookup_batch", batch)) =>
+                    Ok(Req::LookupBatch(try!(<Batch<UD> as FromJson>::from_json(batch)))),
+                Some(("fetch_more", cursor)) =>
+                    Ok(Req::FetchMore(try!(<String as FromJson>::from_json(cursor)))),
+                Some(("subscribe", filter)) =>
+                    Ok(Req::Subscribe(try!(<EventFilter as FromJson>::from_json(filter)))),
+                Some(("lookup_stream", &Json::Object(ref stream))) => match (stream.get("tasks"), stream.get("chunk_size")) {
+                    (Some(&Json::Array(ref tasks)), Some(&Json::U64(chunk_size))) if chunk_size <= u32::MAX as u64 =>
+                        Ok(Req::LookupStream {
+                            tasks: try!(tasks.iter().map(|t| <LookupTask<UD> as FromJson>::from_json(t)).collect()),
+                            chunk_size: chunk_size as u32,
+                        }),
+                    _ =>
+                        Err(JsonDecodeError::MalformedObject(json)),
+                },
                 _ =>
                     Err(JsonDecodeError::MalformedObject(json)),
             },
@@ -371,14 +1257,28 @@ impl<UD> FromJson for Req<UD> where UD: Debug + FromJson {
     }
 }
 
+const TRANS_KEYS: &'static [&'static str] = &["async", "sync", "tagged", "deadline"];
+
 impl<UD> FromJson for Trans<UD> where UD: Debug + FromJson {
     fn from_json<'a>(json: &'a Json) -> Result<Trans<UD>, JsonDecodeError<'a>> {
         match json {
-            &Json::Object(ref obj) => match (obj.get("async"), obj.get("sync")) {
-                (Some(ref req), None) =>
+            &Json::Object(ref obj) => match single_entry(obj, TRANS_KEYS) {
+                Some(("async", req)) =>
                     Ok(Trans::Async(try!(<Req<UD> as FromJson>::from_json(req)))),
-                (None, Some(ref req)) =>
+                Some(("sync", req)) =>
                     Ok(Trans::Sync(try!(<Req<UD> as FromJson>::from_json(req)))),
+                Some(("tagged", &Json::Object(ref tagged))) => match (tagged.get("id"), tagged.get("trans")) {
+                    (Some(&Json::U64(id)), Some(trans)) if !is_entry(trans, TRANS_KEYS, "tagged") =>
+                        Ok(Trans::Tagged(id, Box::new(try!(<Trans<UD> as FromJson>::from_json(trans))))),
+                    _ =>
+                        Err(JsonDecodeError::MalformedObject(json)),
+                },
+                Some(("deadline", &Json::Object(ref deadline))) => match (deadline.get("ms"), deadline.get("trans")) {
+                    (Some(&Json::U64(ms)), Some(trans)) if !is_entry(trans, TRANS_KEYS, "tagged") && !is_entry(trans, TRANS_KEYS, "deadline") =>
+                        Ok(Trans::Deadline(ms, Box::new(try!(<Trans<UD> as FromJson>::from_json(trans))))),
+                    _ =>
+                        Err(JsonDecodeError::MalformedObject(json)),
+                },
                 _ =>
                     Err(JsonDecodeError::MalformedObject(json)),
             },
@@ -391,10 +1291,15 @@ impl<UD> FromJson for Trans<UD> where UD: Debug + FromJson {
 impl<UD> FromJson for Match<UD> where UD: Debug + FromJson {
     fn from_json<'a>(json: &'a Json) -> Result<Match<UD>, JsonDecodeError<'a>> {
         match json {
-            &Json::Object(ref obj) => match (obj.get("cluster_id"), obj.get("similarity"), obj.get("user_data")) {
-                (Some(&Json::U64(cluster_id)), Some(&Json::F64(similarity)), Some(user_data)) =>
+            &Json::Object(ref obj) => match (obj.get("cluster_id"), obj.get("doc_id"), obj.get("similarity"), obj.get("user_data")) {
+                (Some(&Json::U64(cluster_id)), doc_id, Some(&Json::F64(similarity)), Some(user_data)) =>
                     Ok(Match {
                         cluster_id: cluster_id,
+                        doc_id: match doc_id {
+                            Some(&Json::U64(doc_id)) => Some(doc_id),
+                            Some(_) => return Err(JsonDecodeError::MalformedObject(json)),
+                            None => None,
+                        },
                         similarity: similarity,
                         user_data: try!(<UD as FromJson>::from_json(user_data)),
                     }),
@@ -407,17 +1312,364 @@ impl<UD> FromJson for Match<UD> where UD: Debug + FromJson {
     }
 }
 
-impl<UD> FromJson for LookupResult<UD> where UD: Debug + FromJson {
-    fn from_json<'a>(json: &'a Json) -> Result<LookupResult<UD>, JsonDecodeError<'a>> {
+const LOOKUP_RESULT_KEYS: &'static [&'static str] = &["best", "neighbours", "error", "neighbours_page", "with_outcome"];
+
+impl<UD> FromJson for LookupResult<UD> where UD: Debug + FromJson {
+    fn from_json<'a>(json: &'a Json) -> Result<LookupResult<UD>, JsonDecodeError<'a>> {
+        match json {
+            &Json::Null => Ok(LookupResult::EmptySet),
+            &Json::Object(ref obj) => match single_entry(obj, LOOKUP_RESULT_KEYS) {
+                Some(("best", result)) =>
+                    Ok(LookupResult::Best(try!(<Match<UD> as FromJson>::from_json(result)))),
+                Some(("neighbours", workload)) =>
+                    Ok(LookupResult::Neighbours(try!(<Workload<Match<UD>> as FromJson>::from_json(workload)))),
+                Some(("error", error)) =>
+                    Ok(LookupResult::Error(try!(<LookupError as FromJson>::from_json(error)))),
+                Some(("neighbours_page", &Json::Object(ref page))) => match (page.get("matches"), page.get("next")) {
+                    (Some(&Json::Array(ref matches)), next) =>
+                        Ok(LookupResult::NeighboursPage {
+                            matches: try!(matches.iter().map(|m| <Match<UD> as FromJson>::from_json(m)).collect()),
+                            next: match next {
+                                Some(cursor) => Some(try!(<String as FromJson>::from_json(cursor))),
+                                None => None,
+                            },
+                        }),
+                    _ =>
+                        Err(JsonDecodeError::MalformedObject(json)),
+                },
+                Some(("with_outcome", &Json::Object(ref with))) => match (with.get("outcome"), with.get("result")) {
+                    (Some(outcome), Some(result)) if !is_entry(result, LOOKUP_RESULT_KEYS, "with_outcome") =>
+                        Ok(LookupResult::WithOutcome(
+                            try!(<InsertOutcome as FromJson>::from_json(outcome)),
+                            Box::new(try!(<LookupResult<UD> as FromJson>::from_json(result))))),
+                    _ =>
+                        Err(JsonDecodeError::MalformedObject(json)),
+                },
+                _ =>
+                    Err(JsonDecodeError::MalformedObject(json)),
+            },
+            _ =>
+                Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl FromJson for UnexpectedReason {
+    fn from_json<'a>(json: &'a Json) -> Result<UnexpectedReason, JsonDecodeError<'a>> {
+        match json {
+            &Json::String(ref token) if *token == "unspecified" =>
+                Ok(UnexpectedReason::Unspecified),
+            &Json::String(ref token) if *token == "not_initialized" =>
+                Ok(UnexpectedReason::NotInitialized),
+            &Json::String(ref token) if *token == "already_initialized" =>
+                Ok(UnexpectedReason::AlreadyInitialized),
+            &Json::String(ref token) if *token == "terminated" =>
+                Ok(UnexpectedReason::Terminated),
+            &Json::String(ref token) if *token == "unsupported" =>
+                Ok(UnexpectedReason::Unsupported),
+            _ =>
+                Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl FromJson for ReqKind {
+    fn from_json<'a>(json: &'a Json) -> Result<ReqKind, JsonDecodeError<'a>> {
+        match json {
+            &Json::String(ref token) if *token == "init" =>
+                Ok(ReqKind::Init),
+            &Json::String(ref token) if *token == "lookup" =>
+                Ok(ReqKind::Lookup),
+            &Json::String(ref token) if *token == "terminate" =>
+                Ok(ReqKind::Terminate),
+            &Json::String(ref token) if *token == "snapshot" =>
+                Ok(ReqKind::Snapshot),
+            &Json::String(ref token) if *token == "restore" =>
+                Ok(ReqKind::Restore),
+            &Json::String(ref token) if *token == "ping" =>
+                Ok(ReqKind::Ping),
+            &Json::String(ref token) if *token == "create_collection" =>
+                Ok(ReqKind::CreateCollection),
+            &Json::String(ref token) if *token == "list_collections" =>
+                Ok(ReqKind::ListCollections),
+            &Json::String(ref token) if *token == "drop_collection" =>
+                Ok(ReqKind::DropCollection),
+            &Json::String(ref token) if *token == "lookup_stream" =>
+                Ok(ReqKind::LookupStream),
+            &Json::String(ref token) if *token == "cancel" =>
+                Ok(ReqKind::Cancel),
+            &Json::String(ref token) if *token == "subscribe" =>
+                Ok(ReqKind::Subscribe),
+            &Json::String(ref token) if *token == "fetch_more" =>
+                Ok(ReqKind::FetchMore),
+            &Json::String(ref token) if *token == "lookup_batch" =>
+                Ok(ReqKind::LookupBatch),
+            &Json::String(ref token) if *token == "get_cluster" =>
+                Ok(ReqKind::GetCluster),
+            &Json::String(ref token) if *token == "merge_clusters" =>
+                Ok(ReqKind::MergeClusters),
+            &Json::String(ref token) if *token == "move_document" =>
+                Ok(ReqKind::MoveDocument),
+            &Json::String(ref token) if *token == "update_user_data" =>
+                Ok(ReqKind::UpdateUserData),
+            _ =>
+                Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl<UD> FromJson for ClusterInfo<UD> where UD: Debug + FromJson {
+    fn from_json<'a>(json: &'a Json) -> Result<ClusterInfo<UD>, JsonDecodeError<'a>> {
+        match json {
+            &Json::Object(ref obj) => match (obj.get("cluster_id"), obj.get("size"), obj.get("representative"), obj.get("members")) {
+                (Some(&Json::U64(cluster_id)), Some(&Json::U64(size)), representative, Some(&Json::Array(ref members))) =>
+                    Ok(ClusterInfo {
+                        cluster_id: cluster_id,
+                        size: size,
+                        representative: match representative {
+                            Some(doc) => Some(try!(<Document as FromJson>::from_json(doc))),
+                            None => None,
+                        },
+                        members: try!(members.iter().map(|m| <Member<UD> as FromJson>::from_json(m)).collect()),
+                    }),
+                _ =>
+                    Err(JsonDecodeError::MalformedObject(json)),
+            },
+            _ =>
+                Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl<UD> FromJson for Member<UD> where UD: Debug + FromJson {
+    fn from_json<'a>(json: &'a Json) -> Result<Member<UD>, JsonDecodeError<'a>> {
+        match json {
+            &Json::Object(ref obj) => match (obj.get("doc_id"), obj.get("user_data")) {
+                (Some(&Json::U64(doc_id)), Some(user_data)) =>
+                    Ok(Member {
+                        doc_id: doc_id,
+                        user_data: try!(<UD as FromJson>::from_json(user_data)),
+                    }),
+                _ =>
+                    Err(JsonDecodeError::MalformedObject(json)),
+            },
+            _ =>
+                Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl FromJson for DocRef {
+    fn from_json<'a>(json: &'a Json) -> Result<DocRef, JsonDecodeError<'a>> {
+        match json {
+            &Json::Object(ref obj) => match (obj.get("cluster_id"), obj.get("doc_id")) {
+                (Some(&Json::U64(cluster_id)), Some(&Json::U64(doc_id))) =>
+                    Ok(DocRef { cluster_id: cluster_id, doc_id: doc_id, }),
+                _ =>
+                    Err(JsonDecodeError::MalformedObject(json)),
+            },
+            _ =>
+                Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl FromJson for EventFilter {
+    fn from_json<'a>(json: &'a Json) -> Result<EventFilter, JsonDecodeError<'a>> {
+        match json {
+            &Json::Object(ref obj) => match (obj.get("kinds"), obj.get("cluster_ids")) {
+                (Some(&Json::Array(ref kinds)), cluster_ids) =>
+                    Ok(EventFilter {
+                        kinds: try!(kinds.iter().map(|k| <EventKind as FromJson>::from_json(k)).collect()),
+                        cluster_ids: match cluster_ids {
+                            Some(&Json::Array(ref ids)) => Some(try!(ids.iter().map(|id| match id {
+                                &Json::U64(id) => Ok(id),
+                                other => Err(JsonDecodeError::UnexpectedToken(other)),
+                            }).collect())),
+                            Some(other) => return Err(JsonDecodeError::UnexpectedToken(other)),
+                            None => None,
+                        },
+                    }),
+                _ =>
+                    Err(JsonDecodeError::MalformedObject(json)),
+            },
+            _ =>
+                Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl FromJson for EventKind {
+    fn from_json<'a>(json: &'a Json) -> Result<EventKind, JsonDecodeError<'a>> {
+        match json {
+            &Json::String(ref token) if *token == "document_inserted" =>
+                Ok(EventKind::DocumentInserted),
+            &Json::String(ref token) if *token == "cluster_created" =>
+                Ok(EventKind::ClusterCreated),
+            &Json::String(ref token) if *token == "cluster_deleted" =>
+                Ok(EventKind::ClusterDeleted),
+            &Json::String(ref token) if *token == "document_moved" =>
+                Ok(EventKind::DocumentMoved),
+            _ =>
+                Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+const EVENT_KEYS: &'static [&'static str] = &["document_inserted", "cluster_created", "cluster_deleted", "document_moved"];
+
+impl<UD> FromJson for Event<UD> where UD: Debug + FromJson {
+    fn from_json<'a>(json: &'a Json) -> Result<Event<UD>, JsonDecodeError<'a>> {
+        match json {
+            &Json::Object(ref obj) => match single_entry(obj, EVENT_KEYS) {
+                Some((kind, &Json::Object(ref event))) => {
+                    let doc_id = match event.get("doc_id") {
+                        Some(&Json::U64(doc_id)) => Some(doc_id),
+                        Some(_) => return Err(JsonDecodeError::MalformedObject(json)),
+                        None => None,
+                    };
+                    match (kind, event.get("cluster_id"), doc_id, event.get("user_data"), event.get("from_cluster")) {
+                        ("document_inserted", Some(&Json::U64(cluster_id)), doc_id, Some(user_data), None) =>
+                            Ok(Event::DocumentInserted { cluster_id: cluster_id, doc_id: doc_id, user_data: try!(<UD as FromJson>::from_json(user_data)), }),
+                        ("cluster_created", Some(&Json::U64(cluster_id)), doc_id, Some(user_data), None) =>
+                            Ok(Event::ClusterCreated { cluster_id: cluster_id, doc_id: doc_id, user_data: try!(<UD as FromJson>::from_json(user_data)), }),
+                        ("cluster_deleted", Some(&Json::U64(cluster_id)), None, None, None) =>
+                            Ok(Event::ClusterDeleted { cluster_id: cluster_id, }),
+                        ("document_moved", Some(&Json::U64(cluster_id)), Some(doc_id), None, Some(&Json::U64(from_cluster))) =>
+                            Ok(Event::DocumentMoved { doc: DocRef { cluster_id: cluster_id, doc_id: doc_id, }, from_cluster: from_cluster, }),
+                        _ =>
+                            Err(JsonDecodeError::MalformedObject(json)),
+                    }
+                },
+                _ =>
+                    Err(JsonDecodeError::MalformedObject(json)),
+            },
+            _ =>
+                Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+const CANCEL_STATE_KEYS: &'static [&'static str] = &["cancelled"];
+
+impl FromJson for CancelState {
+    fn from_json<'a>(json: &'a Json) -> Result<CancelState, JsonDecodeError<'a>> {
+        match json {
+            &Json::String(ref token) if *token == "not_found" =>
+                Ok(CancelState::NotFound),
+            &Json::Object(ref obj) => match single_entry(obj, CANCEL_STATE_KEYS) {
+                Some(("cancelled", &Json::Object(ref cancelled))) => match cancelled.get("completed") {
+                    Some(&Json::U64(completed)) if completed <= u32::MAX as u64 =>
+                        Ok(CancelState::Cancelled { completed: completed as u32, }),
+                    _ =>
+                        Err(JsonDecodeError::MalformedObject(json)),
+                },
+                _ =>
+                    Err(JsonDecodeError::MalformedObject(json)),
+            },
+            _ =>
+                Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl FromJson for LookupError {
+    fn from_json<'a>(json: &'a Json) -> Result<LookupError, JsonDecodeError<'a>> {
+        match json {
+            &Json::String(ref message) =>
+                Ok(LookupError::other(message.clone())),
+            &Json::Object(ref obj) => match (obj.get("code"), obj.get("retryable"), obj.get("message")) {
+                (Some(code), Some(&Json::Boolean(retryable)), Some(message)) =>
+                    Ok(LookupError {
+                        code: try!(<ErrorCode as FromJson>::from_json(code)),
+                        retryable: retryable,
+                        message: try!(<String as FromJson>::from_json(message)),
+                    }),
+                _ =>
+                    Err(JsonDecodeError::MalformedObject(json)),
+            },
+            _ =>
+                Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl FromJson for ErrorCode {
+    fn from_json<'a>(json: &'a Json) -> Result<ErrorCode, JsonDecodeError<'a>> {
+        match json {
+            &Json::String(ref token) if *token == "other" =>
+                Ok(ErrorCode::Other),
+            &Json::String(ref token) if *token == "deadline_exceeded" =>
+                Ok(ErrorCode::DeadlineExceeded),
+            &Json::String(ref token) if *token == "overloaded" =>
+                Ok(ErrorCode::Overloaded),
+            &Json::String(ref token) if *token == "text_too_long" =>
+                Ok(ErrorCode::TextTooLong),
+            &Json::String(ref token) if *token == "invalid_cluster_choice" =>
+                Ok(ErrorCode::InvalidClusterChoice),
+            &Json::String(ref token) if *token == "unsupported_document" =>
+                Ok(ErrorCode::UnsupportedDocument),
+            &Json::String(ref token) if *token == "invalid_cursor" =>
+                Ok(ErrorCode::InvalidCursor),
+            _ =>
+                Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl FromJson for BusyInfo {
+    fn from_json<'a>(json: &'a Json) -> Result<BusyInfo, JsonDecodeError<'a>> {
+        match json {
+            &Json::Object(ref obj) => {
+                let mut info = BusyInfo::default();
+                for (key, value) in obj {
+                    match (&key[..], value) {
+                        ("retry_after_ms", &Json::U64(ms)) => info.retry_after_ms = Some(ms),
+                        ("queue_depth", &Json::U64(depth)) => info.queue_depth = Some(depth),
+                        ("reason", reason) => info.reason = Some(try!(<BusyReason as FromJson>::from_json(reason))),
+                        ("retry_after_ms", _) | ("queue_depth", _) => return Err(JsonDecodeError::MalformedObject(json)),
+                        _ => (),
+                    }
+                }
+                Ok(info)
+            },
+            _ =>
+                Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+impl FromJson for BusyReason {
+    fn from_json<'a>(json: &'a Json) -> Result<BusyReason, JsonDecodeError<'a>> {
+        match json {
+            &Json::String(ref token) if *token == "queue_full" =>
+                Ok(BusyReason::QueueFull),
+            &Json::String(ref token) if *token == "memory_pressure" =>
+                Ok(BusyReason::MemoryPressure),
+            &Json::String(ref token) if *token == "shutting_down" =>
+                Ok(BusyReason::ShuttingDown),
+            _ =>
+                Err(JsonDecodeError::UnexpectedToken(json)),
+        }
+    }
+}
+
+const INSERT_OUTCOME_KEYS: &'static [&'static str] = &["inserted_into_cluster", "inserted_as_new_cluster"];
+
+impl FromJson for InsertOutcome {
+    fn from_json<'a>(json: &'a Json) -> Result<InsertOutcome, JsonDecodeError<'a>> {
         match json {
-            &Json::Null => Ok(LookupResult::EmptySet),
-            &Json::Object(ref obj) => match (obj.get("best"), obj.get("neighbours"), obj.get("error")) {
-                (Some(result), None, None) =>
-                    Ok(LookupResult::Best(try!(<Match<UD> as FromJson>::from_json(result)))),
-                (None, Some(workload), None) =>
-                    Ok(LookupResult::Neighbours(try!(<Workload<Match<UD>> as FromJson>::from_json(workload)))),
-                (None, None, Some(message)) =>
-                    Ok(LookupResult::Error(try!(<String as FromJson>::from_json(message)))),
+            &Json::String(ref token) if *token == "not_inserted" =>
+                Ok(InsertOutcome::NotInserted),
+            &Json::Object(ref obj) => match single_entry(obj, INSERT_OUTCOME_KEYS) {
+                Some(("inserted_into_cluster", doc)) => {
+                    let (cluster_id, doc_id) = try!(inserted_from_json(doc));
+                    Ok(InsertOutcome::InsertedIntoCluster { cluster_id: cluster_id, doc_id: doc_id, })
+                },
+                Some(("inserted_as_new_cluster", doc)) => {
+                    let (cluster_id, doc_id) = try!(inserted_from_json(doc));
+                    Ok(InsertOutcome::InsertedAsNewCluster { cluster_id: cluster_id, doc_id: doc_id, })
+                },
                 _ =>
                     Err(JsonDecodeError::MalformedObject(json)),
             },
@@ -427,6 +1679,17 @@ impl<UD> FromJson for LookupResult<UD> where UD: Debug + FromJson {
     }
 }
 
+fn inserted_from_json<'a>(json: &'a Json) -> Result<(u64, Option<u64>), JsonDecodeError<'a>> {
+    match json {
+        &Json::U64(cluster_id) =>
+            Ok((cluster_id, None)),
+        _ =>
+            <DocRef as FromJson>::from_json(json).map(|doc| (doc.cluster_id, Some(doc.doc_id))),
+    }
+}
+
+const REP_KEYS: &'static [&'static str] = &["result", "unexpected", "snapshot_error", "pong", "collections", "too_busy", "unknown_collection", "collection_exists", "result_chunk", "end_of_stream", "cluster", "cluster_not_found", "clusters_merged", "document_moved", "document_not_found", "user_data_updated", "event", "auth_failed", "auth_challenge", "cancelled", "tagged"];
+
 impl<UD> FromJson for Rep<UD> where UD: Debug + FromJson {
     fn from_json<'a>(json: &'a Json) -> Result<Rep<UD>, JsonDecodeError<'a>> {
         match json {
@@ -435,14 +1698,104 @@ impl<UD> FromJson for Rep<UD> where UD: Debug + FromJson {
             &Json::String(ref token) if *token == "terminate_ack" =>
                 Ok(Rep::TerminateAck),
             &Json::String(ref token) if *token == "too_busy" =>
-                Ok(Rep::TooBusy),
+                Ok(Rep::TooBusy(BusyInfo::default())),
             &Json::String(ref token) if *token == "want_crash" =>
                 Ok(Rep::WantCrash),
-            &Json::Object(ref obj) => match (obj.get("result"), obj.get("unexpected")) {
-                (Some(workload), None) =>
+            &Json::String(ref token) if *token == "snapshot_ack" =>
+                Ok(Rep::SnapshotAck),
+            &Json::String(ref token) if *token == "restore_ack" =>
+                Ok(Rep::RestoreAck),
+            &Json::String(ref token) if *token == "create_collection_ack" =>
+                Ok(Rep::CreateCollectionAck),
+            &Json::String(ref token) if *token == "drop_collection_ack" =>
+                Ok(Rep::DropCollectionAck),
+            &Json::String(ref token) if *token == "deadline_exceeded" =>
+                Ok(Rep::DeadlineExceeded),
+            &Json::String(ref token) if *token == "subscribe_ack" =>
+                Ok(Rep::SubscribeAck),
+            &Json::Object(ref obj) => match single_entry(obj, REP_KEYS) {
+                Some(("result", workload)) =>
                     Ok(Rep::Result(try!(<Workload<LookupResult<UD>> as FromJson>::from_json(workload)))),
-                (None, Some(req)) =>
-                    Ok(Rep::Unexpected(try!(<Req<UD> as FromJson>::from_json(req)))),
+                Some(("unexpected", &Json::Object(ref u))) if u.contains_key("reason") =>
+                    match (u.get("reason"), u.get("req"), u.get("kind"), u.len()) {
+                        (Some(reason), Some(req), None, 2) =>
+                            Ok(Rep::Unexpected {
+                                reason: try!(<UnexpectedReason as FromJson>::from_json(reason)),
+                                req: ReqEcho::Full(try!(<Req<UD> as FromJson>::from_json(req))),
+                            }),
+                        (Some(reason), None, Some(kind), 2) =>
+                            Ok(Rep::Unexpected {
+                                reason: try!(<UnexpectedReason as FromJson>::from_json(reason)),
+                                req: ReqEcho::Kind(try!(<ReqKind as FromJson>::from_json(kind))),
+                            }),
+                        _ =>
+                            Err(JsonDecodeError::MalformedObject(json)),
+                    },
+                Some(("unexpected", req)) =>
+                    Ok(Rep::Unexpected { reason: UnexpectedReason::Unspecified, req: ReqEcho::Full(try!(<Req<UD> as FromJson>::from_json(req))), }),
+                Some(("snapshot_error", message)) =>
+                    Ok(Rep::SnapshotError(try!(<String as FromJson>::from_json(message)))),
+                Some(("pong", &Json::U64(nonce))) =>
+                    Ok(Rep::Pong(nonce)),
+                Some(("collections", &Json::Array(ref names))) =>
+                    Ok(Rep::Collections(try!(names.iter().map(|n| <String as FromJson>::from_json(n)).collect()))),
+                Some(("too_busy", info)) =>
+                    Ok(Rep::TooBusy(try!(<BusyInfo as FromJson>::from_json(info)))),
+                Some(("unknown_collection", name)) =>
+                    Ok(Rep::UnknownCollection(try!(<String as FromJson>::from_json(name)))),
+                Some(("collection_exists", name)) =>
+                    Ok(Rep::CollectionExists(try!(<String as FromJson>::from_json(name)))),
+                Some(("result_chunk", &Json::Object(ref chunk))) => match (chunk.get("start"), chunk.get("results")) {
+                    (Some(&Json::U64(start)), Some(&Json::Array(ref results))) if start <= u32::MAX as u64 =>
+                        Ok(Rep::ResultChunk {
+                            start: start as u32,
+                            results: try!(results.iter().map(|r| <LookupResult<UD> as FromJson>::from_json(r)).collect()),
+                        }),
+                    _ =>
+                        Err(JsonDecodeError::MalformedObject(json)),
+                },
+                Some(("end_of_stream", &Json::U64(total))) if total <= u32::MAX as u64 =>
+                    Ok(Rep::EndOfStream(total as u32)),
+                Some(("cluster", info)) =>
+                    Ok(Rep::Cluster(try!(<ClusterInfo<UD> as FromJson>::from_json(info)))),
+                Some(("cluster_not_found", &Json::U64(cluster_id))) =>
+                    Ok(Rep::ClusterNotFound(cluster_id)),
+                Some(("clusters_merged", &Json::Object(ref merged))) => match (merged.get("from"), merged.get("into")) {
+                    (Some(&Json::U64(from)), Some(&Json::U64(into))) =>
+                        Ok(Rep::ClustersMerged { from: from, into: into, }),
+                    _ =>
+                        Err(JsonDecodeError::MalformedObject(json)),
+                },
+                Some(("document_moved", &Json::Object(ref moved))) => match (moved.get("doc"), moved.get("to_cluster")) {
+                    (Some(doc), Some(&Json::U64(to_cluster))) =>
+                        Ok(Rep::DocumentMoved { doc: try!(<DocRef as FromJson>::from_json(doc)), to_cluster: to_cluster, }),
+                    _ =>
+                        Err(JsonDecodeError::MalformedObject(json)),
+                },
+                Some(("document_not_found", doc)) =>
+                    Ok(Rep::DocumentNotFound(try!(<DocRef as FromJson>::from_json(doc)))),
+                Some(("user_data_updated", doc)) =>
+                    Ok(Rep::UserDataUpdated(try!(<DocRef as FromJson>::from_json(doc)))),
+                Some(("event", event)) =>
+                    Ok(Rep::Event(try!(<Event<UD> as FromJson>::from_json(event)))),
+                Some(("auth_failed", reason)) =>
+                    Ok(Rep::AuthFailed(try!(<String as FromJson>::from_json(reason)))),
+                Some(("auth_challenge", &Json::String(ref nonce))) => match nonce.from_base64() {
+                    Ok(nonce) => Ok(Rep::AuthChallenge(nonce)),
+                    Err(..) => Err(JsonDecodeError::MalformedObject(json)),
+                },
+                Some(("cancelled", &Json::Object(ref cancelled))) => match (cancelled.get("id"), cancelled.get("state")) {
+                    (Some(&Json::U64(id)), Some(state)) =>
+                        Ok(Rep::Cancelled(id, try!(<CancelState as FromJson>::from_json(state)))),
+                    _ =>
+                        Err(JsonDecodeError::MalformedObject(json)),
+                },
+                Some(("tagged", &Json::Object(ref tagged))) => match (tagged.get("id"), tagged.get("rep")) {
+                    (Some(&Json::U64(id)), Some(rep)) if !is_entry(rep, REP_KEYS, "tagged") =>
+                        Ok(Rep::Tagged(id, Box::new(try!(<Rep<UD> as FromJson>::from_json(rep))))),
+                    _ =>
+                        Err(JsonDecodeError::MalformedObject(json)),
+                },
                 _ =>
                     Err(JsonDecodeError::MalformedObject(json)),
             },
@@ -455,11 +1808,12 @@ impl<UD> FromJson for Rep<UD> where UD: Debug + FromJson {
 #[cfg(test)]
 mod test {
     use rustc_serialize::json::{ToJson};
-    use super::{FromJson};
+    use super::{FromJson, json_str_to_anything};
+    use super::super::signature;
     use super::super::{
         Workload,
-        Trans, Req, LookupTask, PostAction, InsertCond, ClusterAssign, AssignCond, ClusterChoice, LookupType,
-        Rep, LookupResult, Match
+        Trans, Req, InitParams, Credentials, LookupTask, Document, Signature, PostAction, InsertCond, ClusterAssign, AssignCond, Cond, ClusterChoice, LookupType,
+        Rep, ReqKind, ClusterInfo, Member, DocRef, CancelState, EventFilter, EventKind, Event, UnexpectedReason, ReqEcho, BusyInfo, BusyReason, LookupResult, LookupError, ErrorCode, InsertOutcome, Match, MAX_COND_DEPTH
     };
 
     fn encode_decode<T>(value: T) -> T where T: ToJson + FromJson {
@@ -467,107 +1821,515 @@ mod test {
         <T as FromJson>::from_json(&json).unwrap()
     }
 
-    fn encode_decode_req(req: Trans<String>) -> Trans<String> { encode_decode(req) }
-    fn encode_decode_rep(rep: Rep<String>) -> Rep<String> { encode_decode(rep) }
+    fn encode_decode_req(req: Trans<String>) -> Trans<String> { encode_decode(req) }
+    fn encode_decode_rep(rep: Rep<String>) -> Rep<String> { encode_decode(rep) }
+
+    #[test]
+    fn req_00_async() {
+        match encode_decode_req(Trans::Async(Req::Init(InitParams::default()))) {
+            Trans::Async(Req::Init(InitParams { collection: None, credentials: None, })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_00_sync() {
+        match encode_decode_req(Trans::Sync(Req::Init(InitParams::default()))) {
+            Trans::Sync(Req::Init(InitParams { collection: None, credentials: None, })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_01() {
+        match encode_decode_req(Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+            doc: Document::Text("hello world".to_owned()),
+            result: LookupType::All,
+            post_action: PostAction::None,
+        })))) {
+            Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+                doc: Document::Text(ref lookup_text),
+                result: LookupType::All,
+                post_action: PostAction::None,
+            }))) if lookup_text == "hello world" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_02() {
+        match encode_decode_req(Trans::Async(Req::Lookup(Workload::Single(LookupTask {
+            doc: Document::Text("hello world".to_owned()),
+            result: LookupType::BestOrMine,
+            post_action: PostAction::InsertNew {
+                cond: InsertCond::Always,
+                assign: ClusterAssign {
+                    cond: AssignCond::Always,
+                    choice: ClusterChoice::ServerChoice,
+                },
+                user_data: "some data".to_owned(),
+                idempotency_key: None,
+            },
+        })))) {
+            Trans::Async(Req::Lookup(Workload::Single(LookupTask {
+                doc: Document::Text(ref lookup_text),
+                result: LookupType::BestOrMine,
+                post_action: PostAction::InsertNew {
+                    cond: InsertCond::Always,
+                    assign: ClusterAssign {
+                        cond: AssignCond::Always,
+                        choice: ClusterChoice::ServerChoice,
+                    },
+                    user_data: ref lookup_user_data,
+                    idempotency_key: None,
+                },
+            }))) if lookup_text == "hello world" && lookup_user_data == "some data" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_03() {
+        match encode_decode_req(Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+            doc: Document::Text("hello world".to_owned()),
+            result: LookupType::Best,
+            post_action: PostAction::InsertNew {
+                cond: InsertCond::BestSimLessThan(0.5),
+                assign: ClusterAssign {
+                    cond: AssignCond::Always,
+                    choice: ClusterChoice::ClientChoice(177),
+                },
+                user_data: "some data".to_owned(),
+                idempotency_key: None,
+            },
+        })))) {
+            Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+                doc: Document::Text(ref lookup_text),
+                result: LookupType::Best,
+                post_action: PostAction::InsertNew {
+                    cond: InsertCond::BestSimLessThan(0.5),
+                    assign: ClusterAssign {
+                        cond: AssignCond::Always,
+                        choice: ClusterChoice::ClientChoice(177),
+                    },
+                    user_data: ref lookup_user_data,
+                    idempotency_key: None,
+                },
+            }))) if lookup_text == "hello world" && lookup_user_data == "some data" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_04() {
+        match encode_decode_req(Trans::Sync(Req::Terminate)) {
+            Trans::Sync(Req::Terminate) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_05() {
+        match encode_decode_req(Trans::Sync(Req::Snapshot { path: "/var/lib/dupl/index.snap".to_owned(), })) {
+            Trans::Sync(Req::Snapshot { path: ref p, }) if p == "/var/lib/dupl/index.snap" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_req(Trans::Sync(Req::Restore { path: "/var/lib/dupl/index.snap".to_owned(), })) {
+            Trans::Sync(Req::Restore { path: ref p, }) if p == "/var/lib/dupl/index.snap" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_06() {
+        match encode_decode_req(Trans::Async(Req::Ping(17))) {
+            Trans::Async(Req::Ping(17)) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_07() {
+        match encode_decode_req(Trans::Tagged(42, Box::new(Trans::Sync(Req::Init(InitParams::default()))))) {
+            Trans::Tagged(42, ref trans) => match **trans {
+                Trans::Sync(Req::Init(..)) => (),
+                ref other => panic!("bad inner result: {:?}", other),
+            },
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_08() {
+        match json_str_to_anything::<Trans<String>>(r#"{"async":"terminate"}"#) {
+            Ok(ref trans @ Trans::Async(Req::Terminate)) if trans.id().is_none() => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_09() {
+        match encode_decode_req(Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+            doc: Document::Text("hello world".to_owned()),
+            result: LookupType::TopK(10),
+            post_action: PostAction::None,
+        })))) {
+            Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+                doc: Document::Text(ref lookup_text),
+                result: LookupType::TopK(10),
+                post_action: PostAction::None,
+            }))) if lookup_text == "hello world" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        assert!(json_str_to_anything::<LookupType>(r#"{"top_k":5000000000}"#).is_err());
+    }
+
+    #[test]
+    fn req_10() {
+        match encode_decode::<LookupType>(LookupType::AboveThreshold(0.75)) {
+            LookupType::AboveThreshold(0.75) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode::<LookupType>(LookupType::TopKAboveThreshold(5, 1.0)) {
+            LookupType::TopKAboveThreshold(5, 1.0) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        assert!(json_str_to_anything::<LookupType>(r#"{"above_threshold":1.5}"#).is_err());
+        assert!(json_str_to_anything::<LookupType>(r#"{"above_threshold":2}"#).is_err());
+        assert!(json_str_to_anything::<LookupType>(r#"{"above_threshold":"0.5"}"#).is_err());
+        match json_str_to_anything::<LookupType>(r#"{"above_threshold":1}"#) {
+            Ok(LookupType::AboveThreshold(t)) if t == 1.0 => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<LookupType>(r#"{"top_k":3,"above_threshold":0}"#) {
+            Ok(LookupType::TopKAboveThreshold(3, t)) if t == 0.0 => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        assert!(json_str_to_anything::<LookupType>(r#"{"top_k":3,"above_threshold":-0.1}"#).is_err());
+    }
+
+    #[test]
+    fn req_11() {
+        match json_str_to_anything::<InsertCond>(
+            r#"{"and":[{"best_sim_less_than":0.9},{"text_len_greater_than":200},{"not":{"or":["never",{"no_neighbour_in_cluster":177}]}}]}"#)
+        {
+            Ok(Cond::And(ref conds)) => {
+                match conds.get(0) { Some(&Cond::BestSimLessThan(0.9)) => (), other => panic!("bad cond 0: {:?}", other), }
+                match conds.get(1) { Some(&Cond::TextLenGreaterThan(200)) => (), other => panic!("bad cond 1: {:?}", other), }
+                match conds.get(2) {
+                    Some(&Cond::Not(ref cond)) => match **cond {
+                        Cond::Or(ref conds) => match (conds.get(0), conds.get(1)) {
+                            (Some(&Cond::Never), Some(&Cond::NoNeighbourInCluster(177))) => (),
+                            other => panic!("bad cond 2: {:?}", other),
+                        },
+                        ref other => panic!("bad cond 2: {:?}", other),
+                    },
+                    other => panic!("bad cond 2: {:?}", other),
+                }
+            },
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode::<AssignCond>(Cond::Or(vec![Cond::BestSimGreaterThan(0.5), Cond::Never])) {
+            Cond::Or(ref conds) if conds.len() == 2 => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_12() {
+        match encode_decode::<ClusterAssign>(ClusterAssign { cond: Cond::BestSimGreaterThan(0.7), choice: ClusterChoice::BestMatchCluster, }) {
+            ClusterAssign { cond: Cond::BestSimGreaterThan(0.7), choice: ClusterChoice::BestMatchCluster, } => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode::<ClusterChoice>(ClusterChoice::NewCluster) {
+            ClusterChoice::NewCluster => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_13() {
+        match encode_decode_req(Trans::Sync(Req::Init(InitParams { collection: Some("news".to_owned()), credentials: None, }))) {
+            Trans::Sync(Req::Init(InitParams { collection: Some(ref c), credentials: None, })) if c == "news" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_req(Trans::Sync(Req::CreateCollection("news".to_owned()))) {
+            Trans::Sync(Req::CreateCollection(ref c)) if c == "news" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_req(Trans::Sync(Req::ListCollections)) {
+            Trans::Sync(Req::ListCollections) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_req(Trans::Sync(Req::DropCollection("news".to_owned()))) {
+            Trans::Sync(Req::DropCollection(ref c)) if c == "news" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_14() {
+        match encode_decode_req(Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+            doc: Document::Bytes(vec![0xff, 0x00, 0xfe, 0x80]),
+            result: LookupType::Best,
+            post_action: PostAction::None,
+        })))) {
+            Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+                doc: Document::Bytes(ref bytes),
+                result: LookupType::Best,
+                post_action: PostAction::None,
+            }))) if *bytes == [0xff, 0x00, 0xfe, 0x80] => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<LookupTask<String>>(r#"{"bytes":"/wD+gA==","result":"best","post_action":"none"}"#) {
+            Ok(LookupTask { doc: Document::Bytes(ref bytes), .. }) if *bytes == [0xff, 0x00, 0xfe, 0x80] => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        assert!(json_str_to_anything::<LookupTask<String>>(r#"{"text":"a","bytes":"YQ==","result":"best","post_action":"none"}"#).is_err());
+    }
+
+    #[test]
+    fn req_15() {
+        let signature = signature::minhash("hello, world", 2, 16);
+        match encode_decode_req(Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
+            doc: Document::Signature(signature.clone()),
+            result: LookupType::Best,
+            post_action: PostAction::None,
+        })))) {
+            Trans::Sync(Req::Lookup(Workload::Single(LookupTask { doc: Document::Signature(ref s), .. }))) if *s == signature => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<Document>(r#"{"shingles":{"shingle_len":1,"hashes":["1","2","18446744073709551615"]}}"#) {
+            Ok(Document::Signature(Signature::Shingles { shingle_len: 1, ref hashes, })) if *hashes == [1, 2, ::std::u64::MAX] => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        let doc = Document::Signature(Signature::MinHash { shingle_len: 2, hashes: vec![::std::u64::MAX, 9007199254740993], });
+        assert_eq!(doc.to_json().to_string(), r#"{"minhash":{"hashes":["18446744073709551615","9007199254740993"],"shingle_len":2}}"#);
+        match encode_decode(doc) {
+            Document::Signature(Signature::MinHash { shingle_len: 2, ref hashes, }) if *hashes == [::std::u64::MAX, 9007199254740993] => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        assert!(json_str_to_anything::<Document>(r#"{"shingles":{"shingle_len":1,"hashes":[1,2]}}"#).is_err());
+    }
+
+    #[test]
+    fn req_16() {
+        let trans = encode_decode_req(Trans::Tagged(42, Box::new(Trans::Deadline(1500, Box::new(Trans::Sync(Req::Terminate))))));
+        assert_eq!(trans.id(), Some(42));
+        assert_eq!(trans.deadline_ms(), Some(1500));
+        match trans.req() {
+            &Req::Terminate => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_17() {
+        match json_str_to_anything::<Trans<String>>(
+            r#"{"sync":{"lookup_stream":{"tasks":[{"text":"first","result":"best","post_action":"none"}],"chunk_size":50}}}"#)
+        {
+            Ok(Trans::Sync(Req::LookupStream { ref tasks, chunk_size: 50, })) if tasks.len() == 1 => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_req(Trans::Sync(Req::LookupStream { tasks: vec![], chunk_size: 10, })) {
+            Trans::Sync(Req::LookupStream { ref tasks, chunk_size: 10, }) if tasks.is_empty() => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_18() {
+        match json_str_to_anything::<Trans<String>>(r#"{"async":{"cancel":42}}"#) {
+            Ok(Trans::Async(Req::Cancel(42))) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_19() {
+        let params = InitParams {
+            collection: None,
+            credentials: Some(Credentials::Hmac { key_id: "client-1".to_owned(), nonce: vec![1, 2, 3], mac: vec![0xff, 0x00], }),
+        };
+        match encode_decode_req(Trans::Sync(Req::Init(params))) {
+            Trans::Sync(Req::Init(InitParams { collection: None, credentials: Some(Credentials::Hmac { ref key_id, ref nonce, ref mac, }), }))
+                if key_id == "client-1" && *nonce == [1, 2, 3] && *mac == [0xff, 0x00] => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<Trans<String>>(r#"{"sync":{"init":{"collection":"news","credentials":{"token":"secret"}}}}"#) {
+            Ok(Trans::Sync(Req::Init(InitParams { collection: Some(ref c), credentials: Some(Credentials::Token(ref t)), })))
+                if c == "news" && t == "secret" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        assert!(json_str_to_anything::<Trans<String>>(r#"{"sync":{"init":{"credentials":{"hmac":{"key_id":"k","nonce":"!","mac":""}}}}}"#).is_err());
+    }
+
+    #[test]
+    fn req_20() {
+        match json_str_to_anything::<Trans<String>>(r#"{"async":{"subscribe":{"kinds":["cluster_created"],"cluster_ids":[1,2]}}}"#) {
+            Ok(Trans::Async(Req::Subscribe(EventFilter { ref kinds, cluster_ids: Some(ref cluster_ids), })))
+                if *kinds == [EventKind::ClusterCreated] && *cluster_ids == [1, 2] => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_req(Trans::Async(Req::Subscribe(EventFilter { kinds: vec![EventKind::DocumentInserted], cluster_ids: None, }))) {
+            Trans::Async(Req::Subscribe(EventFilter { ref kinds, cluster_ids: None, })) if *kinds == [EventKind::DocumentInserted] => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_21() {
+        match json_str_to_anything::<PostAction<String>>(
+            r#"{"cond":"always","assign":{"cond":"always","choice":"server_choice"},"user_data":"some data","idempotency_key":"upload-17"}"#)
+        {
+            Ok(PostAction::InsertNew { idempotency_key: Some(ref key), .. }) if key == "upload-17" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        let post_action = PostAction::InsertNew {
+            cond: InsertCond::Always,
+            assign: ClusterAssign { cond: AssignCond::Always, choice: ClusterChoice::ServerChoice, },
+            user_data: "some data".to_owned(),
+            idempotency_key: None,
+        };
+        assert!(post_action.to_json().find("idempotency_key").is_none());
+        match encode_decode(post_action) {
+            PostAction::InsertNew { idempotency_key: None, .. } => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_22() {
+        match json_str_to_anything::<Trans<String>>(r#"{"sync":{"fetch_more":"page-2"}}"#) {
+            Ok(Trans::Sync(Req::FetchMore(ref cursor))) if cursor == "page-2" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_23() {
+        let json = r#"{"sync":{"lookup_batch":{
+            "result":"best",
+            "insert_cond":{"best_sim_less_than":0.5},
+            "assign":{"cond":"always","choice":"server_choice"},
+            "tasks":[
+                {"text":"first","insert":{"user_data":"doc 1"}},
+                {"text":"second","result":{"top_k":3}},
+                {"text":"third","insert":{"user_data":"doc 3","cond":"never","idempotency_key":"k3"}}
+            ]}}}"#;
+        let batch = match json_str_to_anything::<Trans<String>>(json) {
+            Ok(Trans::Sync(Req::LookupBatch(batch))) => batch,
+            other => panic!("bad result: {:?}", other),
+        };
+        let tasks = match encode_decode_req(Trans::Sync(Req::LookupBatch(batch))) {
+            Trans::Sync(Req::LookupBatch(batch)) => batch.expand(),
+            other => panic!("bad result: {:?}", other),
+        };
+        match (&tasks[0].result, &tasks[0].post_action) {
+            (&LookupType::Best, &PostAction::InsertNew { cond: Cond::BestSimLessThan(..), ref user_data, idempotency_key: None, .. })
+                if user_data == "doc 1" => (),
+            other => panic!("bad task: {:?}", other),
+        }
+        match (&tasks[1].result, &tasks[1].post_action) {
+            (&LookupType::TopK(3), &PostAction::None) => (),
+            other => panic!("bad task: {:?}", other),
+        }
+        match (&tasks[2].result, &tasks[2].post_action) {
+            (&LookupType::Best, &PostAction::InsertNew { cond: Cond::Never, idempotency_key: Some(ref key), .. }) if key == "k3" => (),
+            other => panic!("bad task: {:?}", other),
+        }
+    }
 
     #[test]
-    fn req_00_async() {
-        match encode_decode_req(Trans::Async(Req::Init)) {
-            Trans::Async(Req::Init) => (),
+    fn req_24() {
+        match json_str_to_anything::<Trans<String>>(r#"{"sync":{"get_cluster":{"cluster_id":177,"offset":0}}}"#) {
+            Ok(Trans::Sync(Req::GetCluster { cluster_id: 177, offset: 0, limit: None, })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_req(Trans::Sync(Req::GetCluster { cluster_id: 177, offset: 100, limit: Some(50), })) {
+            Trans::Sync(Req::GetCluster { cluster_id: 177, offset: 100, limit: Some(50), }) => (),
             other => panic!("bad result: {:?}", other),
         }
     }
 
     #[test]
-    fn req_00_sync() {
-        match encode_decode_req(Trans::Sync(Req::Init)) {
-            Trans::Sync(Req::Init) => (),
+    fn req_25() {
+        match json_str_to_anything::<Trans<String>>(r#"{"sync":{"merge_clusters":{"from":177,"into":12}}}"#) {
+            Ok(Trans::Sync(Req::MergeClusters { from: 177, into: 12, })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<Trans<String>>(r#"{"sync":{"move_document":{"doc":{"cluster_id":177,"doc_id":5}}}}"#) {
+            Ok(Trans::Sync(Req::MoveDocument { doc: DocRef { cluster_id: 177, doc_id: 5, }, to_cluster: None, })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        let doc = DocRef { cluster_id: 177, doc_id: 5, };
+        match encode_decode_req(Trans::Sync(Req::MoveDocument { doc: doc, to_cluster: Some(12), })) {
+            Trans::Sync(Req::MoveDocument { doc: DocRef { cluster_id: 177, doc_id: 5, }, to_cluster: Some(12), }) => (),
             other => panic!("bad result: {:?}", other),
         }
     }
 
     #[test]
-    fn req_01() {
-        match encode_decode_req(Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
-            text: "hello world".to_owned(),
-            result: LookupType::All,
-            post_action: PostAction::None,
-        })))) {
-            Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
-                text: ref lookup_text,
-                result: LookupType::All,
-                post_action: PostAction::None,
-            }))) if lookup_text == "hello world" => (),
+    fn req_26() {
+        match json_str_to_anything::<Trans<String>>(r#"{"sync":{"update_user_data":{"doc":{"cluster_id":177,"doc_id":5},"user_data":"new url"}}}"#) {
+            Ok(Trans::Sync(Req::UpdateUserData { doc: DocRef { cluster_id: 177, doc_id: 5, }, ref user_data, })) =>
+                assert_eq!(user_data, "new url"),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_req(Trans::Sync(Req::UpdateUserData { doc: DocRef { cluster_id: 1, doc_id: 2, }, user_data: "x".to_owned(), })) {
+            Trans::Sync(Req::UpdateUserData { doc: DocRef { cluster_id: 1, doc_id: 2, }, .. }) => (),
             other => panic!("bad result: {:?}", other),
         }
     }
 
     #[test]
-    fn req_02() {
-        match encode_decode_req(Trans::Async(Req::Lookup(Workload::Single(LookupTask {
-            text: "hello world".to_owned(),
-            result: LookupType::BestOrMine,
-            post_action: PostAction::InsertNew {
-                cond: InsertCond::Always,
-                assign: ClusterAssign {
-                    cond: AssignCond::Always,
-                    choice: ClusterChoice::ServerChoice,
-                },
-                user_data: "some data".to_owned(),
-            },
-        })))) {
-            Trans::Async(Req::Lookup(Workload::Single(LookupTask {
-                text: ref lookup_text,
-                result: LookupType::BestOrMine,
-                post_action: PostAction::InsertNew {
-                    cond: InsertCond::Always,
-                    assign: ClusterAssign {
-                        cond: AssignCond::Always,
-                        choice: ClusterChoice::ServerChoice,
-                    },
-                    user_data: ref lookup_user_data,
-                },
-            }))) if lookup_text == "hello world" && lookup_user_data == "some data" => (),
+    fn req_27() {
+        match json_str_to_anything::<Trans<String>>(r#"{"sync":"init","x":1}"#) {
+            Ok(Trans::Sync(Req::Init(InitParams { collection: None, credentials: None, }))) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<Trans<String>>(r#"{"sync":{"ping":7,"trace":"abc"}}"#) {
+            Ok(Trans::Sync(Req::Ping(7))) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<Trans<String>>(r#"{"sync":"terminate","async":"terminate"}"#) {
+            Err(..) => (),
             other => panic!("bad result: {:?}", other),
         }
     }
 
     #[test]
-    fn req_03() {
-        match encode_decode_req(Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
-            text: "hello world".to_owned(),
-            result: LookupType::Best,
-            post_action: PostAction::InsertNew {
-                cond: InsertCond::BestSimLessThan(0.5),
-                assign: ClusterAssign {
-                    cond: AssignCond::Always,
-                    choice: ClusterChoice::ClientChoice(177),
-                },
-                user_data: "some data".to_owned(),
-            },
-        })))) {
-            Trans::Sync(Req::Lookup(Workload::Single(LookupTask {
-                text: ref lookup_text,
-                result: LookupType::Best,
-                post_action: PostAction::InsertNew {
-                    cond: InsertCond::BestSimLessThan(0.5),
-                    assign: ClusterAssign {
-                        cond: AssignCond::Always,
-                        choice: ClusterChoice::ClientChoice(177),
-                    },
-                    user_data: ref lookup_user_data,
-                },
-            }))) if lookup_text == "hello world" && lookup_user_data == "some data" => (),
+    fn req_28() {
+        assert!(json_str_to_anything::<Trans<String>>(r#"{"tagged":{"id":1,"trans":{"tagged":{"id":2,"trans":{"sync":"terminate"}}}}}"#).is_err());
+    }
+
+    #[test]
+    fn req_29() {
+        let nested = |depth: usize| (0 .. depth).fold(r#""always""#.to_owned(), |cond, _| format!(r#"{{"not":{}}}"#, cond));
+        assert!(json_str_to_anything::<Cond>(&nested(MAX_COND_DEPTH)).is_ok());
+        assert!(json_str_to_anything::<Cond>(&nested(MAX_COND_DEPTH + 1)).is_err());
+        let nested_and = (0 .. MAX_COND_DEPTH + 1).fold(r#""never""#.to_owned(), |cond, _| format!(r#"{{"and":["always",{}]}}"#, cond));
+        assert!(json_str_to_anything::<Cond>(&nested_and).is_err());
+    }
+
+    #[test]
+    fn req_30() {
+        assert!(json_str_to_anything::<Trans<String>>(r#"{"deadline":{"ms":1500,"trans":{"tagged":{"id":42,"trans":{"sync":"terminate"}}}}}"#).is_err());
+        assert!(json_str_to_anything::<Trans<String>>(r#"{"deadline":{"ms":1500,"trans":{"deadline":{"ms":500,"trans":{"sync":"terminate"}}}}}"#).is_err());
+        match json_str_to_anything::<Trans<String>>(r#"{"tagged":{"id":42,"trans":{"deadline":{"ms":1500,"trans":{"sync":"terminate"}}}}}"#) {
+            Ok(Trans::Tagged(42, ref trans)) => assert_eq!(trans.deadline_ms(), Some(1500)),
             other => panic!("bad result: {:?}", other),
         }
     }
 
     #[test]
-    fn req_04() {
-        match encode_decode_req(Trans::Sync(Req::Terminate)) {
-            Trans::Sync(Req::Terminate) => (),
+    fn req_31() {
+        match json_str_to_anything::<Document>(r#"{"text":"hello world","lang":"en"}"#) {
+            Ok(Document::Text(ref text)) if text == "hello world" => (),
             other => panic!("bad result: {:?}", other),
         }
+        assert!(json_str_to_anything::<Document>(r#"{"text":"hello world","bytes":"AJ+SlA=="}"#).is_err());
+        assert!(json_str_to_anything::<Document>(r#"{"lang":"en"}"#).is_err());
     }
 
     #[test]
@@ -588,8 +2350,8 @@ mod test {
 
     #[test]
     fn rep_02() {
-        match encode_decode_rep(Rep::TooBusy) {
-            Rep::TooBusy => (),
+        match encode_decode_rep(Rep::TooBusy(BusyInfo::default())) {
+            Rep::TooBusy(ref info) if info.is_default() => (),
             other => panic!("bad result: {:?}", other),
         }
     }
@@ -614,15 +2376,348 @@ mod test {
     fn rep_05() {
         match encode_decode_rep(Rep::Result(Workload::Single(LookupResult::Best(Match {
             cluster_id: 177,
+            doc_id: Some(5),
             similarity: 0.5,
             user_data: "some data".to_owned(),
         })))) {
             Rep::Result(Workload::Single(LookupResult::Best(Match {
                 cluster_id: 177,
+                doc_id: Some(5),
                 similarity: 0.5,
                 user_data: ref match_user_data,
             }))) if match_user_data == "some data" => (),
             other => panic!("bad result: {:?}", other),
         }
     }
+
+    #[test]
+    fn rep_06() {
+        match encode_decode_rep(Rep::SnapshotAck) {
+            Rep::SnapshotAck => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::RestoreAck) {
+            Rep::RestoreAck => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::SnapshotError("no such file".to_owned())) {
+            Rep::SnapshotError(ref e) if e == "no such file" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_07() {
+        match encode_decode_rep(Rep::Pong(17)) {
+            Rep::Pong(17) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_08() {
+        match encode_decode_rep(Rep::reply_to(Some(42), Rep::TooBusy(BusyInfo::default()))) {
+            Rep::Tagged(42, ref rep) => match **rep {
+                Rep::TooBusy(..) => (),
+                ref other => panic!("bad inner result: {:?}", other),
+            },
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_09() {
+        match encode_decode_rep(Rep::Result(Workload::Many(vec![
+            LookupResult::WithOutcome(InsertOutcome::NotInserted, Box::new(LookupResult::Best(Match {
+                cluster_id: 177,
+                doc_id: Some(5),
+                similarity: 0.95,
+                user_data: "some data".to_owned(),
+            }))),
+            LookupResult::WithOutcome(InsertOutcome::InsertedIntoCluster { cluster_id: 177, doc_id: Some(5), }, Box::new(LookupResult::EmptySet)),
+            LookupResult::WithOutcome(InsertOutcome::InsertedAsNewCluster { cluster_id: 178, doc_id: Some(6), }, Box::new(LookupResult::EmptySet)),
+        ]))) {
+            Rep::Result(Workload::Many(ref results)) => {
+                match results.get(0) {
+                    Some(&LookupResult::WithOutcome(InsertOutcome::NotInserted, ref result)) => match **result {
+                        LookupResult::Best(Match { cluster_id: 177, .. }) => (),
+                        ref other => panic!("bad result 0: {:?}", other),
+                    },
+                    other => panic!("bad result 0: {:?}", other),
+                }
+                match results.get(1).and_then(|r| r.insert_outcome()) {
+                    Some(&InsertOutcome::InsertedIntoCluster { cluster_id: 177, doc_id: Some(5), }) => (),
+                    other => panic!("bad result 1: {:?}", other),
+                }
+                match results.get(2).and_then(|r| r.insert_outcome()) {
+                    Some(&InsertOutcome::InsertedAsNewCluster { cluster_id: 178, doc_id: Some(6), }) => (),
+                    other => panic!("bad result 2: {:?}", other),
+                }
+            },
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_10() {
+        match encode_decode_rep(Rep::Collections(vec!["news".to_owned(), "blogs".to_owned()])) {
+            Rep::Collections(ref names) if *names == ["news".to_owned(), "blogs".to_owned()] => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::UnknownCollection("forums".to_owned())) {
+            Rep::UnknownCollection(ref name) if name == "forums" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::CreateCollectionAck) {
+            Rep::CreateCollectionAck => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_11() {
+        match encode_decode_rep(Rep::DeadlineExceeded) {
+            Rep::DeadlineExceeded => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_12() {
+        let info = BusyInfo { retry_after_ms: Some(250), queue_depth: None, reason: Some(BusyReason::QueueFull), };
+        match encode_decode_rep(Rep::TooBusy(info)) {
+            Rep::TooBusy(BusyInfo { retry_after_ms: Some(250), queue_depth: None, reason: Some(BusyReason::QueueFull), }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<Rep<String>>(r#"{"too_busy":{"queue_depth":17,"reason":"shutting_down"}}"#) {
+            Ok(Rep::TooBusy(BusyInfo { retry_after_ms: None, queue_depth: Some(17), reason: Some(BusyReason::ShuttingDown), })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        assert_eq!(Rep::TooBusy::<String>(BusyInfo::default()).to_json().to_string(), r#""too_busy""#);
+        assert!(json_str_to_anything::<Rep<String>>(r#"{"too_busy":{"reason":"bored"}}"#).is_err());
+        assert!(json_str_to_anything::<Rep<String>>(r#"{"too_busy":{"queue_depth":"17"}}"#).is_err());
+        match json_str_to_anything::<Rep<String>>(r#"{"too_busy":{"retry_after_ms":250,"region":"eu"}}"#) {
+            Ok(Rep::TooBusy(BusyInfo { retry_after_ms: Some(250), queue_depth: None, reason: None, })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_13() {
+        let error = LookupError::new(ErrorCode::InvalidClusterChoice, "no cluster 177".to_owned());
+        match encode_decode_rep(Rep::Result(Workload::Single(LookupResult::Error(error)))) {
+            Rep::Result(Workload::Single(LookupResult::Error(LookupError { code: ErrorCode::InvalidClusterChoice, retryable: false, ref message, })))
+                if message == "no cluster 177" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<LookupResult<String>>(r#"{"error":{"code":"deadline_exceeded","retryable":true,"message":""}}"#) {
+            Ok(LookupResult::Error(LookupError { code: ErrorCode::DeadlineExceeded, retryable: true, .. })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<LookupResult<String>>(r#"{"error":"oops"}"#) {
+            Ok(LookupResult::Error(ref e)) if e.is_legacy() && e.message == "oops" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        assert_eq!(LookupResult::Error::<String>(LookupError::other("oops".to_owned())).to_json().to_string(), r#"{"error":"oops"}"#);
+    }
+
+    #[test]
+    fn rep_14() {
+        match encode_decode_rep(Rep::Unexpected { reason: UnexpectedReason::NotInitialized, req: ReqEcho::Kind(ReqKind::Lookup), }) {
+            Rep::Unexpected { reason: UnexpectedReason::NotInitialized, req: ReqEcho::Kind(ReqKind::Lookup), } => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::Unexpected { reason: UnexpectedReason::Terminated, req: ReqEcho::Full(Req::Ping(7)), }) {
+            Rep::Unexpected { reason: UnexpectedReason::Terminated, req: ReqEcho::Full(Req::Ping(7)), } => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<Rep<String>>(r#"{"unexpected":"init"}"#) {
+            Ok(Rep::Unexpected { reason: UnexpectedReason::Unspecified, req: ReqEcho::Full(Req::Init(..)), }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        assert!(json_str_to_anything::<Rep<String>>(r#"{"unexpected":{"reason":"terminated","req":"init","kind":"init"}}"#).is_err());
+    }
+
+    #[test]
+    fn rep_15() {
+        match encode_decode_rep(Rep::ResultChunk { start: 100, results: vec![LookupResult::EmptySet, LookupResult::EmptySet], }) {
+            Rep::ResultChunk { start: 100, ref results, } if results.len() == 2 => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::EndOfStream(102)) {
+            Rep::EndOfStream(102) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_16() {
+        match encode_decode_rep(Rep::Cancelled(42, CancelState::Cancelled { completed: 17, })) {
+            Rep::Cancelled(42, CancelState::Cancelled { completed: 17, }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<Rep<String>>(r#"{"cancelled":{"id":43,"state":"not_found"}}"#) {
+            Ok(Rep::Cancelled(43, CancelState::NotFound)) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_17() {
+        match encode_decode_rep(Rep::AuthFailed("unknown token".to_owned())) {
+            Rep::AuthFailed(ref reason) if reason == "unknown token" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_18() {
+        match encode_decode_rep(Rep::Event(Event::ClusterCreated { cluster_id: 7, doc_id: Some(5), user_data: "doc 1".to_owned(), })) {
+            Rep::Event(Event::ClusterCreated { cluster_id: 7, doc_id: Some(5), ref user_data, }) if user_data == "doc 1" => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<Rep<String>>(r#"{"event":{"document_inserted":{"cluster_id":7,"user_data":"doc 1"}}}"#) {
+            Ok(Rep::Event(Event::DocumentInserted { cluster_id: 7, doc_id: None, .. })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<Rep<String>>(r#"{"event":{"document_moved":{"cluster_id":9,"doc_id":5,"from_cluster":7}}}"#) {
+            Ok(Rep::Event(Event::DocumentMoved { doc: DocRef { cluster_id: 9, doc_id: 5, }, from_cluster: 7, })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::Event(Event::DocumentMoved { doc: DocRef { cluster_id: 9, doc_id: 5, }, from_cluster: 7, })) {
+            Rep::Event(Event::DocumentMoved { doc: DocRef { cluster_id: 9, doc_id: 5, }, from_cluster: 7, }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        assert!(json_str_to_anything::<Rep<String>>(r#"{"event":{"document_moved":{"cluster_id":9,"from_cluster":7}}}"#).is_err());
+        match json_str_to_anything::<Rep<String>>(r#"{"event":{"cluster_deleted":{"cluster_id":8}}}"#) {
+            Ok(Rep::Event(Event::ClusterDeleted { cluster_id: 8, })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::SubscribeAck) {
+            Rep::SubscribeAck => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_19() {
+        let page = LookupResult::NeighboursPage {
+            matches: vec![Match { cluster_id: 1, doc_id: Some(5), similarity: 0.9, user_data: "doc 1".to_owned(), }],
+            next: Some("page-2".to_owned()),
+        };
+        match encode_decode_rep(Rep::Result(Workload::Single(page))) {
+            Rep::Result(Workload::Single(ref result @ LookupResult::NeighboursPage { .. })) => {
+                assert_eq!(result.next_cursor().map(|c| &c[..]), Some("page-2"));
+            },
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<LookupResult<String>>(r#"{"neighbours_page":{"matches":[]}}"#) {
+            Ok(LookupResult::NeighboursPage { ref matches, next: None, }) if matches.is_empty() => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_20() {
+        let info = ClusterInfo {
+            cluster_id: 177,
+            size: 3,
+            representative: Some(Document::Bytes(vec![0xff, 0x00])),
+            members: vec![Member { doc_id: 1, user_data: "doc 1".to_owned(), }],
+        };
+        match encode_decode_rep(Rep::Cluster(info)) {
+            Rep::Cluster(ClusterInfo { cluster_id: 177, size: 3, representative: Some(Document::Bytes(ref bytes)), ref members, }) => {
+                assert_eq!(*bytes, [0xff, 0x00]);
+                assert_eq!(members.len(), 1);
+                assert_eq!(members[0].doc_id, 1);
+            },
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<Rep<String>>(r#"{"cluster":{"cluster_id":1,"size":0,"members":[]}}"#) {
+            Ok(Rep::Cluster(ClusterInfo { cluster_id: 1, size: 0, representative: None, .. })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::ClusterNotFound(178)) {
+            Rep::ClusterNotFound(178) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_21() {
+        let doc = DocRef { cluster_id: 177, doc_id: 5, };
+        match encode_decode_rep(Rep::ClustersMerged { from: 177, into: 12, }) {
+            Rep::ClustersMerged { from: 177, into: 12, } => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<Rep<String>>(r#"{"document_moved":{"doc":{"cluster_id":177,"doc_id":5},"to_cluster":178}}"#) {
+            Ok(Rep::DocumentMoved { doc: DocRef { cluster_id: 177, doc_id: 5, }, to_cluster: 178, }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match encode_decode_rep(Rep::DocumentNotFound(doc)) {
+            Rep::DocumentNotFound(DocRef { cluster_id: 177, doc_id: 5, }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_22() {
+        match encode_decode_rep(Rep::UserDataUpdated(DocRef { cluster_id: 177, doc_id: 5, })) {
+            Rep::UserDataUpdated(DocRef { cluster_id: 177, doc_id: 5, }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_23() {
+        assert!(json_str_to_anything::<Rep<String>>(r#"{"tagged":{"id":1,"rep":{"tagged":{"id":2,"rep":"init_ack"}}}}"#).is_err());
+    }
+
+    #[test]
+    fn rep_24() {
+        assert!(json_str_to_anything::<LookupResult<String>>(
+            r#"{"with_outcome":{"outcome":"not_inserted","result":{"with_outcome":{"outcome":"not_inserted","result":null}}}}"#).is_err());
+        match json_str_to_anything::<LookupResult<String>>(r#"{"with_outcome":{"outcome":"not_inserted","result":null}}"#) {
+            Ok(LookupResult::WithOutcome(InsertOutcome::NotInserted, _)) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_25() {
+        match json_str_to_anything::<Rep<String>>(r#"{"auth_challenge":"BwD/"}"#) {
+            Ok(Rep::AuthChallenge(ref nonce)) if *nonce == [7, 0, 255] => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        assert!(json_str_to_anything::<Rep<String>>(r#"{"auth_challenge":"!"}"#).is_err());
+        match encode_decode_rep(Rep::AuthChallenge(vec![1, 2, 3])) {
+            Rep::AuthChallenge(ref nonce) if *nonce == [1, 2, 3] => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rep_26() {
+        match json_str_to_anything::<LookupResult<String>>(r#"{"best":{"cluster_id":1,"doc_id":9,"similarity":0.5,"user_data":"x"}}"#) {
+            Ok(LookupResult::Best(Match { cluster_id: 1, doc_id: Some(9), .. })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<LookupResult<String>>(r#"{"best":{"cluster_id":1,"similarity":0.5,"user_data":"x"}}"#) {
+            Ok(LookupResult::Best(Match { cluster_id: 1, doc_id: None, .. })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        assert!(json_str_to_anything::<LookupResult<String>>(r#"{"best":{"cluster_id":1,"doc_id":"9","similarity":0.5,"user_data":"x"}}"#).is_err());
+        match json_str_to_anything::<InsertOutcome>(r#"{"inserted_as_new_cluster":{"cluster_id":3,"doc_id":9}}"#) {
+            Ok(InsertOutcome::InsertedAsNewCluster { cluster_id: 3, doc_id: Some(9), }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match json_str_to_anything::<InsertOutcome>(r#"{"inserted_into_cluster":3}"#) {
+            Ok(InsertOutcome::InsertedIntoCluster { cluster_id: 3, doc_id: None, }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        let legacy = InsertOutcome::InsertedIntoCluster { cluster_id: 3, doc_id: None, };
+        assert_eq!(legacy.to_json().to_string(), r#"{"inserted_into_cluster":3}"#);
+        let legacy = Match { cluster_id: 1, doc_id: None, similarity: 0.5, user_data: "x".to_owned(), };
+        assert_eq!(legacy.to_json().to_string(), r#"{"cluster_id":1,"similarity":0.5,"user_data":"x"}"#);
+    }
 }
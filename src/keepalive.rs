@@ -0,0 +1,126 @@
+//! Client side liveness checking with `Req::Ping` / `Rep::Pong`.
+//!
+//! `Keepalive` is a transport agnostic state machine: the client calls `poll` whenever it is
+//! about to block on the connection and acts on the returned `Action`, and feeds every received
+//! `Rep::Pong` into `pong`. A server is declared dead when a ping stays unanswered for longer
+//! than the configured timeout, so a dead server is detected at most `interval + timeout` after
+//! the last sign of life.
+
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub enum Action {
+    /// Nothing to do for the given duration.
+    Wait(Duration),
+    /// Send `Req::Ping` with the given nonce now and expect `Rep::Pong` with the same nonce.
+    Ping(u64),
+    /// The server did not answer the last ping in time.
+    Dead,
+}
+
+#[derive(Debug)]
+pub struct Keepalive {
+    interval: Duration,
+    timeout: Duration,
+    last_seen: Instant,
+    next_nonce: u64,
+    outstanding: Option<(u64, Instant)>,
+}
+
+impl Keepalive {
+    pub fn new(interval: Duration, timeout: Duration, now: Instant) -> Keepalive {
+        Keepalive {
+            interval: interval,
+            timeout: timeout,
+            last_seen: now,
+            next_nonce: 1,
+            outstanding: None,
+        }
+    }
+
+    /// Records any traffic received from the server, postponing the next ping.
+    pub fn activity(&mut self, now: Instant) {
+        self.last_seen = now;
+    }
+
+    /// Handles a received `Rep::Pong`. Returns false if the nonce does not match the outstanding ping.
+    pub fn pong(&mut self, nonce: u64, now: Instant) -> bool {
+        match self.outstanding {
+            Some((expected, _)) if expected == nonce => {
+                self.outstanding = None;
+                self.last_seen = now;
+                true
+            },
+            _ =>
+                false,
+        }
+    }
+
+    pub fn poll(&mut self, now: Instant) -> Action {
+        match self.outstanding {
+            Some((_, sent_at)) => {
+                let elapsed = now.duration_since(sent_at);
+                if elapsed >= self.timeout {
+                    Action::Dead
+                } else {
+                    Action::Wait(self.timeout - elapsed)
+                }
+            },
+            None => {
+                let idle = now.duration_since(self.last_seen);
+                if idle >= self.interval {
+                    let nonce = self.next_nonce;
+                    self.next_nonce = self.next_nonce.wrapping_add(1);
+                    self.outstanding = Some((nonce, now));
+                    Action::Ping(nonce)
+                } else {
+                    Action::Wait(self.interval - idle)
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+    use super::{Keepalive, Action};
+
+    #[test]
+    fn alive() {
+        let start = Instant::now();
+        let mut keepalive = Keepalive::new(Duration::from_secs(10), Duration::from_secs(3), start);
+        match keepalive.poll(start + Duration::from_secs(4)) {
+            Action::Wait(d) if d == Duration::from_secs(6) => (),
+            other => panic!("bad action: {:?}", other),
+        }
+        let nonce = match keepalive.poll(start + Duration::from_secs(10)) {
+            Action::Ping(nonce) => nonce,
+            other => panic!("bad action: {:?}", other),
+        };
+        assert!(!keepalive.pong(nonce + 1, start + Duration::from_secs(11)));
+        assert!(keepalive.pong(nonce, start + Duration::from_secs(11)));
+        match keepalive.poll(start + Duration::from_secs(12)) {
+            Action::Wait(d) if d == Duration::from_secs(9) => (),
+            other => panic!("bad action: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn dead() {
+        let start = Instant::now();
+        let mut keepalive = Keepalive::new(Duration::from_secs(10), Duration::from_secs(3), start);
+        match keepalive.poll(start + Duration::from_secs(10)) {
+            Action::Ping(..) => (),
+            other => panic!("bad action: {:?}", other),
+        }
+        match keepalive.poll(start + Duration::from_secs(12)) {
+            Action::Wait(d) if d == Duration::from_secs(1) => (),
+            other => panic!("bad action: {:?}", other),
+        }
+        match keepalive.poll(start + Duration::from_secs(13)) {
+            Action::Dead => (),
+            other => panic!("bad action: {:?}", other),
+        }
+    }
+}
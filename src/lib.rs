@@ -1,22 +1,199 @@
 extern crate byteorder;
 extern crate rustc_serialize;
 
+use std::fmt;
 use std::fmt::Debug;
+use std::cmp::Ordering;
+use std::time::{Duration, Instant};
 
 pub mod bin;
 pub mod json;
+pub mod snapshot;
+pub mod keepalive;
+pub mod cond;
+pub mod signature;
+pub mod retry;
+pub mod stream;
+pub mod auth;
+
+pub type RequestId = u64;
+
+/// Opaque continuation token of a paginated result, see `LookupResult::NeighboursPage`.
+pub type Cursor = String;
 
 #[derive(Debug)]
 pub enum Trans<UD> where UD: Debug {
     Async(Req<UD>),
     Sync(Req<UD>),
+    /// Transaction carrying a client chosen id: the server wraps its reply into `Rep::Tagged` with the same id.
+    /// Decoders reject a `Tagged` nested into another one.
+    Tagged(RequestId, Box<Trans<UD>>),
+    /// Transaction which should be abandoned if it is not done within the given number of milliseconds
+    /// since the server received it. The server then replies `Rep::DeadlineExceeded` instead; effects of
+    /// post actions which were already applied are kept. It wraps `Async` or `Sync` directly and can
+    /// only be wrapped into `Tagged`, decoders reject any other nesting.
+    Deadline(u64, Box<Trans<UD>>),
+}
+
+impl<UD> Trans<UD> where UD: Debug {
+    pub fn id(&self) -> Option<RequestId> {
+        match self {
+            &Trans::Async(..) | &Trans::Sync(..) => None,
+            &Trans::Tagged(id, _) => Some(id),
+            &Trans::Deadline(_, ref trans) => trans.id(),
+        }
+    }
+
+    pub fn deadline_ms(&self) -> Option<u64> {
+        match self {
+            &Trans::Async(..) | &Trans::Sync(..) => None,
+            &Trans::Tagged(_, ref trans) => trans.deadline_ms(),
+            &Trans::Deadline(ms, _) => Some(ms),
+        }
+    }
+
+    /// Absolute deadline for a transaction received at `received_at`.
+    pub fn expires_at(&self, received_at: Instant) -> Option<Instant> {
+        self.deadline_ms().map(|ms| received_at + Duration::from_millis(ms))
+    }
+
+    pub fn req(&self) -> &Req<UD> {
+        match self {
+            &Trans::Async(ref req) | &Trans::Sync(ref req) => req,
+            &Trans::Tagged(_, ref trans) | &Trans::Deadline(_, ref trans) => trans.req(),
+        }
+    }
+
+    pub fn into_req(self) -> Req<UD> {
+        match self {
+            Trans::Async(req) | Trans::Sync(req) => req,
+            Trans::Tagged(_, trans) | Trans::Deadline(_, trans) => trans.into_req(),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum Req<UD> where UD: Debug {
-    Init,
+    Init(InitParams),
     Lookup(Workload<LookupTask<UD>>),
     Terminate,
+    Snapshot { path: String, },
+    Restore { path: String, },
+    Ping(u64),
+    CreateCollection(String),
+    ListCollections,
+    DropCollection(String),
+    /// Lookup answered with a sequence of `Rep::ResultChunk` of at most `chunk_size` results each,
+    /// followed by `Rep::EndOfStream`.
+    LookupStream { tasks: Vec<LookupTask<UD>>, chunk_size: u32, },
+    /// Revokes a pending transaction sent with `Trans::Tagged` under the given id. The cancel itself is
+    /// always answered with one `Rep::Cancelled`, and a transaction it stops sends no reply of its own.
+    /// A transaction that already finished keeps its normal reply and gets `CancelState::NotFound`.
+    Cancel(RequestId),
+    /// Starts receiving `Rep::Event` for every change matching the filter, replacing any previous subscription.
+    Subscribe(EventFilter),
+    /// Requests the page following `LookupResult::NeighboursPage` with this cursor, answered with a single
+    /// `Rep::Result` holding the next page or an `ErrorCode::InvalidCursor` error.
+    FetchMore(Cursor),
+    /// Compact form of `Lookup(Workload::Many(batch.expand()))`, answered exactly like it.
+    LookupBatch(Batch<UD>),
+    /// Lists the members of a cluster, skipping the first `offset` and returning at most `limit`
+    /// (all remaining when `None`). Answered with `Rep::Cluster` or `Rep::ClusterNotFound`.
+    GetCluster { cluster_id: u64, offset: u64, limit: Option<u32>, },
+    /// Moves every document of cluster `from` into cluster `into` and removes `from`. Later
+    /// `Match`es of those documents report `into`, their doc ids do not change. Answered with
+    /// `Rep::ClustersMerged` or `Rep::ClusterNotFound`.
+    MergeClusters { from: u64, into: u64, },
+    /// Moves a single document to cluster `to_cluster`, or splits it off into a new cluster when
+    /// `None`. Answered with `Rep::DocumentMoved`, `Rep::DocumentNotFound` or `Rep::ClusterNotFound`.
+    MoveDocument { doc: DocRef, to_cluster: Option<u64>, },
+    /// Replaces the user data stored with an inserted document, later `Match`es carry the new value.
+    /// Answered with `Rep::UserDataUpdated` or `Rep::DocumentNotFound`.
+    UpdateUserData { doc: DocRef, user_data: UD, },
+}
+
+impl<UD> Req<UD> where UD: Debug {
+    pub fn kind(&self) -> ReqKind {
+        match self {
+            &Req::Init(..) => ReqKind::Init,
+            &Req::Lookup(..) => ReqKind::Lookup,
+            &Req::Terminate => ReqKind::Terminate,
+            &Req::Snapshot { .. } => ReqKind::Snapshot,
+            &Req::Restore { .. } => ReqKind::Restore,
+            &Req::Ping(..) => ReqKind::Ping,
+            &Req::CreateCollection(..) => ReqKind::CreateCollection,
+            &Req::ListCollections => ReqKind::ListCollections,
+            &Req::DropCollection(..) => ReqKind::DropCollection,
+            &Req::LookupStream { .. } => ReqKind::LookupStream,
+            &Req::Cancel(..) => ReqKind::Cancel,
+            &Req::Subscribe(..) => ReqKind::Subscribe,
+            &Req::FetchMore(..) => ReqKind::FetchMore,
+            &Req::LookupBatch(..) => ReqKind::LookupBatch,
+            &Req::GetCluster { .. } => ReqKind::GetCluster,
+            &Req::MergeClusters { .. } => ReqKind::MergeClusters,
+            &Req::MoveDocument { .. } => ReqKind::MoveDocument,
+            &Req::UpdateUserData { .. } => ReqKind::UpdateUserData,
+        }
+    }
+}
+
+/// Kind of a request without its payload, see `Req::kind`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReqKind {
+    Init,
+    Lookup,
+    Terminate,
+    Snapshot,
+    Restore,
+    Ping,
+    CreateCollection,
+    ListCollections,
+    DropCollection,
+    LookupStream,
+    Cancel,
+    Subscribe,
+    FetchMore,
+    LookupBatch,
+    GetCluster,
+    MergeClusters,
+    MoveDocument,
+    UpdateUserData,
+}
+
+#[derive(Debug, Default)]
+pub struct InitParams {
+    /// Collection all requests of this session go to, `None` selects the default collection.
+    /// Every collection is an isolated index with its own cluster id space.
+    pub collection: Option<String>,
+    /// Credentials checked by the server with an `auth::Verifier`, answered with `Rep::AuthFailed` when rejected.
+    pub credentials: Option<Credentials>,
+}
+
+impl InitParams {
+    pub fn is_default(&self) -> bool {
+        self.collection.is_none() && self.credentials.is_none()
+    }
+}
+
+/// `Debug` output leaves out the token and the mac, so requests can be logged safely.
+#[derive(Clone, PartialEq)]
+pub enum Credentials {
+    /// Shared secret token sent as is.
+    Token(String),
+    /// Challenge-response: `nonce` is the one the server sent in `Rep::AuthChallenge` and `mac` its
+    /// HMAC under the secret key named `key_id`.
+    Hmac { key_id: String, nonce: Vec<u8>, mac: Vec<u8>, },
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Credentials::Token(..) =>
+                f.write_str("Token(<redacted>)"),
+            &Credentials::Hmac { ref key_id, ref nonce, .. } =>
+                write!(f, "Hmac {{ key_id: {:?}, nonce: {:?}, mac: <redacted> }}", key_id, nonce),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -27,42 +204,153 @@ pub enum Workload<T> where T: Debug {
 
 #[derive(Debug)]
 pub struct LookupTask<UD> where UD: Debug {
-    pub text: String,
+    pub doc: Document,
     pub result: LookupType,
     pub post_action: PostAction<UD>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum Document {
+    Text(String),
+    /// Raw binary content (perceptual hashes, byte streams), base64 encoded in JSON.
+    Bytes(Vec<u8>),
+    /// Signature precomputed by the client with the `signature` module instead of the text itself.
+    Signature(Signature),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Signature {
+    MinHash { shingle_len: u32, hashes: Vec<u64>, },
+    Shingles { shingle_len: u32, hashes: Vec<u64>, },
+}
+
+impl Document {
+    /// Payload length in bytes, the value `Cond::TextLenGreaterThan` compares. It is `None` for
+    /// a signature, whose original text length is unknown, so length conditions do not apply to it.
+    pub fn text_len(&self) -> Option<usize> {
+        match self {
+            &Document::Text(ref text) => Some(text.len()),
+            &Document::Bytes(ref bytes) => Some(bytes.len()),
+            &Document::Signature(..) => None,
+        }
+    }
+}
+
+/// Lookup tasks sharing a default `LookupType` and default insert conditions, which every task may override.
 #[derive(Debug)]
-pub enum LookupType { All, Best, BestOrMine }
+pub struct Batch<UD> where UD: Debug {
+    pub result: LookupType,
+    pub insert_cond: InsertCond,
+    pub assign: ClusterAssign,
+    pub tasks: Vec<BatchTask<UD>>,
+}
 
 #[derive(Debug)]
-pub enum PostAction<UD> where UD: Debug {
-    None,
-    InsertNew { cond: InsertCond, assign: ClusterAssign, user_data: UD, },
+pub struct BatchTask<UD> where UD: Debug {
+    pub doc: Document,
+    /// Overrides `Batch::result`.
+    pub result: Option<LookupType>,
+    /// `None` stands for `PostAction::None`.
+    pub insert: Option<BatchInsert<UD>>,
 }
 
+/// `PostAction::InsertNew` of a batch task, `cond` and `assign` override the batch defaults.
 #[derive(Debug)]
-pub enum InsertCond {
-    Always,
-    BestSimLessThan(f64),
+pub struct BatchInsert<UD> where UD: Debug {
+    pub user_data: UD,
+    pub idempotency_key: Option<String>,
+    pub cond: Option<InsertCond>,
+    pub assign: Option<ClusterAssign>,
+}
+
+impl<UD> Batch<UD> where UD: Debug {
+    /// Equivalent list of self-contained tasks, in the same order.
+    pub fn expand(self) -> Vec<LookupTask<UD>> {
+        let Batch { result, insert_cond, assign, tasks, } = self;
+        tasks.into_iter()
+            .map(|task| LookupTask {
+                doc: task.doc,
+                result: task.result.unwrap_or_else(|| result.clone()),
+                post_action: match task.insert {
+                    None => PostAction::None,
+                    Some(insert) => PostAction::InsertNew {
+                        cond: insert.cond.unwrap_or_else(|| insert_cond.clone()),
+                        assign: insert.assign.unwrap_or_else(|| assign.clone()),
+                        user_data: insert.user_data,
+                        idempotency_key: insert.idempotency_key,
+                    },
+                },
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum LookupType {
+    All,
+    Best,
+    BestOrMine,
+    /// At most K matches with the highest similarity, sorted by descending similarity.
+    TopK(u32),
+    /// All matches with similarity at or above the threshold in [0, 1], sorted by descending similarity.
+    AboveThreshold(f64),
+    /// At most K matches with similarity at or above the threshold, sorted by descending similarity.
+    TopKAboveThreshold(u32, f64),
 }
 
 #[derive(Debug)]
+pub enum PostAction<UD> where UD: Debug {
+    None,
+    /// Inserts the task document if `cond` holds. A repeated `idempotency_key` is not inserted again but
+    /// answered with the `InsertOutcome` of its first use, see `IDEMPOTENCY_KEY_RETENTION_SECS`.
+    InsertNew { cond: InsertCond, assign: ClusterAssign, user_data: UD, idempotency_key: Option<String>, },
+}
+
+/// Minimum time a server remembers an idempotency key after its first use. Keys are scoped to the
+/// collection; they are not part of snapshots, so `Req::Restore` and dropping the collection forget them.
+pub const IDEMPOTENCY_KEY_RETENTION_SECS: u64 = 24 * 60 * 60;
+
+pub type InsertCond = Cond;
+
+#[derive(Debug, Clone)]
 pub struct ClusterAssign {
     pub cond: AssignCond,
     pub choice: ClusterChoice,
 }
 
-#[derive(Debug)]
-pub enum AssignCond {
+pub type AssignCond = Cond;
+
+/// Maximum nesting of `And`, `Or` and `Not` in a `Cond`, decoders reject deeper conditions.
+pub const MAX_COND_DEPTH: usize = 32;
+
+/// Condition expression shared by `InsertCond` and `AssignCond`, see `cond::Context` for evaluation.
+#[derive(Debug, Clone)]
+pub enum Cond {
     Always,
     BestSimLessThan(f64),
+    Never,
+    BestSimGreaterThan(f64),
+    /// Compares `Document::text_len`. It is not applicable to `Document::Signature`, whose length is
+    /// unknown: servers answer such tasks with `ErrorCode::UnsupportedDocument` (see `Cond::uses_text_len`)
+    /// instead of evaluating it.
+    TextLenGreaterThan(u64),
+    NoNeighbourInCluster(u64),
+    And(Vec<Cond>),
+    Or(Vec<Cond>),
+    Not(Box<Cond>),
 }
 
-#[derive(Debug)]
+/// Cluster for a document inserted with `PostAction::InsertNew`. The choice only applies when
+/// `ClusterAssign::cond` holds, otherwise the server decides as with `ServerChoice`
+/// (see `ClusterAssign::resolve`).
+#[derive(Debug, Clone)]
 pub enum ClusterChoice {
     ServerChoice,
     ClientChoice(u64),
+    /// Join the cluster of the most similar match, or start a new cluster if there are no matches.
+    BestMatchCluster,
+    /// Always start a new cluster.
+    NewCluster,
 }
 
 #[derive(Debug)]
@@ -70,9 +358,219 @@ pub enum Rep<UD> where UD: Debug {
     InitAck,
     Result(Workload<LookupResult<UD>>),
     TerminateAck,
-    Unexpected(Req<UD>),
-    TooBusy,
+    /// The request is not valid in the current session state.
+    Unexpected { reason: UnexpectedReason, req: ReqEcho<UD>, },
+    TooBusy(BusyInfo),
     WantCrash,
+    SnapshotAck,
+    RestoreAck,
+    SnapshotError(String),
+    Pong(u64),
+    /// Reply to a `Trans::Tagged`, never nested into another `Tagged`.
+    Tagged(RequestId, Box<Rep<UD>>),
+    CreateCollectionAck,
+    Collections(Vec<String>),
+    DropCollectionAck,
+    UnknownCollection(String),
+    CollectionExists(String),
+    DeadlineExceeded,
+    /// Results for the tasks `start .. start + results.len()` of a `Req::LookupStream`, chunks are sent in task order.
+    ResultChunk { start: u32, results: Vec<LookupResult<UD>>, },
+    /// Last reply to a `Req::LookupStream`, carrying the total number of results sent.
+    EndOfStream(u32),
+    /// Sent to the client of a `Req::Cancel`, exactly once per cancel, with the id of the targeted
+    /// transaction; a transaction it stops sends no reply of its own.
+    Cancelled(RequestId, CancelState),
+    /// Answer to `Req::Init` with missing or rejected credentials, the session stays uninitialized.
+    AuthFailed(String),
+    /// Answer to a `Req::Init` without credentials when the server wants `Credentials::Hmac`: the client
+    /// resends `Req::Init` with the mac of this nonce. A nonce is valid for a single `Req::Init` of the same session.
+    AuthChallenge(Vec<u8>),
+    SubscribeAck,
+    /// Change pushed by the server to a client with a matching `Req::Subscribe`.
+    Event(Event<UD>),
+    Cluster(ClusterInfo<UD>),
+    ClusterNotFound(u64),
+    ClustersMerged { from: u64, into: u64, },
+    /// `doc` carries the cluster before the move, `to_cluster` the cluster the document ended up in.
+    DocumentMoved { doc: DocRef, to_cluster: u64, },
+    /// No document with `doc_id` in the collection, the echoed `cluster_id` is not checked.
+    DocumentNotFound(DocRef),
+    UserDataUpdated(DocRef),
+}
+
+/// Refers to an inserted document. Servers look documents up by `doc_id` alone, so a reference stays
+/// valid after `Req::MergeClusters` or `Req::MoveDocument`; `cluster_id` is the cluster the client
+/// last saw it in, and replies carry the current one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DocRef {
+    pub cluster_id: u64,
+    pub doc_id: u64,
+}
+
+#[derive(Debug)]
+pub struct ClusterInfo<UD> where UD: Debug {
+    pub cluster_id: u64,
+    /// Total number of documents in the cluster, regardless of paging.
+    pub size: u64,
+    /// Document the cluster was started with, if the server keeps document contents.
+    pub representative: Option<Document>,
+    /// Requested page of members, in insertion order.
+    pub members: Vec<Member<UD>>,
+}
+
+#[derive(Debug)]
+pub struct Member<UD> where UD: Debug {
+    /// Server assigned id, unique within the collection and never reused. It does not change when
+    /// the document moves to another cluster.
+    pub doc_id: u64,
+    pub user_data: UD,
+}
+
+/// Tasks of a `Workload::Many` or `Req::LookupStream` batch run in order, so a cancelled batch
+/// is always split into a done prefix and a never started rest. Post actions of the done tasks,
+/// including `PostAction::InsertNew`, stay applied; results already sent in `Rep::ResultChunk`
+/// stay valid, but no `Rep::EndOfStream` follows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CancelState {
+    /// No pending transaction with this id: it never existed or already finished, in which case its
+    /// normal reply was sent before the `Rep::Cancelled` and stays valid.
+    NotFound,
+    /// The transaction was stopped after its first `completed` tasks.
+    Cancelled { completed: u32, },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnexpectedReason {
+    /// No reason given, also used for replies received in the legacy form.
+    Unspecified,
+    /// Request other than `Req::Init` before the session was initialized.
+    NotInitialized,
+    /// Second `Req::Init` in the same session.
+    AlreadyInitialized,
+    /// Request after `Req::Terminate`.
+    Terminated,
+    /// Request the server does not implement.
+    Unsupported,
+}
+
+/// Rejected request as echoed back in `Rep::Unexpected`: either in full or only its kind, which
+/// keeps the reply small for big lookups.
+#[derive(Debug)]
+pub enum ReqEcho<UD> where UD: Debug {
+    Full(Req<UD>),
+    Kind(ReqKind),
+}
+
+impl<UD> ReqEcho<UD> where UD: Debug {
+    pub fn kind(&self) -> ReqKind {
+        match self {
+            &ReqEcho::Full(ref req) => req.kind(),
+            &ReqEcho::Kind(kind) => kind,
+        }
+    }
+}
+
+/// Load information sent with `Rep::TooBusy`, every field is an optional hint (see `retry::Backoff`).
+#[derive(Debug, Default)]
+pub struct BusyInfo {
+    /// Suggested delay before the request is retried.
+    pub retry_after_ms: Option<u64>,
+    /// Number of requests queued on the server.
+    pub queue_depth: Option<u64>,
+    pub reason: Option<BusyReason>,
+}
+
+impl BusyInfo {
+    pub fn is_default(&self) -> bool {
+        self.retry_after_ms.is_none() && self.queue_depth.is_none() && self.reason.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BusyReason {
+    QueueFull,
+    MemoryPressure,
+    /// The server is going away, retrying on the same connection is pointless.
+    ShuttingDown,
+}
+
+impl<UD> Rep<UD> where UD: Debug {
+    /// Wraps a reply into `Rep::Tagged` if the transaction it answers carried an id.
+    pub fn reply_to(id: Option<RequestId>, rep: Rep<UD>) -> Rep<UD> {
+        match id {
+            Some(id) => Rep::Tagged(id, Box::new(rep)),
+            None => rep,
+        }
+    }
+
+    pub fn id(&self) -> Option<RequestId> {
+        match self {
+            &Rep::Tagged(id, _) => Some(id),
+            _ => None,
+        }
+    }
+}
+
+/// Events of interest for `Req::Subscribe`. An empty `kinds` list ends the subscription.
+#[derive(Debug)]
+pub struct EventFilter {
+    pub kinds: Vec<EventKind>,
+    /// Restricts events to these clusters, `None` means all clusters. A move matches both the
+    /// cluster the document left and the one it went to.
+    pub cluster_ids: Option<Vec<u64>>,
+}
+
+impl EventFilter {
+    pub fn matches<UD>(&self, event: &Event<UD>) -> bool where UD: Debug {
+        self.kinds.contains(&event.kind()) && match self.cluster_ids {
+            Some(ref cluster_ids) => cluster_ids.contains(&event.cluster_id()) || match event {
+                &Event::DocumentMoved { from_cluster, .. } => cluster_ids.contains(&from_cluster),
+                _ => false,
+            },
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    DocumentInserted,
+    ClusterCreated,
+    ClusterDeleted,
+    DocumentMoved,
+}
+
+#[derive(Debug)]
+pub enum Event<UD> where UD: Debug {
+    /// A document joined an existing cluster. `doc_id` is `None` when the event came in the layout
+    /// from before document ids were reported.
+    DocumentInserted { cluster_id: u64, doc_id: Option<u64>, user_data: UD, },
+    /// A document started a new cluster, `doc_id` and `user_data` are the ones of that document.
+    ClusterCreated { cluster_id: u64, doc_id: Option<u64>, user_data: UD, },
+    ClusterDeleted { cluster_id: u64, },
+    /// A document left `from_cluster` after `Req::MoveDocument`, `doc` refers to its new place.
+    DocumentMoved { doc: DocRef, from_cluster: u64, },
+}
+
+impl<UD> Event<UD> where UD: Debug {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            &Event::DocumentInserted { .. } => EventKind::DocumentInserted,
+            &Event::ClusterCreated { .. } => EventKind::ClusterCreated,
+            &Event::ClusterDeleted { .. } => EventKind::ClusterDeleted,
+            &Event::DocumentMoved { .. } => EventKind::DocumentMoved,
+        }
+    }
+
+    pub fn cluster_id(&self) -> u64 {
+        match self {
+            &Event::DocumentInserted { cluster_id, .. } | &Event::ClusterCreated { cluster_id, .. } | &Event::ClusterDeleted { cluster_id, } =>
+                cluster_id,
+            &Event::DocumentMoved { ref doc, .. } =>
+                doc.cluster_id,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -80,12 +578,230 @@ pub enum LookupResult<UD> where UD: Debug {
     EmptySet,
     Best(Match<UD>),
     Neighbours(Workload<Match<UD>>),
-    Error(String),
+    Error(LookupError),
+    /// Result of a task with `PostAction::InsertNew`, together with what the post action actually did.
+    /// The wrapped result is never a `WithOutcome` itself, decoders reject such nesting.
+    WithOutcome(InsertOutcome, Box<LookupResult<UD>>),
+    /// Part of a neighbour set too big for one reply, in the same order as `Neighbours`. The rest is
+    /// fetched with `Req::FetchMore(next)`; `next` is `None` on the last page.
+    NeighboursPage { matches: Vec<Match<UD>>, next: Option<Cursor>, },
+}
+
+#[derive(Debug)]
+pub enum InsertOutcome {
+    NotInserted,
+    /// Where the new document went, its `doc_id` can be used with `Req::UpdateUserData` and `Req::MoveDocument`.
+    /// It is `None` when the reply came in the layout from before document ids were reported.
+    InsertedIntoCluster { cluster_id: u64, doc_id: Option<u64>, },
+    InsertedAsNewCluster { cluster_id: u64, doc_id: Option<u64>, },
+}
+
+#[derive(Debug)]
+pub struct LookupError {
+    pub code: ErrorCode,
+    /// Whether the same task may succeed when sent again later.
+    pub retryable: bool,
+    pub message: String,
+}
+
+impl LookupError {
+    /// Error with the retryable flag implied by `code`.
+    pub fn new(code: ErrorCode, message: String) -> LookupError {
+        LookupError {
+            retryable: code.is_retryable(),
+            code: code,
+            message: message,
+        }
+    }
+
+    /// Error in the legacy free text form: `ErrorCode::Other` and not retryable.
+    pub fn other(message: String) -> LookupError {
+        LookupError::new(ErrorCode::Other, message)
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.code == ErrorCode::Other && !self.retryable
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    /// Unclassified error, also used for errors received in the legacy string form.
+    Other,
+    /// The task did not finish before the deadline of its transaction.
+    DeadlineExceeded,
+    Overloaded,
+    /// The document exceeds the size limit of the server.
+    TextTooLong,
+    /// `ClusterChoice::ClientChoice` names a cluster the server does not accept.
+    InvalidClusterChoice,
+    /// The server cannot handle this kind of document, e.g. a signature with unexpected parameters.
+    UnsupportedDocument,
+    /// The cursor of `Req::FetchMore` is unknown or expired.
+    InvalidCursor,
+}
+
+impl ErrorCode {
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            &ErrorCode::DeadlineExceeded | &ErrorCode::Overloaded => true,
+            _ => false,
+        }
+    }
+}
+
+impl<UD> LookupResult<UD> where UD: Debug {
+    pub fn insert_outcome(&self) -> Option<&InsertOutcome> {
+        match self {
+            &LookupResult::WithOutcome(ref outcome, _) => Some(outcome),
+            _ => None,
+        }
+    }
+
+    /// Cursor of the next page if this is a `NeighboursPage` which is not the last one.
+    pub fn next_cursor(&self) -> Option<&Cursor> {
+        match self {
+            &LookupResult::NeighboursPage { next: Some(ref cursor), .. } => Some(cursor),
+            &LookupResult::WithOutcome(_, ref result) => result.next_cursor(),
+            _ => None,
+        }
+    }
+
+    /// Builds the reply for `LookupType::TopK(k)` from an unordered neighbour set.
+    pub fn top_k(matches: Vec<Match<UD>>, k: u32) -> LookupResult<UD> {
+        LookupResult::select(matches, Some(k), None)
+    }
+
+    /// Builds the reply for `LookupType::AboveThreshold(threshold)` from an unordered neighbour set.
+    pub fn above_threshold(matches: Vec<Match<UD>>, threshold: f64) -> LookupResult<UD> {
+        LookupResult::select(matches, None, Some(threshold))
+    }
+
+    /// Builds the reply for `LookupType::TopKAboveThreshold(k, threshold)` from an unordered neighbour set.
+    pub fn top_k_above_threshold(matches: Vec<Match<UD>>, k: u32, threshold: f64) -> LookupResult<UD> {
+        LookupResult::select(matches, Some(k), Some(threshold))
+    }
+
+    fn select(mut matches: Vec<Match<UD>>, k: Option<u32>, threshold: Option<f64>) -> LookupResult<UD> {
+        if let Some(threshold) = threshold {
+            matches.retain(|m| m.similarity >= threshold);
+        }
+        matches.sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap_or(Ordering::Equal));
+        if let Some(k) = k {
+            matches.truncate(k as usize);
+        }
+        if matches.is_empty() {
+            LookupResult::EmptySet
+        } else {
+            LookupResult::Neighbours(Workload::Many(matches))
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Match<UD> where UD: Debug {
+    /// Current cluster of the matched document, reflecting any `Req::MergeClusters` or `Req::MoveDocument`.
     pub cluster_id: u64,
+    /// Id of the matched document, see `Member::doc_id`. It is `None` when the reply came in the
+    /// layout from before document ids were reported.
+    pub doc_id: Option<u64>,
     pub similarity: f64,
     pub user_data: UD,
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Workload, LookupResult, Match, DocRef, EventFilter, EventKind, Event};
+    use super::{Batch, BatchTask, BatchInsert, LookupType, PostAction, Document, Signature, Cond, ClusterAssign, ClusterChoice};
+
+    fn m(cluster_id: u64, similarity: f64) -> Match<()> {
+        Match { cluster_id: cluster_id, doc_id: None, similarity: similarity, user_data: (), }
+    }
+
+    #[test]
+    fn top_k() {
+        match LookupResult::top_k(vec![m(1, 0.3), m(2, 0.9), m(3, 0.5), m(4, 0.7)], 3) {
+            LookupResult::Neighbours(Workload::Many(ref matches)) =>
+                assert_eq!(matches.iter().map(|m| m.cluster_id).collect::<Vec<_>>(), vec![2, 4, 3]),
+            other => panic!("bad result: {:?}", other),
+        }
+        match LookupResult::top_k(vec![m(1, 0.3)], 3) {
+            LookupResult::Neighbours(Workload::Many(ref matches)) if matches.len() == 1 => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match LookupResult::top_k(vec![m(1, 0.3)], 0) {
+            LookupResult::EmptySet => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn above_threshold() {
+        match LookupResult::above_threshold(vec![m(1, 0.3), m(2, 0.9), m(3, 0.5), m(4, 0.7)], 0.5) {
+            LookupResult::Neighbours(Workload::Many(ref matches)) =>
+                assert_eq!(matches.iter().map(|m| m.cluster_id).collect::<Vec<_>>(), vec![2, 4, 3]),
+            other => panic!("bad result: {:?}", other),
+        }
+        match LookupResult::top_k_above_threshold(vec![m(1, 0.3), m(2, 0.9), m(3, 0.5), m(4, 0.7)], 1, 0.5) {
+            LookupResult::Neighbours(Workload::Many(ref matches)) =>
+                assert_eq!(matches.iter().map(|m| m.cluster_id).collect::<Vec<_>>(), vec![2]),
+            other => panic!("bad result: {:?}", other),
+        }
+        match LookupResult::above_threshold(vec![m(1, 0.3)], 0.95) {
+            LookupResult::EmptySet => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn text_len() {
+        assert_eq!(Document::Text("hello".to_owned()).text_len(), Some(5));
+        assert_eq!(Document::Bytes(vec![]).text_len(), Some(0));
+        assert_eq!(Document::Signature(Signature::MinHash { shingle_len: 2, hashes: vec![1, 2], }).text_len(), None);
+    }
+
+    #[test]
+    fn event_filter() {
+        let inserted = Event::DocumentInserted { cluster_id: 1, doc_id: Some(5), user_data: (), };
+        let deleted: Event<()> = Event::ClusterDeleted { cluster_id: 2, };
+        let all = EventFilter { kinds: vec![EventKind::DocumentInserted, EventKind::ClusterDeleted], cluster_ids: None, };
+        assert!(all.matches(&inserted) && all.matches(&deleted));
+        let cluster_1 = EventFilter { kinds: vec![EventKind::DocumentInserted, EventKind::ClusterDeleted], cluster_ids: Some(vec![1]), };
+        assert!(cluster_1.matches(&inserted) && !cluster_1.matches(&deleted));
+        let created = EventFilter { kinds: vec![EventKind::ClusterCreated], cluster_ids: None, };
+        assert!(!created.matches(&inserted));
+        assert!(!EventFilter { kinds: vec![], cluster_ids: None, }.matches(&inserted));
+        let moved: Event<()> = Event::DocumentMoved { doc: DocRef { cluster_id: 3, doc_id: 5, }, from_cluster: 1, };
+        let moves = |cluster_id| EventFilter { kinds: vec![EventKind::DocumentMoved], cluster_ids: Some(vec![cluster_id]), };
+        assert!(moves(1).matches(&moved) && moves(3).matches(&moved) && !moves(2).matches(&moved));
+    }
+
+    #[test]
+    fn batch_expand() {
+        let task = |text: &str, result, insert| BatchTask { doc: Document::Text(text.to_owned()), result: result, insert: insert, };
+        let batch = Batch {
+            result: LookupType::Best,
+            insert_cond: Cond::BestSimLessThan(0.5),
+            assign: ClusterAssign { cond: Cond::Always, choice: ClusterChoice::ServerChoice, },
+            tasks: vec![
+                task("a", None, None),
+                task("b", Some(LookupType::TopK(3)), Some(BatchInsert { user_data: 1, idempotency_key: None, cond: None, assign: None, })),
+                task("c", None, Some(BatchInsert { user_data: 2, idempotency_key: None, cond: Some(Cond::Never), assign: None, })),
+            ],
+        };
+        let tasks = batch.expand();
+        assert_eq!(tasks.len(), 3);
+        match (&tasks[0].result, &tasks[0].post_action) {
+            (&LookupType::Best, &PostAction::None) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match (&tasks[1].result, &tasks[1].post_action) {
+            (&LookupType::TopK(3), &PostAction::InsertNew { cond: Cond::BestSimLessThan(..), user_data: 1, .. }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match (&tasks[2].result, &tasks[2].post_action) {
+            (&LookupType::Best, &PostAction::InsertNew { cond: Cond::Never, assign: ClusterAssign { choice: ClusterChoice::ServerChoice, .. }, user_data: 2, .. }) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+}
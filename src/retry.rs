@@ -0,0 +1,90 @@
+//! Client side retrying of requests rejected with `Rep::TooBusy`.
+//!
+//! `Backoff` computes how long to wait before resending a rejected request. A `retry_after_ms`
+//! hint from the server is never shortened: it is waited out as is, and the request is given up
+//! if the hint exceeds `max`. Without a hint the delay doubles with every attempt, starting from
+//! `initial` and capped at `max`. Nothing is retried after `BusyReason::ShuttingDown` or once
+//! `max_attempts` retries were spent.
+
+use std::{cmp, u32};
+use std::time::Duration;
+use super::{BusyInfo, BusyReason};
+
+#[derive(Debug)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    max_attempts: u32,
+    attempts: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration, max_attempts: u32) -> Backoff {
+        Backoff {
+            initial: initial,
+            max: max,
+            max_attempts: max_attempts,
+            attempts: 0,
+        }
+    }
+
+    /// Delay before the next retry of a request answered with `Rep::TooBusy(info)`, or `None` if the
+    /// request should not be retried.
+    pub fn next_delay(&mut self, info: &BusyInfo) -> Option<Duration> {
+        if info.reason == Some(BusyReason::ShuttingDown) || self.attempts >= self.max_attempts {
+            return None;
+        }
+        let delay = match info.retry_after_ms {
+            Some(ms) if Duration::from_millis(ms) > self.max => return None,
+            Some(ms) => Duration::from_millis(ms),
+            None => {
+                let factor = 1u32.checked_shl(self.attempts).unwrap_or(u32::MAX);
+                cmp::min(self.initial.checked_mul(factor).unwrap_or(self.max), self.max)
+            },
+        };
+        self.attempts += 1;
+        Some(delay)
+    }
+
+    /// Forgets previous attempts, to be called once a request went through.
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use super::Backoff;
+    use super::super::{BusyInfo, BusyReason};
+
+    #[test]
+    fn exponential() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(500), 5);
+        let busy = BusyInfo::default();
+        let delays: Vec<_> = (0 .. 6).map(|_| backoff.next_delay(&busy)).collect();
+        assert_eq!(delays, vec![
+            Some(Duration::from_millis(100)),
+            Some(Duration::from_millis(200)),
+            Some(Duration::from_millis(400)),
+            Some(Duration::from_millis(500)),
+            Some(Duration::from_millis(500)),
+            None,
+        ]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(&busy), Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn hints() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(500), 5);
+        let hinted = BusyInfo { retry_after_ms: Some(2000), queue_depth: Some(10), reason: Some(BusyReason::QueueFull), };
+        assert_eq!(backoff.next_delay(&hinted), None);
+        let exact = BusyInfo { retry_after_ms: Some(500), queue_depth: None, reason: None, };
+        assert_eq!(backoff.next_delay(&exact), Some(Duration::from_millis(500)));
+        let short = BusyInfo { retry_after_ms: Some(20), queue_depth: None, reason: None, };
+        assert_eq!(backoff.next_delay(&short), Some(Duration::from_millis(20)));
+        let shutting_down = BusyInfo { retry_after_ms: Some(10), queue_depth: None, reason: Some(BusyReason::ShuttingDown), };
+        assert_eq!(backoff.next_delay(&shutting_down), None);
+    }
+}
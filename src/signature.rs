@@ -0,0 +1,173 @@
+//! Deterministic document signatures for `Document::Signature` lookups.
+//!
+//! Clients compute a signature locally and send it instead of the document text; servers
+//! compute the same signature for texts they index. Everything here is fully specified so
+//! both sides agree bit-for-bit regardless of platform or Rust version:
+//!
+//! * text is split into tokens on ASCII whitespace and ASCII punctuation, tokens are ASCII
+//!   lowercased (other characters are kept verbatim);
+//! * a shingle is a run of `shingle_len` consecutive tokens (or all tokens, if there are fewer),
+//!   hashed with 64-bit FNV-1a over the token bytes, each token followed by a `0x1f` byte;
+//! * `Signature::Shingles` is the sorted set of distinct shingle hashes;
+//! * `Signature::MinHash` slot `i` is the minimum of `mix(h ^ mix(i))` over all shingle hashes
+//!   `h`, where `mix` is the SplitMix64 finalizer, or `u64::MAX` for a text without tokens.
+
+use std::u64;
+use super::Signature;
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+const TOKEN_SEPARATOR: u8 = 0x1f;
+
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET, |hash, &byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+pub fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+fn tokens(text: &str) -> Vec<String> {
+    text.split(|c: char| c.is_ascii_whitespace() || c.is_ascii_punctuation())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_ascii_lowercase())
+        .collect()
+}
+
+fn shingle_hashes(text: &str, shingle_len: u32) -> Vec<u64> {
+    let tokens = tokens(text);
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+    let window = if shingle_len == 0 { 1 } else { shingle_len as usize };
+    let window = if window > tokens.len() { tokens.len() } else { window };
+    let mut buf = Vec::new();
+    let mut hashes: Vec<u64> = tokens.windows(window)
+        .map(|shingle| {
+            buf.clear();
+            for token in shingle {
+                buf.extend_from_slice(token.as_bytes());
+                buf.push(TOKEN_SEPARATOR);
+            }
+            fnv1a(&buf)
+        })
+        .collect();
+    hashes.sort();
+    hashes.dedup();
+    hashes
+}
+
+pub fn shingles(text: &str, shingle_len: u32) -> Signature {
+    Signature::Shingles { shingle_len: shingle_len, hashes: shingle_hashes(text, shingle_len), }
+}
+
+pub fn minhash(text: &str, shingle_len: u32, num_hashes: u32) -> Signature {
+    let shingles = shingle_hashes(text, shingle_len);
+    let hashes = (0 .. num_hashes as u64)
+        .map(|i| {
+            let seed = mix(i);
+            shingles.iter().fold(u64::MAX, |min, &h| {
+                let value = mix(h ^ seed);
+                if value < min { value } else { min }
+            })
+        })
+        .collect();
+    Signature::MinHash { shingle_len: shingle_len, hashes: hashes, }
+}
+
+/// Estimated Jaccard similarity of two signatures, or `None` if they were computed with
+/// different kinds or parameters and cannot be compared.
+pub fn similarity(a: &Signature, b: &Signature) -> Option<f64> {
+    match (a, b) {
+        (&Signature::MinHash { shingle_len: la, hashes: ref ha, }, &Signature::MinHash { shingle_len: lb, hashes: ref hb, })
+            if la == lb && ha.len() == hb.len() =>
+        {
+            if ha.is_empty() {
+                return Some(0.0);
+            }
+            let equal = ha.iter().zip(hb.iter()).filter(|&(x, y)| x == y).count();
+            Some(equal as f64 / ha.len() as f64)
+        },
+        (&Signature::Shingles { shingle_len: la, hashes: ref ha, }, &Signature::Shingles { shingle_len: lb, hashes: ref hb, })
+            if la == lb =>
+        {
+            let (mut i, mut j, mut common) = (0, 0, 0);
+            while i < ha.len() && j < hb.len() {
+                if ha[i] == hb[j] {
+                    common += 1;
+                    i += 1;
+                    j += 1;
+                } else if ha[i] < hb[j] {
+                    i += 1;
+                } else {
+                    j += 1;
+                }
+            }
+            let union = ha.len() + hb.len() - common;
+            Some(if union == 0 { 0.0 } else { common as f64 / union as f64 })
+        },
+        _ =>
+            None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fnv1a, mix, shingles, minhash, similarity};
+    use super::super::Signature;
+
+    #[test]
+    fn hash_primitives() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+        assert_eq!(mix(0), 0xe220a8397b1dcdaf);
+    }
+
+    #[test]
+    fn reference_values() {
+        assert_eq!(shingles("Hello, world", 2), Signature::Shingles { shingle_len: 2, hashes: vec![0xd510fca251cc03f5], });
+        assert_eq!(minhash("Hello, world", 2, 4), Signature::MinHash {
+            shingle_len: 2,
+            hashes: vec![0xfef7f81722c1d2c1, 0x71641ce11d301888, 0x097b2dc28c13efc9, 0x830174d9b9f5b1fd],
+        });
+    }
+
+    #[test]
+    fn normalization() {
+        match (shingles("Hello,   World! ", 2), shingles("hello world", 2)) {
+            (Signature::Shingles { hashes: ref a, .. }, Signature::Shingles { hashes: ref b, .. }) => {
+                assert_eq!(a.len(), 1);
+                assert_eq!(a, b);
+            },
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn similar_texts() {
+        let a = "the quick brown fox jumps over the lazy dog near the river bank";
+        let b = "the quick brown fox jumps over the lazy cat near the river bank";
+        let c = "completely unrelated sentence about protocol encodings";
+        let sa = minhash(a, 2, 128);
+        let sb = minhash(b, 2, 128);
+        let sc = minhash(c, 2, 128);
+        assert_eq!(similarity(&sa, &minhash(a, 2, 128)), Some(1.0));
+        assert!(similarity(&sa, &sb).unwrap() > similarity(&sa, &sc).unwrap());
+        assert_eq!(similarity(&sa, &minhash(a, 3, 128)), None);
+        assert_eq!(similarity(&sa, &shingles(a, 2)), None);
+        let exact = similarity(&shingles(a, 2), &shingles(b, 2)).unwrap();
+        assert!(exact > 0.5 && exact < 1.0);
+    }
+
+    #[test]
+    fn empty_text() {
+        match minhash(" ,. ", 3, 4) {
+            Signature::MinHash { shingle_len: 3, ref hashes, } => assert_eq!(*hashes, vec![::std::u64::MAX; 4]),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! On-disk snapshot format for a dupl server index.
+//!
+//! A snapshot is written in response to `Req::Snapshot` and loaded back on `Req::Restore`.
+//! Every field is encoded with `ToBin`, so the layout (and native endianness) is exactly
+//! the one used on the wire:
+//!
+//! ```text
+//! magic       [u8; 8]   b"DUPLSNAP"
+//! version     u32       currently 2
+//! count       u64       number of entries that follow
+//! entries     count × { cluster_id: u64, doc_id: u64, doc: Document, user_data: UD }
+//! ```
+//!
+//! Entries are stored in insertion order, so restoring them one by one with the recorded
+//! `cluster_id` and `doc_id` reproduces the original clustering and keeps `DocRef`s valid.
+//! Version 1 entries had no `doc_id` and a plain text instead of `doc`, they are rejected.
+
+use std::{io, fmt, cmp};
+use std::fmt::Debug;
+use std::io::{Read, Write};
+use std::mem::size_of;
+use super::Document;
+use super::bin::{self, ToBin, FromBin};
+
+pub const MAGIC: &'static [u8; 8] = b"DUPLSNAP";
+pub const VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Bin(bin::Error),
+    BadMagic,
+    UnsupportedVersion(u32),
+    TrailingData(usize),
+}
+
+#[derive(Debug)]
+pub struct Entry<UD> where UD: Debug {
+    pub cluster_id: u64,
+    /// Id the document was reported with in `Member::doc_id`, kept across restores.
+    pub doc_id: u64,
+    pub doc: Document,
+    pub user_data: UD,
+}
+
+impl<UD> ToBin for Entry<UD> where UD: ToBin + Debug {
+    fn encode_len(&self) -> usize {
+        self.cluster_id.encode_len() + self.doc_id.encode_len() + self.doc.encode_len() + self.user_data.encode_len()
+    }
+
+    fn encode<'a>(&self, area: &'a mut [u8]) -> &'a mut [u8] {
+        let area = self.cluster_id.encode(area);
+        let area = self.doc_id.encode(area);
+        let area = self.doc.encode(area);
+        let area = self.user_data.encode(area);
+        area
+    }
+}
+
+impl<UD> FromBin for Entry<UD> where UD: FromBin + Debug {
+    fn decode<'a>(area: &'a [u8]) -> Result<(Entry<UD>, &'a [u8]), bin::Error> {
+        let (cluster_id, area) = try!(u64::decode(area));
+        let (doc_id, area) = try!(u64::decode(area));
+        let (doc, area) = try!(Document::decode(area));
+        let (user_data, area) = try!(UD::decode(area));
+        Ok((Entry {
+            cluster_id: cluster_id,
+            doc_id: doc_id,
+            doc: doc,
+            user_data: user_data,
+        }, area))
+    }
+}
+
+fn write_bin<W, T>(writer: &mut W, buf: &mut Vec<u8>, value: &T) -> Result<(), Error> where W: Write, T: ToBin {
+    buf.clear();
+    buf.resize(value.encode_len(), 0);
+    value.encode(buf);
+    writer.write_all(buf).map_err(Error::Io)
+}
+
+/// Writes a complete snapshot of `entries` to `writer`.
+pub fn write<W, UD>(writer: &mut W, entries: &[Entry<UD>]) -> Result<(), Error> where W: Write, UD: ToBin + Debug {
+    try!(writer.write_all(MAGIC).map_err(Error::Io));
+    let mut buf = Vec::new();
+    try!(write_bin(writer, &mut buf, &VERSION));
+    try!(write_bin(writer, &mut buf, &(entries.len() as u64)));
+    for entry in entries {
+        try!(write_bin(writer, &mut buf, entry));
+    }
+    writer.flush().map_err(Error::Io)
+}
+
+/// Reads a complete snapshot from `reader`, validating its header.
+pub fn read<R, UD>(reader: &mut R) -> Result<Vec<Entry<UD>>, Error> where R: Read, UD: FromBin + Debug {
+    let mut data = Vec::new();
+    try!(reader.read_to_end(&mut data).map_err(Error::Io));
+    if data.len() < MAGIC.len() || &data[.. MAGIC.len()] != &MAGIC[..] {
+        return Err(Error::BadMagic);
+    }
+    let area = &data[MAGIC.len() ..];
+    let (version, area) = try!(u32::decode(area).map_err(Error::Bin));
+    if version != VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let (count, mut area) = try!(u64::decode(area).map_err(Error::Bin));
+    let mut entries = Vec::with_capacity(cmp::min(count as usize, area.len() / size_of::<u64>()));
+    for _ in 0 .. count {
+        let (entry, next_area) = try!(Entry::decode(area).map_err(Error::Bin));
+        entries.push(entry);
+        area = next_area;
+    }
+    if area.len() > 0 {
+        return Err(Error::TrailingData(area.len()));
+    }
+    Ok(entries)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::Io(ref err) => write!(f, "I/O error {}", err),
+            &Error::Bin(ref err) => write!(f, "snapshot decoding error: {}", err),
+            &Error::BadMagic => f.write_str("not a dupl snapshot (bad magic)"),
+            &Error::UnsupportedVersion(version) => write!(f, "unsupported snapshot version {}", version),
+            &Error::TrailingData(len) => write!(f, "{} bytes of trailing data after snapshot entries", len),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use super::{Entry, Error, MAGIC, read, write};
+    use super::super::bin::ToBin;
+    use super::super::Document;
+
+    #[test]
+    fn write_read() {
+        let entries = vec![Entry {
+            cluster_id: 1,
+            doc_id: 1,
+            doc: Document::Text("hello, world".to_owned()),
+            user_data: "doc 1".to_owned(),
+        }, Entry {
+            cluster_id: 1,
+            doc_id: 2,
+            doc: Document::Text("hello, world!".to_owned()),
+            user_data: "doc 2".to_owned(),
+        }, Entry {
+            cluster_id: 2,
+            doc_id: 3,
+            doc: Document::Text("hello, cat".to_owned()),
+            user_data: "doc 3".to_owned(),
+        }];
+        let mut file = Vec::new();
+        write(&mut file, &entries).unwrap();
+        let restored: Vec<Entry<String>> = read(&mut Cursor::new(file)).unwrap();
+        assert_eq!(restored.len(), entries.len());
+        for (a, b) in entries.iter().zip(restored.iter()) {
+            assert_eq!(a.cluster_id, b.cluster_id);
+            assert_eq!(a.doc_id, b.doc_id);
+            assert_eq!(a.doc, b.doc);
+            assert_eq!(a.user_data, b.user_data);
+        }
+    }
+
+    #[test]
+    fn bad_magic() {
+        match read::<_, String>(&mut Cursor::new(b"NOTASNAPSHOT".to_vec())) {
+            Err(Error::BadMagic) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let mut file = Vec::new();
+        write(&mut file, &[Entry { cluster_id: 7, doc_id: 1, doc: Document::Text("some text".to_owned()), user_data: 0.5f64, }]).unwrap();
+        let len = file.len();
+        file.truncate(len - 1);
+        match read::<_, f64>(&mut Cursor::new(file)) {
+            Err(Error::Bin(..)) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn old_version() {
+        let mut file = MAGIC.to_vec();
+        let mut header = vec![0u8; 1u32.encode_len() + 1u64.encode_len()];
+        let area = 1u32.encode(&mut header);
+        1u64.encode(area);
+        file.extend_from_slice(&header);
+        file.extend_from_slice(&[0; 32]);
+        match read::<_, String>(&mut Cursor::new(file)) {
+            Err(Error::UnsupportedVersion(1)) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Client side reassembly of a streamed `Req::LookupStream` reply.
+//!
+//! The server answers a `Req::LookupStream` with a sequence of `Rep::ResultChunk` covering the
+//! tasks in order, without gaps or overlaps, and finishes with `Rep::EndOfStream` carrying the
+//! number of results sent. `ResultStream` wraps any source of replies (`Rep::Tagged` wrappers are
+//! removed) and yields `(task_index, result)` pairs as soon as their chunk arrives.
+
+use std::vec;
+use std::fmt::Debug;
+use super::{Rep, LookupResult};
+
+#[derive(Debug)]
+pub enum Error<UD> where UD: Debug {
+    /// A chunk does not start right after the previous one.
+    OutOfOrder { expected: u32, start: u32, },
+    /// `Rep::EndOfStream` announced a different number of results than were received.
+    CountMismatch { received: u32, total: u32, },
+    /// The replies ended before `Rep::EndOfStream`.
+    Truncated { received: u32, },
+    /// A reply which is not part of a stream, e.g. `Rep::TooBusy` or `Rep::DeadlineExceeded`.
+    Unexpected(Rep<UD>),
+}
+
+pub struct ResultStream<UD, I> where UD: Debug, I: Iterator<Item = Rep<UD>> {
+    reps: I,
+    chunk: vec::IntoIter<LookupResult<UD>>,
+    next_index: u32,
+    done: bool,
+}
+
+impl<UD, I> ResultStream<UD, I> where UD: Debug, I: Iterator<Item = Rep<UD>> {
+    pub fn new(reps: I) -> ResultStream<UD, I> {
+        ResultStream {
+            reps: reps,
+            chunk: Vec::new().into_iter(),
+            next_index: 0,
+            done: false,
+        }
+    }
+
+    fn fail(&mut self, error: Error<UD>) -> Option<Result<(u32, LookupResult<UD>), Error<UD>>> {
+        self.done = true;
+        Some(Err(error))
+    }
+}
+
+impl<UD, I> Iterator for ResultStream<UD, I> where UD: Debug, I: Iterator<Item = Rep<UD>> {
+    type Item = Result<(u32, LookupResult<UD>), Error<UD>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(result) = self.chunk.next() {
+                let index = self.next_index;
+                self.next_index += 1;
+                return Some(Ok((index, result)));
+            }
+            if self.done {
+                return None;
+            }
+            let mut rep = match self.reps.next() {
+                Some(rep) => rep,
+                None => {
+                    let received = self.next_index;
+                    return self.fail(Error::Truncated { received: received, });
+                },
+            };
+            while let Rep::Tagged(_, inner) = rep {
+                rep = *inner;
+            }
+            match rep {
+                Rep::ResultChunk { start, results, } => {
+                    if start != self.next_index {
+                        let expected = self.next_index;
+                        return self.fail(Error::OutOfOrder { expected: expected, start: start, });
+                    }
+                    self.chunk = results.into_iter();
+                },
+                Rep::EndOfStream(total) => {
+                    self.done = true;
+                    if total != self.next_index {
+                        let received = self.next_index;
+                        return self.fail(Error::CountMismatch { received: received, total: total, });
+                    }
+                },
+                other =>
+                    return self.fail(Error::Unexpected(other)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ResultStream, Error};
+    use super::super::{Rep, LookupResult, BusyInfo};
+
+    fn chunk(start: u32, len: usize) -> Rep<()> {
+        Rep::ResultChunk { start: start, results: (0 .. len).map(|_| LookupResult::EmptySet).collect(), }
+    }
+
+    #[test]
+    fn complete() {
+        let reps = vec![chunk(0, 2), Rep::Tagged(1, Box::new(chunk(2, 1))), chunk(3, 0), Rep::EndOfStream(3)];
+        let indices: Vec<_> = ResultStream::new(reps.into_iter()).map(|r| r.unwrap().0).collect();
+        assert_eq!(indices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn broken() {
+        let mut stream = ResultStream::new(vec![chunk(0, 1), chunk(2, 1)].into_iter());
+        assert!(stream.next().unwrap().is_ok());
+        match stream.next() {
+            Some(Err(Error::OutOfOrder { expected: 1, start: 2, })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        assert!(stream.next().is_none());
+
+        match ResultStream::new(vec![chunk(0, 1), Rep::EndOfStream(2)].into_iter()).last() {
+            Some(Err(Error::CountMismatch { received: 1, total: 2, })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match ResultStream::new(vec![chunk(0, 1)].into_iter()).last() {
+            Some(Err(Error::Truncated { received: 1, })) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+        match ResultStream::new(vec![Rep::TooBusy::<()>(BusyInfo::default())].into_iter()).next() {
+            Some(Err(Error::Unexpected(Rep::TooBusy(..)))) => (),
+            other => panic!("bad result: {:?}", other),
+        }
+    }
+}